@@ -0,0 +1,636 @@
+/*
+Job artifacts: listing, downloading and opening files of the archive.
+
+GitLab only offers the artifacts metadata to runners, so entries are
+listed from the central directory of the zip archive itself. Only the
+end of the archive holding that directory is fetched, with HTTP range
+requests. Servers ignoring ranges get the whole archive downloaded.
+
+The archive of a finished job never changes, so a downloaded one is
+kept in the cache directory for ARCHIVE_MAX_AGE.
+Single files are extracted by GitLab.
+
+GitLab redirects downloads to object storage, which must not see the
+token, so redirects are followed here and the token stays with GitLab.
+*/
+use crate::cache::cache_dir;
+use crate::config::Configuration;
+use crate::tokens::token_for;
+use clap::Subcommand;
+use reqwest::blocking::{Client, Response};
+use reqwest::header::LOCATION;
+use reqwest::{redirect, StatusCode, Url};
+use std::fs::{self, File};
+use std::io::{self, stderr, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+const ARCHIVE_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 3600);
+// End of central directory record and the largest possible comment
+const ZIP_TAIL: u64 = 22 + 65535;
+const MAX_REDIRECTS: usize = 10;
+
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+pub enum ArtifactAction {
+    /// List the files of the archive
+    List,
+    /// Download a single file of the archive
+    Get {
+        path: String,
+        #[arg(long, short, default_value = ".")]
+        dir: PathBuf,
+    },
+    /// Download the whole archive
+    Download {
+        #[arg(long, short, default_value = ".")]
+        dir: PathBuf,
+    },
+    /// Open a file, by default the coverage or JUnit report
+    Open { path: Option<String> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArtifactEntry {
+    pub path: String,
+    pub size: u64,
+}
+
+/// Where a job's artifacts live on GitLab
+pub struct JobArtifacts {
+    pub host: String,
+    pub project_id: u64,
+    pub job_id: u64,
+    pub job_url: String,
+}
+
+fn read_u16(data: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([data[at], data[at + 1]])
+}
+
+fn read_u32(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+}
+
+/// Reads the central directory at the end of a zip archive.
+/// Directories are left out.
+pub fn zip_entries<R: Read + Seek>(archive: &mut R) -> Result<Vec<ArtifactEntry>, String> {
+    let invalid = |e: std::io::Error| format!("Could not read artifacts archive: {}", e);
+    let length = archive.seek(SeekFrom::End(0)).map_err(invalid)?;
+    let tail_length = length.min(ZIP_TAIL);
+    archive
+        .seek(SeekFrom::Start(length - tail_length))
+        .map_err(invalid)?;
+    let mut tail = vec![0; tail_length as usize];
+    archive.read_exact(&mut tail).map_err(invalid)?;
+    let end = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&i| read_u32(&tail, i) == 0x06054b50)
+        .ok_or("Artifacts archive is not a zip file")?;
+    let count = read_u16(&tail, end + 10);
+    let directory_size = read_u32(&tail, end + 12);
+    let directory_offset = read_u32(&tail, end + 16);
+    if count == 0xffff || directory_offset == 0xffffffff {
+        return Err("Zip64 artifact archives are not supported".to_string());
+    }
+
+    archive
+        .seek(SeekFrom::Start(directory_offset as u64))
+        .map_err(invalid)?;
+    let mut directory = vec![0; directory_size as usize];
+    archive.read_exact(&mut directory).map_err(invalid)?;
+
+    let mut entries = Vec::new();
+    let mut at = 0;
+    for _ in 0..count {
+        if at + 46 > directory.len() || read_u32(&directory, at) != 0x02014b50 {
+            return Err("Artifacts archive has a broken central directory".to_string());
+        }
+        let size = read_u32(&directory, at + 24) as u64;
+        let name_length = read_u16(&directory, at + 28) as usize;
+        let extra_length = read_u16(&directory, at + 30) as usize;
+        let comment_length = read_u16(&directory, at + 32) as usize;
+        let name = directory
+            .get(at + 46..at + 46 + name_length)
+            .ok_or("Artifacts archive has a broken central directory")?;
+        let path = String::from_utf8_lossy(name).to_string();
+        if !path.ends_with('/') {
+            entries.push(ArtifactEntry { path, size });
+        }
+        at += 46 + name_length + extra_length + comment_length;
+    }
+    Ok(entries)
+}
+
+/// `path` of an archive entry relative to where it is saved.
+/// Entries must not climb out of that directory.
+pub fn entry_path(path: &str) -> Result<PathBuf, String> {
+    let relative = Path::new(path);
+    let mut parts = relative.components().peekable();
+    if parts.peek().is_none() {
+        return Err("Artifact path is empty".to_string());
+    }
+    if parts.all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) {
+        Ok(relative.to_path_buf())
+    } else {
+        Err(format!(
+            "Refusing artifact path {} outside the directory",
+            path
+        ))
+    }
+}
+
+/// Reads a file over HTTP with range requests, for zip_entries
+pub struct RangeReader<'a> {
+    client: &'a Client,
+    url: String,
+    header: (&'static str, String),
+    length: u64,
+    position: u64,
+    // The last bytes fetched and where they start
+    buffer: (u64, Vec<u8>),
+}
+
+/// GETs `url`, following redirects. The token is only sent
+/// until a redirect leaves the host of `url`.
+fn get(
+    client: &Client,
+    url: &str,
+    header: (&str, &str),
+    range: Option<String>,
+) -> Result<Response, String> {
+    let failed = |e: &dyn std::fmt::Display| format!("Could not download {}: {}", url, e);
+    let mut next = Url::parse(url).map_err(|e| failed(&e))?;
+    let origin = next.origin();
+    let mut trusted = true;
+    for _ in 0..=MAX_REDIRECTS {
+        trusted &= next.origin() == origin;
+        let mut request = client.get(next.clone());
+        if trusted {
+            request = request.header(header.0, header.1);
+        }
+        if let Some(range) = &range {
+            request = request.header("Range", range);
+        }
+        let response = request.send().map_err(|e| failed(&e))?;
+        if !response.status().is_redirection() {
+            return Ok(response);
+        }
+        let location = response
+            .headers()
+            .get(LOCATION)
+            .and_then(|l| l.to_str().ok())
+            .ok_or_else(|| failed(&"redirect without a location"))?;
+        next = next.join(location).map_err(|e| failed(&e))?;
+    }
+    Err(failed(&"too many redirects"))
+}
+
+/// Total length of "bytes 100-199/12345"
+fn content_range_length(response: &Response) -> Option<u64> {
+    let range = response.headers().get("content-range")?.to_str().ok()?;
+    range.rsplit_once('/')?.1.parse().ok()
+}
+
+impl<'a> RangeReader<'a> {
+    /// Fetches the last `tail` bytes, None if the server ignores ranges
+    fn open(
+        client: &'a Client,
+        url: &str,
+        header: (&'static str, String),
+        tail: u64,
+    ) -> Result<Option<RangeReader<'a>>, String> {
+        let range = format!("bytes=-{}", tail);
+        let response = get(client, url, (header.0, &header.1), Some(range))?;
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            if !response.status().is_success() {
+                return Err(format!("Could not download {}: {}", url, response.status()));
+            }
+            return Ok(None);
+        }
+        let length = match content_range_length(&response) {
+            Some(length) => length,
+            None => return Ok(None),
+        };
+        let data = response
+            .bytes()
+            .map_err(|e| format!("Could not download {}: {}", url, e))?;
+        Ok(Some(RangeReader {
+            client,
+            url: url.to_string(),
+            header,
+            length,
+            position: 0,
+            buffer: (length - data.len() as u64, data.to_vec()),
+        }))
+    }
+}
+
+impl Read for RangeReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.length || buf.is_empty() {
+            return Ok(0);
+        }
+        let (start, data) = &self.buffer;
+        let end = start + data.len() as u64;
+        if self.position < *start || self.position >= end {
+            let last = (self.position + buf.len() as u64).min(self.length) - 1;
+            let range = format!("bytes={}-{}", self.position, last);
+            let header = (self.header.0, self.header.1.as_str());
+            let response = get(self.client, &self.url, header, Some(range))
+                .and_then(|r| r.error_for_status().map_err(|e| e.to_string()))
+                .map_err(io::Error::other)?;
+            if response.status() != StatusCode::PARTIAL_CONTENT {
+                return Err(io::Error::other("Server stopped answering range requests"));
+            }
+            let data = response.bytes().map_err(io::Error::other)?;
+            self.buffer = (self.position, data.to_vec());
+        }
+        let (start, data) = &self.buffer;
+        let available = &data[(self.position - start) as usize..];
+        let read = available.len().min(buf.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for RangeReader<'_> {
+    fn seek(&mut self, to: SeekFrom) -> io::Result<u64> {
+        self.position = match to {
+            SeekFrom::Start(at) => at,
+            SeekFrom::End(offset) => self.length.saturating_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.saturating_add_signed(offset),
+        };
+        Ok(self.position)
+    }
+}
+
+/// Removes archives not used for ARCHIVE_MAX_AGE and aborted downloads
+fn prune_archives(dir: &Path, now: SystemTime) {
+    for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        let modified = entry.metadata().and_then(|m| m.modified());
+        let age = modified.map(|m| now.duration_since(m).unwrap_or_default());
+        if age.is_ok_and(|age| age > ARCHIVE_MAX_AGE) {
+            fs::remove_file(entry.path()).ok();
+        }
+    }
+}
+
+/// JUnit and coverage reports, best first
+pub fn find_report(entries: &[ArtifactEntry]) -> Option<&ArtifactEntry> {
+    let kinds: [fn(&str) -> bool; 4] = [
+        |p| p.contains("coverage") && p.ends_with("index.html"),
+        |p| p.contains("junit") && p.ends_with(".xml"),
+        |p| p.contains("cobertura") && p.ends_with(".xml"),
+        |p| p.ends_with("lcov.info"),
+    ];
+    kinds.iter().find_map(|kind| {
+        entries
+            .iter()
+            .find(|e| kind(e.path.to_lowercase().as_str()))
+    })
+}
+
+pub fn human_size(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        return format!("{} B", bytes);
+    }
+    format!("{:.1} {}", size, units[unit])
+}
+
+/// Percent encodes every path segment, keeping the slashes
+fn encode_path(path: &str) -> String {
+    path.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+impl JobArtifacts {
+    fn api_url(&self) -> String {
+        format!(
+            "https://{}/api/v4/projects/{}/jobs/{}/artifacts",
+            self.host, self.project_id, self.job_id
+        )
+    }
+
+    fn client(url: &str) -> Result<Client, String> {
+        // No overall timeout, large archives take a while
+        Client::builder()
+            .timeout(None)
+            .redirect(redirect::Policy::none())
+            .build()
+            .map_err(|e| format!("Could not download {}: {}", url, e))
+    }
+
+    fn download(&self, url: &str, destination: &Path, conf: &Configuration) -> Result<(), String> {
+        let token = token_for(&self.host, conf)?;
+        let (header, value) = token.header();
+        let client = JobArtifacts::client(url)?;
+        let mut response = get(&client, url, (header, value), None)?;
+        if !response.status().is_success() {
+            return Err(format!("Could not download {}: {}", url, response.status()));
+        }
+        if let Some(dir) = destination.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+        }
+        // Download next to the destination and rename,
+        // so an aborted download never looks complete
+        let name = destination
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let tmp = destination.with_file_name(format!("{}.part", name));
+        let mut file =
+            File::create(&tmp).map_err(|e| format!("Could not write {}: {}", tmp.display(), e))?;
+        let total = response.content_length();
+        let show_progress = stderr().is_terminal();
+        let mut done = 0;
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let read = response
+                .read(&mut buffer)
+                .map_err(|e| format!("Could not download {}: {}", url, e))?;
+            if read == 0 {
+                break;
+            }
+            file.write_all(&buffer[..read])
+                .map_err(|e| format!("Could not write {}: {}", tmp.display(), e))?;
+            done += read as u64;
+            if show_progress {
+                eprint!("\r{}", progress_line(&name, done, total));
+            }
+        }
+        if show_progress {
+            eprintln!();
+        }
+        fs::rename(&tmp, destination)
+            .map_err(|e| format!("Could not write {}: {}", destination.display(), e))
+    }
+
+    fn cached_archive(&self) -> Result<PathBuf, String> {
+        Ok(cache_dir()
+            .ok_or("Could not determine cache directory")?
+            .join("artifacts")
+            .join(format!("{}%{}.zip", self.host, self.job_id)))
+    }
+
+    /// The whole archive, from the cache if downloaded before
+    pub fn archive(&self, conf: &Configuration) -> Result<PathBuf, String> {
+        let file = self.cached_archive()?;
+        if let Some(dir) = file.parent() {
+            prune_archives(dir, SystemTime::now());
+        }
+        if !file.exists() {
+            self.download(&self.api_url(), &file, conf)?;
+        }
+        Ok(file)
+    }
+
+    /// The files of the archive, read from its end only
+    pub fn entries(&self, conf: &Configuration) -> Result<Vec<ArtifactEntry>, String> {
+        let cached = self.cached_archive()?;
+        if !cached.exists() {
+            let url = self.api_url();
+            let token = token_for(&self.host, conf)?;
+            let (header, value) = token.header();
+            let client = JobArtifacts::client(&url)?;
+            let reader = RangeReader::open(&client, &url, (header, value.to_string()), ZIP_TAIL)?;
+            if let Some(mut reader) = reader {
+                return zip_entries(&mut reader);
+            }
+        }
+        let archive = self.archive(conf)?;
+        let mut file = File::open(&archive)
+            .map_err(|e| format!("Could not read {}: {}", archive.display(), e))?;
+        zip_entries(&mut file)
+    }
+
+    /// Saves the whole archive as artifacts-<job id>.zip in `dir`
+    pub fn download_archive(&self, dir: &Path, conf: &Configuration) -> Result<PathBuf, String> {
+        let destination = dir.join(format!("artifacts-{}.zip", self.job_id));
+        fs::copy(self.archive(conf)?, &destination)
+            .map_err(|e| format!("Could not write {}: {}", destination.display(), e))?;
+        Ok(destination)
+    }
+
+    /// Saves a single file of the archive in `dir`, keeping its path
+    pub fn download_file(
+        &self,
+        path: &str,
+        dir: &Path,
+        conf: &Configuration,
+    ) -> Result<PathBuf, String> {
+        let destination = dir.join(entry_path(path)?);
+        let url = format!("{}/{}", self.api_url(), encode_path(path));
+        self.download(&url, &destination, conf)?;
+        Ok(destination)
+    }
+
+    /// HTML reports are shown by GitLab, so that their stylesheets and
+    /// scripts work. Everything else is downloaded to the cache directory.
+    pub fn open_url(&self, path: &str, conf: &Configuration) -> Result<String, String> {
+        if path.ends_with(".html") {
+            return Ok(format!(
+                "{}/artifacts/file/{}",
+                self.job_url,
+                encode_path(path)
+            ));
+        }
+        let dir = cache_dir()
+            .ok_or("Could not determine cache directory")?
+            .join("artifacts")
+            .join(format!("{}%{}", self.host, self.job_id));
+        let file = self.download_file(path, &dir, conf)?;
+        Ok(file.display().to_string())
+    }
+}
+
+fn progress_line(name: &str, done: u64, total: Option<u64>) -> String {
+    match total {
+        Some(total) if total > 0 => format!(
+            "{} {} / {} ({}%)",
+            name,
+            human_size(done),
+            human_size(total),
+            done * 100 / total
+        ),
+        _ => format!("{} {}", name, human_size(done)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_zip_entries() {
+        let mut archive = Cursor::new(include_bytes!("../tests/fixtures/artifacts.zip").to_vec());
+        let entries = zip_entries(&mut archive).unwrap();
+        let paths: Vec<&str> = entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "target/release/gitlab-pipeline-viewer",
+                "reports/junit.xml",
+                "coverage/index.html"
+            ]
+        );
+        assert_eq!(entries[0].size, 2004);
+        assert_eq!(find_report(&entries).unwrap().path, "coverage/index.html");
+        assert_eq!(
+            find_report(&entries[..2]).unwrap().path,
+            "reports/junit.xml"
+        );
+
+        assert!(zip_entries(&mut Cursor::new(b"not a zip".to_vec())).is_err());
+    }
+
+    /// Serves `data` on localhost, honouring single ranges like GitLab,
+    /// and counts the requests
+    fn serve_ranges(data: &'static [u8], requests: usize) -> String {
+        use std::io::{BufRead, BufReader};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/artifacts", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming().take(requests) {
+                let mut stream = stream.unwrap();
+                let mut range = None;
+                for line in BufReader::new(&stream).lines() {
+                    let line = line.unwrap();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(r) = line.to_lowercase().strip_prefix("range: bytes=") {
+                        range = Some(r.to_string());
+                    }
+                }
+                let length = data.len();
+                let (start, end) = match range.as_deref().unwrap().split_once('-').unwrap() {
+                    ("", suffix) => (length.saturating_sub(suffix.parse().unwrap()), length - 1),
+                    (start, end) => (start.parse().unwrap(), end.parse().unwrap()),
+                };
+                let header = format!(
+                    "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    start, end, length, end - start + 1
+                );
+                stream.write_all(header.as_bytes()).unwrap();
+                stream.write_all(&data[start..=end]).unwrap();
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn test_range_reader() {
+        let data = include_bytes!("../tests/fixtures/artifacts.zip");
+        let url = serve_ranges(data, 2);
+        let client = Client::new();
+        let header = ("PRIVATE-TOKEN", "glpat-test".to_string());
+        // Small tail, so the central directory needs a second request
+        let mut reader = RangeReader::open(&client, &url, header, 30)
+            .unwrap()
+            .unwrap();
+        let entries = zip_entries(&mut reader).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1].path, "reports/junit.xml");
+    }
+
+    /// Answers one request with `response` and sends its header lines back
+    fn serve_once(response: String) -> (String, std::sync::mpsc::Receiver<Vec<String>>) {
+        use std::io::{BufRead, BufReader};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/artifacts", listener.local_addr().unwrap());
+        let (sender, receiver) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let lines: Vec<String> = BufReader::new(&stream)
+                .lines()
+                .map(|l| l.unwrap().to_lowercase())
+                .take_while(|l| !l.is_empty())
+                .collect();
+            stream.write_all(response.as_bytes()).unwrap();
+            sender.send(lines).unwrap();
+        });
+        (url, receiver)
+    }
+
+    #[test]
+    fn test_redirect_drops_token() {
+        let (storage, storage_headers) = serve_once(
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok".to_string(),
+        );
+        // Another port is another origin
+        let redirect = format!(
+            "HTTP/1.1 302 Found\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            storage
+        );
+        let (gitlab, gitlab_headers) = serve_once(redirect);
+        let client = JobArtifacts::client(&gitlab).unwrap();
+        let range = Some("bytes=-30".to_string());
+        let response = get(&client, &gitlab, ("PRIVATE-TOKEN", "glpat-test"), range).unwrap();
+        assert_eq!(response.text().unwrap(), "ok");
+        let gitlab_headers = gitlab_headers.recv().unwrap();
+        assert!(gitlab_headers.contains(&"private-token: glpat-test".to_string()));
+        let storage_headers = storage_headers.recv().unwrap();
+        assert!(storage_headers.contains(&"range: bytes=-30".to_string()));
+        assert!(!storage_headers.iter().any(|l| l.contains("glpat-test")));
+    }
+
+    #[test]
+    fn test_entry_path() {
+        assert_eq!(
+            entry_path("reports/junit.xml"),
+            Ok(PathBuf::from("reports/junit.xml"))
+        );
+        assert!(entry_path("../../.bashrc").is_err());
+        assert!(entry_path("reports/../../.bashrc").is_err());
+        assert!(entry_path("/etc/passwd").is_err());
+        assert!(entry_path("").is_err());
+    }
+
+    #[test]
+    fn test_prune_archives() {
+        let dir = std::env::temp_dir().join(format!("gpv-artifacts-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("gitlab.com%1.zip"), b"zip").unwrap();
+        fs::write(dir.join("gitlab.com%2.zip.part"), b"zi").unwrap();
+        prune_archives(&dir, SystemTime::now());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        prune_archives(&dir, SystemTime::now() + ARCHIVE_MAX_AGE * 2);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_human_size() {
+        assert_eq!(human_size(512), "512 B");
+        assert_eq!(human_size(2004), "2.0 KB");
+        assert_eq!(human_size(40 * 1024 * 1024 + 100), "40.0 MB");
+        assert_eq!(
+            progress_line("a.zip", 512, Some(1024)),
+            "a.zip 512 B / 1.0 KB (50%)"
+        );
+    }
+
+    #[test]
+    fn test_encode_path() {
+        assert_eq!(
+            encode_path("coverage/my report.html"),
+            "coverage/my%20report.html"
+        );
+    }
+}
@@ -0,0 +1,118 @@
+/*
+Opening GitLab pages in the browser,
+for `gpv open` and the key bindings of the terminal view.
+*/
+use crate::config::Configuration;
+use crate::gitlabbing::{GitlabProjectPipelines, PipelineJob};
+use clap::Subcommand;
+use gitlab::{Pipeline, StatusState};
+use std::env;
+use std::process::{Command, Stdio};
+use std::thread;
+
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+pub enum OpenTarget {
+    /// The latest pipeline
+    Pipeline,
+    /// A job of the latest pipeline
+    Job { name: String },
+    /// The first failed job of the latest pipeline
+    Failed,
+    /// The artifacts of a job of the latest pipeline
+    Artifacts { job: String },
+    /// The open merge request of the branch
+    Mr,
+    /// The project page
+    Project,
+}
+
+fn latest_pipeline(
+    project: &GitlabProjectPipelines,
+) -> Result<&(Pipeline, Vec<PipelineJob>), String> {
+    project
+        .pipelines
+        .first()
+        .ok_or_else(|| "There is no pipeline".to_string())
+}
+
+/// Everything but merge requests, which need another API call.
+pub fn url_for(what: &OpenTarget, project: &GitlabProjectPipelines) -> Result<String, String> {
+    match what {
+        OpenTarget::Pipeline => Ok(latest_pipeline(project)?.0.web_url.clone()),
+        OpenTarget::Job { name } => Ok(project.find_job(name)?.job.web_url.clone()),
+        OpenTarget::Failed => latest_pipeline(project)?
+            .1
+            .iter()
+            .rev()
+            .find(|j| j.job.status == StatusState::Failed && !j.job.allow_failure)
+            .map(|j| j.job.web_url.clone())
+            .ok_or_else(|| "No failed job in the latest pipeline".to_string()),
+        OpenTarget::Artifacts { job } => Ok(format!(
+            "{}/artifacts/browse",
+            project.find_job(job)?.job.web_url
+        )),
+        OpenTarget::Mr => Err("Merge requests are looked up on GitLab".to_string()),
+        OpenTarget::Project => Ok(project.web_url.clone()),
+    }
+}
+
+/// `browser` of the config, the first entry of $BROWSER, or the system opener
+fn browser_command(conf: &Configuration) -> String {
+    if let Some(browser) = &conf.browser {
+        return browser.clone();
+    }
+    match env::var("BROWSER") {
+        Ok(b) if !b.is_empty() => b.split(':').next().unwrap().to_string(),
+        _ if cfg!(target_os = "macos") => "open".to_string(),
+        _ => "xdg-open".to_string(),
+    }
+}
+
+pub fn open_url(url: &str, conf: &Configuration) -> Result<(), String> {
+    let browser = browser_command(conf);
+    let mut child = Command::new(&browser)
+        .arg(url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("Could not run {}: {}", browser, e))?;
+    // Reap the browser, without waiting for it
+    thread::spawn(move || child.wait());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::*;
+
+    #[test]
+    fn test_url_for() {
+        let mut jobs = jobs();
+        jobs[2].job.status = StatusState::Failed;
+        let project = project_pipelines(vec![(pipeline(), jobs.clone())]);
+
+        assert_eq!(
+            url_for(&OpenTarget::Pipeline, &project),
+            Ok(project.pipelines[0].0.web_url.clone())
+        );
+        assert_eq!(
+            url_for(&OpenTarget::Failed, &project),
+            Ok(jobs[2].job.web_url.clone())
+        );
+        let name = jobs[0].job.name.clone();
+        assert_eq!(
+            url_for(&OpenTarget::Artifacts { job: name.clone() }, &project),
+            Ok(format!("{}/artifacts/browse", jobs[0].job.web_url))
+        );
+        assert!(url_for(
+            &OpenTarget::Job {
+                name: "nope".to_string()
+            },
+            &project
+        )
+        .is_err());
+        assert!(url_for(&OpenTarget::Pipeline, &project_pipelines(vec![])).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+/*
+On-disk cache of the latest pipeline per repository.
+
+Every gpv process that fetches pipelines writes it, so `gpv prompt`
+can print a status segment without talking to GitLab at all.
+One small JSON file per repository lives in
+$XDG_CACHE_HOME/gitlab-pipeline-viewer (or ~/.cache/gitlab-pipeline-viewer).
+*/
+use crate::git::RepositoryDetails;
+use crate::gitlabbing::GitlabProjectPipelines;
+use chrono::{DateTime, Utc};
+use gitlab::StatusState;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CachedPipeline {
+    pub id: u64,
+    pub status: StatusState,
+    pub web_url: String,
+    pub created_at: Option<DateTime<Utc>>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub duration: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PromptCache {
+    pub written_at: DateTime<Utc>,
+    pub project: String,
+    #[serde(rename = "ref")]
+    pub ref_: String,
+    pub pipeline: Option<CachedPipeline>,
+}
+
+pub fn cache_dir() -> Option<PathBuf> {
+    match env::var("XDG_CACHE_HOME") {
+        Ok(v) if !v.is_empty() => Some(PathBuf::from(v)),
+        _ => env::var("HOME").ok().map(|h| Path::new(&h).join(".cache")),
+    }
+    .map(|p| p.join("gitlab-pipeline-viewer"))
+}
+
+/// "/home/me/src/gpv" becomes "%home%me%src%gpv.json"
+fn cache_file_name(repo_path: &Path) -> String {
+    let path = repo_path.to_string_lossy();
+    format!("{}.json", path.trim_end_matches('/').replace('/', "%"))
+}
+
+pub fn cache_file(repo_path: &Path) -> Option<PathBuf> {
+    cache_dir().map(|d| d.join(cache_file_name(repo_path)))
+}
+
+pub fn prompt_cache(repo: &RepositoryDetails, project: &GitlabProjectPipelines) -> PromptCache {
+    PromptCache {
+        written_at: Utc::now(),
+        project: project.name.clone(),
+        ref_: repo.branch_or_ref.clone(),
+        pipeline: project.pipelines.first().map(|(p, _)| CachedPipeline {
+            id: p.id.value(),
+            status: p.status,
+            web_url: p.web_url.clone(),
+            created_at: p.created_at,
+            started_at: p.started_at,
+            duration: p.duration,
+        }),
+    }
+}
+
+pub fn write_prompt_cache(
+    repo: &RepositoryDetails,
+    project: &GitlabProjectPipelines,
+) -> Result<(), String> {
+    let file = cache_file(&repo.path).ok_or("Could not determine cache directory")?;
+    let dir = file.parent().unwrap();
+    fs::create_dir_all(dir).map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+    let content = serde_json::to_string(&prompt_cache(repo, project)).unwrap();
+    // Write and rename, so a prompt never reads a half written file
+    let tmp = file.with_extension("json.tmp");
+    fs::write(&tmp, content).map_err(|e| format!("Could not write {}: {}", tmp.display(), e))?;
+    fs::rename(&tmp, &file).map_err(|e| format!("Could not write {}: {}", file.display(), e))
+}
+
+pub fn read_prompt_cache(repo_path: &Path) -> Option<PromptCache> {
+    let content = fs::read_to_string(cache_file(repo_path)?).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cache_file_name() {
+        assert_eq!(
+            cache_file_name(Path::new("/home/me/src/gpv/")),
+            "%home%me%src%gpv.json"
+        );
+    }
+}
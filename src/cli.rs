@@ -0,0 +1,292 @@
+use crate::artifacts::ArtifactAction;
+use crate::browser::OpenTarget;
+use crate::config_command::ConfigAction;
+use crate::deployments::EnvironmentAction;
+use crate::output::OutputFormat;
+use crate::prompt;
+use crate::run;
+use clap::{Args, Parser, Subcommand};
+use gitlab::StatusState;
+use std::path::PathBuf;
+
+/// Monitor your GitLab pipelines from your terminal.
+#[derive(Parser, Debug)]
+#[command(name = "gpv", version, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    /// Print machine readable output instead of the terminal view.
+    /// json and yaml print a single snapshot, ndjson keeps watching
+    /// and prints one snapshot or diff event per refresh.
+    #[arg(long, short, value_enum)]
+    pub output: Option<OutputFormat>,
+
+    /// Project or pipeline URL copied from the browser.
+    /// Watches it without needing a local checkout.
+    #[arg(value_parser = parse_url)]
+    pub url: Option<String>,
+
+    #[command(flatten)]
+    pub target: TargetArgs,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// What to watch or act on instead of the local checkout
+#[derive(Args, Debug, Clone, Default)]
+pub struct TargetArgs {
+    /// Watch this project (e.g. group/sub/project) instead of
+    /// the one of the local checkout.
+    #[arg(long)]
+    pub project: Option<String>,
+
+    /// Ref to watch, defaults to the checked out branch,
+    /// or the default branch for --project.
+    #[arg(long = "ref")]
+    pub ref_: Option<String>,
+
+    /// Watch a single pipeline by id.
+    #[arg(long)]
+    pub pipeline: Option<u64>,
+
+    /// GitLab host of --project, defaults to the only host in `gitlab-tokens`.
+    #[arg(long)]
+    pub host: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Print a one line status of the current branch for shell prompts
+    /// and status bars. Reads the cache kept fresh by a running gpv,
+    /// never talks to GitLab.
+    Prompt {
+        /// Placeholders: {icon} {id} {status} {duration} {ref}
+        /// {project} {url} {stale}
+        #[arg(long, default_value = prompt::DEFAULT_FORMAT)]
+        format: String,
+        /// Seconds after which the cached status is marked as stale.
+        #[arg(long, default_value_t = 60.0)]
+        max_age: f64,
+    },
+    /// Keep polling GitLab in the background and serve the results
+    /// to every gpv on this machine over a Unix socket.
+    Daemon,
+    /// Show the latest pipeline of several repositories at once.
+    /// Enter expands the selected repository into the detailed view.
+    Dashboard {
+        /// Repository paths, defaults to `projects` of the config file.
+        repos: Vec<PathBuf>,
+    },
+    /// Open the pipeline, a job, the merge request or the project
+    /// in the browser.
+    Open {
+        #[command(subcommand)]
+        what: Option<OpenTarget>,
+        #[command(flatten)]
+        target: TargetArgs,
+    },
+    /// List, download or open the artifacts of a job
+    /// of the latest pipeline. Lists them by default.
+    Artifacts {
+        job: String,
+        #[command(subcommand)]
+        action: Option<ArtifactAction>,
+        #[command(flatten)]
+        target: TargetArgs,
+    },
+    /// List the failed tests of the latest pipeline,
+    /// with their messages and stack traces.
+    Tests {
+        /// Only the tests of this job
+        #[arg(long)]
+        job: Option<String>,
+        #[command(flatten)]
+        target: TargetArgs,
+    },
+    /// List the last pipelines of the ref with source, author and commit.
+    /// In the viewer, l opens the same list to pick a pipeline.
+    History {
+        #[command(flatten)]
+        target: TargetArgs,
+    },
+    /// Start a pipeline for the ref, then watch it. Asks for the
+    /// variables described in the CI config when run in a terminal.
+    Run {
+        /// Variables as KEY=VALUE
+        #[arg(value_parser = run::parse_variable)]
+        variables: Vec<(String, String)>,
+        /// Do not ask, keep the defaults of the CI config
+        #[arg(long, short)]
+        yes: bool,
+        #[command(flatten)]
+        target: TargetArgs,
+    },
+    /// Stop an environment or roll it back to the previous deployment
+    Environment {
+        #[command(subcommand)]
+        action: EnvironmentAction,
+        /// Do not ask for confirmation
+        #[arg(long, short)]
+        yes: bool,
+        #[command(flatten)]
+        target: TargetArgs,
+    },
+    /// Check the configuration and tokens, write a first config
+    /// or show the merged one
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Show pipelines across a GitLab group or of a user,
+    /// no local checkout needed.
+    Overview {
+        /// GitLab host, defaults to the only host in `gitlab-tokens`.
+        #[arg(long)]
+        host: Option<String>,
+        /// Group path, subgroups are included.
+        #[arg(long)]
+        group: Option<String>,
+        /// Only pipelines triggered by this user, "me" for yourself.
+        #[arg(long)]
+        user: Option<String>,
+        /// Only pipelines with this status, e.g. running or failed.
+        #[arg(long, value_parser = parse_status)]
+        status: Option<StatusState>,
+        /// Only pipelines updated within this time, e.g. 30m, 1h or 2d.
+        #[arg(long, value_parser = parse_age)]
+        since: Option<chrono::Duration>,
+    },
+}
+
+impl Command {
+    /// The target arguments of subcommands working on a project
+    pub fn target(&self) -> Option<&TargetArgs> {
+        match self {
+            Command::Open { target, .. }
+            | Command::Artifacts { target, .. }
+            | Command::Tests { target, .. }
+            | Command::History { target }
+            | Command::Run { target, .. }
+            | Command::Environment { target, .. } => Some(target),
+            _ => None,
+        }
+    }
+}
+
+/// Also catches a subcommand after top-level target arguments,
+/// which clap takes for the URL as both cannot be combined
+fn parse_url(url: &str) -> Result<String, String> {
+    if url.starts_with("https://") || url.starts_with("http://") {
+        return Ok(url.to_string());
+    }
+    Err(format!(
+        "{} is not a URL. Pass --project, --ref, --pipeline and --host after a subcommand",
+        url
+    ))
+}
+
+fn parse_status(status: &str) -> Result<StatusState, String> {
+    serde_yaml::from_str(status).map_err(|_| format!("Unknown status {}", status))
+}
+
+/// "45s", "30m", "1h" or "2d"
+fn parse_age(age: &str) -> Result<chrono::Duration, String> {
+    let error = || format!("Invalid age {}, use e.g. 30m, 1h or 2d", age);
+    if age.len() < 2 {
+        return Err(error());
+    }
+    let (number, unit) = age.split_at(age.len() - 1);
+    let number: i64 = number.parse().map_err(|_| error())?;
+    if number <= 0 {
+        return Err(error());
+    }
+    let unit: i64 = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return Err(error()),
+    };
+    // chrono::Duration panics beyond its range
+    match number.checked_mul(unit) {
+        Some(seconds) if seconds <= chrono::Duration::max_value().num_seconds() => {
+            Ok(chrono::Duration::seconds(seconds))
+        }
+        _ => Err(format!("Age {} is too large", age)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_url_and_subcommands() {
+        let cli =
+            Cli::try_parse_from(["gpv", "https://gitlab.com/group/project/-/pipelines/1"]).unwrap();
+        assert!(cli.url.is_some() && cli.command.is_none());
+        let cli = Cli::try_parse_from(["gpv", "daemon"]).unwrap();
+        assert!(cli.url.is_none() && matches!(cli.command, Some(Command::Daemon)));
+        let cli =
+            Cli::try_parse_from(["gpv", "--project", "group/project", "--ref", "main"]).unwrap();
+        assert_eq!(cli.target.ref_.as_deref(), Some("main"));
+        let cli =
+            Cli::try_parse_from(["gpv", "open", "--project", "group/project", "job", "build"])
+                .unwrap();
+        let command = cli.command.unwrap();
+        assert!(command.target().unwrap().project.is_some());
+        assert!(matches!(
+            command,
+            Command::Open {
+                what: Some(OpenTarget::Job { .. }),
+                ..
+            }
+        ));
+        let cli =
+            Cli::try_parse_from(["gpv", "run", "--ref", "main", "DEPLOY_ENV=qa", "-y"]).unwrap();
+        match cli.command {
+            Some(Command::Run {
+                variables,
+                yes,
+                target,
+            }) => {
+                assert_eq!(
+                    variables,
+                    vec![("DEPLOY_ENV".to_string(), "qa".to_string())]
+                );
+                assert!(yes);
+                assert_eq!(target.ref_.as_deref(), Some("main"));
+            }
+            other => panic!("Expected run, got {:?}", other),
+        }
+        // Top-level target arguments only apply to the viewer
+        assert!(Cli::try_parse_from(["gpv", "--project", "group/project", "open"]).is_err());
+        assert!(
+            Cli::try_parse_from(["gpv", "https://gitlab.com/group/project", "history"]).is_err()
+        );
+        assert!(Cli::try_parse_from(["gpv", "--ref", "main", "daemon"]).is_err());
+        assert!(Cli::try_parse_from(["gpv", "run", "DEPLOY_ENV"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_age() {
+        assert_eq!(parse_age("90s"), Ok(chrono::Duration::seconds(90)));
+        assert_eq!(parse_age("1h"), Ok(chrono::Duration::hours(1)));
+        assert_eq!(parse_age("2d"), Ok(chrono::Duration::days(2)));
+        assert!(parse_age("h").is_err());
+        assert!(parse_age("1w").is_err());
+        assert!(parse_age("0m").is_err());
+        assert!(parse_age("-2d").is_err());
+        assert!(parse_age("99999999999999d").is_err());
+        assert!(parse_age("9223372036854775807s").is_err());
+    }
+
+    #[test]
+    fn test_parse_status() {
+        assert_eq!(parse_status("running"), Ok(StatusState::Running));
+        assert_eq!(
+            parse_status("waiting_for_resource"),
+            Ok(StatusState::WaitingForResource)
+        );
+        assert!(parse_status("exploded").is_err());
+    }
+}
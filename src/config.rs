@@ -1,30 +1,499 @@
-use serde::Deserialize;
-use serde_yaml::from_str;
+use gitlab::StatusState;
+use serde::{Deserialize, Serialize};
+use serde_yaml::{from_str, Value};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 use std::{collections::HashMap, env, fs};
 
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct NotificationConfig {
+    #[serde(default)]
+    pub bell: bool,
+    pub osc: Option<u16>, // 9 or 777
+    #[serde(default)]
+    pub desktop: bool,
+    pub command: Option<String>,
+    pub statuses: Option<Vec<StatusState>>, // defaults to success, failed, canceled
+    #[serde(default)]
+    pub jobs: bool,
+    #[serde(flatten)]
+    pub unknown: HashMap<String, Value>, // reported by gpv config check
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum HookEvent {
+    PipelineStarted,
+    PipelineFinished,
+    PipelineFailed,
+    PipelineSucceeded,
+    Stage,
+    Job,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct HookConfig {
+    pub on: HookEvent,
+    pub stage: Option<String>,
+    pub job: Option<String>, // job name
+    pub status: Option<StatusState>,
+    pub command: String,
+    #[serde(flatten)]
+    pub unknown: HashMap<String, Value>, // reported by gpv config check
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct HistoryConfig {
+    pub pipelines: Option<usize>, // defaults to 10, 0 hides the history
+    #[serde(rename = "coverage-drop")]
+    pub coverage_drop: Option<f64>, // percentage points, defaults to 1.0
+    #[serde(rename = "duration-increase")]
+    pub duration_increase: Option<f64>, // percent, defaults to 20.0
+    #[serde(flatten)]
+    pub unknown: HashMap<String, Value>, // reported by gpv config check
+}
+
+/// Seconds between refreshes, depending on what is going on
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct PollingConfig {
+    pub active: Option<f32>,    // jobs running or pending, defaults to cooldown
+    pub finished: Option<f32>,  // all pipelines finished, defaults to 30
+    pub unfocused: Option<f32>, // terminal in the background, defaults to 60
+    pub idle: Option<f32>,      // nothing happened for idle-after, defaults to 120
+    #[serde(rename = "idle-after")]
+    pub idle_after: Option<f32>, // defaults to 600
+    pub overview: Option<f32>,  // gpv overview, defaults to 60
+    #[serde(flatten)]
+    pub unknown: HashMap<String, Value>, // reported by gpv config check
+}
+
+/// Where pipeline and job webhooks of GitLab are received
+#[derive(Deserialize, Debug, Clone)]
+pub struct WebhookConfig {
+    pub listen: String,         // e.g. 127.0.0.1:8642
+    pub secret: Option<String>, // expected as X-Gitlab-Token
+    #[serde(flatten)]
+    pub unknown: HashMap<String, Value>, // reported by gpv config check
+}
+
+/// Which pipelines besides the latest one get the detailed view
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum PipelineSelection {
+    #[default]
+    Running, // other running pipelines
+    Recent, // the previous pipelines, whatever their status
+    Latest, // none
+}
+
+/// How pipelines are fetched from GitLab
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    #[default]
+    Rest, // requests per pipeline for details, jobs and test counts
+    Graphql, // a list query, then one query per shown pipeline and page of its jobs
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct PipelinesConfig {
+    pub list: Option<usize>, // pipelines in the history list, defaults to 50
+    pub selection: Option<PipelineSelection>,
+    pub count: Option<usize>, // defaults to 5
+    #[serde(flatten)]
+    pub unknown: HashMap<String, Value>, // reported by gpv config check
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct Configuration {
     pub remote: Option<String>, // defaults to "origin"
-    #[serde(rename = "gitlab-tokens")]
+    #[serde(rename = "gitlab-tokens", default)]
     pub gitlab_token_mapping: HashMap<String, String>,
+    #[serde(rename = "token-command")]
+    pub token_command: Option<HashMap<String, String>>, // host to command printing the token
+    pub keyring: Option<bool>, // defaults to true
     pub cooldown: Option<f32>, // defaults to 5.0
+    pub polling: Option<PollingConfig>,
+    pub webhook: Option<WebhookConfig>,
+    #[serde(rename = "queue-warning")]
+    pub queue_warning: Option<f32>, // defaults to 300.0
+    pub notifications: Option<NotificationConfig>,
+    pub hooks: Option<Vec<HookConfig>>,
+    #[serde(rename = "daemon-socket")]
+    pub daemon_socket: Option<String>, // defaults to $XDG_RUNTIME_DIR/gitlab-pipeline-viewer.sock
+    #[serde(rename = "use-daemon")]
+    pub use_daemon: Option<bool>, // defaults to true
+    pub projects: Option<Vec<String>>, // repositories shown by gpv dashboard
+    pub hyperlinks: Option<bool>,      // defaults to true
+    pub browser: Option<String>,       // defaults to $BROWSER, then xdg-open
+    pub history: Option<HistoryConfig>,
+    pub pipelines: Option<PipelinesConfig>,
+    pub backend: Option<Backend>, // defaults to rest
+    #[serde(flatten)]
+    pub unknown: HashMap<String, Value>, // reported by gpv config check
 }
 
-pub fn read_config() -> Result<Configuration, String> {
-    let home = match env::var("HOME") {
-        Ok(v) => Ok(v),
-        Err(_) => Err(format!("HOME variable undefined")),
-    }?;
-    let content = fs::read_to_string(&format!("{}/.gitlab-pipeline-viewer.yaml", home));
-    if content.is_err() {
-        return Err(format!("Failed to read ~/.gitlab-pipeline-viewer.yaml"));
-    }
-    let content = content.unwrap();
-    match from_str(&content) {
-        Ok(v) => Ok(v),
-        Err(e) => Err(format!(
-            "Failed to parse ~/.gitlab-pipeline-viewer.yaml\n{}",
-            e
+fn prefixed(prefix: &str, unknown: &HashMap<String, Value>) -> Vec<String> {
+    unknown
+        .keys()
+        .map(|key| format!("{}{}", prefix, key))
+        .collect()
+}
+
+impl Configuration {
+    /// Keys no setting is known for, probably typos
+    pub fn unknown_keys(&self) -> Vec<String> {
+        let mut keys = prefixed("", &self.unknown);
+        if let Some(notifications) = &self.notifications {
+            keys.extend(prefixed("notifications.", &notifications.unknown));
+        }
+        for (i, hook) in self.hooks.iter().flatten().enumerate() {
+            keys.extend(prefixed(&format!("hooks[{}].", i), &hook.unknown));
+        }
+        if let Some(history) = &self.history {
+            keys.extend(prefixed("history.", &history.unknown));
+        }
+        if let Some(pipelines) = &self.pipelines {
+            keys.extend(prefixed("pipelines.", &pipelines.unknown));
+        }
+        if let Some(polling) = &self.polling {
+            keys.extend(prefixed("polling.", &polling.unknown));
+        }
+        if let Some(webhook) = &self.webhook {
+            keys.extend(prefixed("webhook.", &webhook.unknown));
+        }
+        keys.sort();
+        keys
+    }
+}
+
+/// Keys a checked in .gpv.yaml must not set: secrets,
+/// and commands a cloned repository could run on our behalf
+const REPO_FORBIDDEN: [&str; 7] = [
+    "gitlab-tokens",
+    "token-command",
+    "hooks",
+    "browser",
+    "daemon-socket",
+    "projects",
+    "webhook",
+];
+
+/// `over` wins, mappings are merged key by key
+fn merge(base: &mut Value, over: Value) {
+    match (base, over) {
+        (Value::Mapping(base), Value::Mapping(over)) => {
+            for (key, value) in over {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, over) => *base = over,
+    }
+}
+
+pub fn check_repo_config(config: &Value, path: &Path) -> Result<(), String> {
+    let forbidden = REPO_FORBIDDEN
+        .iter()
+        .find(|key| config.get(**key).is_some())
+        .or_else(|| {
+            config
+                .get("notifications")
+                .and_then(|n| n.get("command"))
+                .map(|_| &"notifications.command")
+        });
+    match forbidden {
+        Some(key) => Err(format!(
+            "{} must not set {}, keep it in your own config",
+            path.display(),
+            key
         )),
+        None => Ok(()),
+    }
+}
+
+/// GPV_QUEUE_WARNING=60 sets queue-warning,
+/// GPV_HISTORY__COVERAGE_DROP=2 sets coverage-drop of history
+fn env_overrides<I>(vars: I) -> Value
+where
+    I: Iterator<Item = (String, String)>,
+{
+    let mut overrides = Value::Mapping(Default::default());
+    for (name, value) in vars {
+        let path = match name.strip_prefix("GPV_") {
+            Some(path) if !path.is_empty() => path.to_lowercase(),
+            _ => continue,
+        };
+        let value = from_str(&value).unwrap_or(Value::String(value));
+        let nested = path.rsplit("__").fold(value, |value, key| {
+            let mut mapping = serde_yaml::Mapping::new();
+            mapping.insert(Value::String(key.replace('_', "-")), value);
+            Value::Mapping(mapping)
+        });
+        merge(&mut overrides, nested);
+    }
+    overrides
+}
+
+/// The user config, its XDG location first
+pub fn user_config_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    let home = env::var("HOME").ok().map(PathBuf::from);
+    match (env::var("XDG_CONFIG_HOME"), &home) {
+        (Ok(config), _) if !config.is_empty() => paths.push(PathBuf::from(config)),
+        (_, Some(home)) => paths.push(home.join(".config")),
+        _ => (),
+    }
+    if let Some(config) = paths.pop() {
+        paths.push(config.join("gitlab-pipeline-viewer").join("config.yaml"));
+    }
+    if let Some(home) = home {
+        paths.push(home.join(".gitlab-pipeline-viewer.yaml"));
+    }
+    paths
+}
+
+/// .gpv.yaml of the repository `dir` is in, looked up to its root
+pub fn repo_config_path(dir: &Path) -> Option<PathBuf> {
+    for dir in dir.ancestors() {
+        let path = dir.join(".gpv.yaml");
+        if path.is_file() {
+            return Some(path);
+        }
+        if dir.join(".git").exists() {
+            return None;
+        }
+    }
+    None
+}
+
+/// Checked against `Configuration` on its own,
+/// so that errors point to a line of this file
+pub fn read_yaml(path: &Path) -> Result<Value, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let invalid = |e: serde_yaml::Error| format!("{}: {}", path.display(), e);
+    match from_str::<Value>(&content).map_err(invalid)? {
+        // An empty file has no settings
+        Value::Null => Ok(Value::Mapping(Default::default())),
+        value => {
+            from_str::<Configuration>(&content).map_err(invalid)?;
+            Ok(value)
+        }
+    }
+}
+
+/// The files a configuration is read from, if they exist
+#[derive(Debug, Clone, Default)]
+pub struct ConfigSources {
+    pub user: Option<PathBuf>,
+    pub repo: Option<PathBuf>,
+}
+
+impl ConfigSources {
+    pub fn find() -> ConfigSources {
+        ConfigSources {
+            user: user_config_paths().into_iter().find(|p| p.is_file()),
+            repo: env::current_dir()
+                .ok()
+                .and_then(|dir| repo_config_path(&dir)),
+        }
+    }
+}
+
+/// GPV_* variables on their own
+pub fn env_config<I>(vars: I) -> Result<Value, String>
+where
+    I: Iterator<Item = (String, String)>,
+{
+    let value = env_overrides(vars);
+    serde_yaml::from_value::<Configuration>(value.clone())
+        .map_err(|e| format!("GPV_* variables: {}", e))?;
+    Ok(value)
+}
+
+/// The user config, the .gpv.yaml of the repository and GPV_* variables
+/// merged in that order. All of them are optional.
+pub fn merged_config<I>(sources: &ConfigSources, vars: I) -> Result<Value, String>
+where
+    I: Iterator<Item = (String, String)>,
+{
+    let mut config = Value::Mapping(Default::default());
+    if let Some(path) = &sources.user {
+        merge(&mut config, read_yaml(path)?);
+    }
+    if let Some(path) = &sources.repo {
+        let repo_config = read_yaml(path)?;
+        check_repo_config(&repo_config, path)?;
+        merge(&mut config, repo_config);
+    }
+    merge(&mut config, env_config(vars)?);
+    Ok(config)
+}
+
+pub fn layered_config<I>(sources: &ConfigSources, vars: I) -> Result<Configuration, String>
+where
+    I: Iterator<Item = (String, String)>,
+{
+    serde_yaml::from_value(merged_config(sources, vars)?)
+        .map_err(|e| format!("Invalid configuration\n{}", e))
+}
+
+pub fn read_config() -> Result<Configuration, String> {
+    layered_config(&ConfigSources::find(), env::vars())
+}
+
+/// Notices edits of the config files, including ones created
+/// after the start, by their modification times
+pub struct ConfigWatcher {
+    seen: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+fn modification_times(paths: Vec<PathBuf>) -> Vec<(PathBuf, Option<SystemTime>)> {
+    paths
+        .into_iter()
+        .map(|path| {
+            let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+            (path, modified)
+        })
+        .collect()
+}
+
+fn watched_paths() -> Vec<PathBuf> {
+    let mut paths = user_config_paths();
+    paths.extend(
+        env::current_dir()
+            .ok()
+            .and_then(|dir| repo_config_path(&dir)),
+    );
+    paths
+}
+
+impl ConfigWatcher {
+    pub fn new() -> ConfigWatcher {
+        ConfigWatcher {
+            seen: modification_times(watched_paths()),
+        }
+    }
+
+    fn changed(&mut self, paths: Vec<PathBuf>) -> bool {
+        let current = modification_times(paths);
+        if current == self.seen {
+            return false;
+        }
+        self.seen = current;
+        true
+    }
+
+    /// The configuration read again if a file changed since the last poll
+    pub fn poll(&mut self) -> Option<Result<Configuration, String>> {
+        if !self.changed(watched_paths()) {
+            return None;
+        }
+        Some(read_config())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write(dir: &Path, name: &str, content: &str) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_layered_config() {
+        let dir = env::temp_dir().join(format!("gpv-config-{}", std::process::id()));
+        fs::create_dir_all(dir.join("repo/.git")).unwrap();
+        fs::create_dir_all(dir.join("repo/src")).unwrap();
+        let user = write(
+            &dir,
+            "config.yaml",
+            "gitlab-tokens: {gitlab.com: glpat-x}\ncooldown: 10\nhistory: {pipelines: 20, coverage-drop: 2}\n",
+        );
+        let repo = write(
+            &dir,
+            "repo/.gpv.yaml",
+            "remote: upstream\nhistory: {pipelines: 5}\n",
+        );
+        assert_eq!(repo_config_path(&dir.join("repo/src")), Some(repo.clone()));
+        assert_eq!(repo_config_path(&dir.join("repo/.git")), Some(repo.clone()));
+
+        let vars = vec![
+            ("GPV_COOLDOWN".to_string(), "2".to_string()),
+            (
+                "GPV_HISTORY__DURATION_INCREASE".to_string(),
+                "50".to_string(),
+            ),
+            ("GPV_HYPERLINKS".to_string(), "false".to_string()),
+            ("HOME".to_string(), "/home/me".to_string()),
+        ];
+        let sources = ConfigSources {
+            user: Some(user),
+            repo: Some(repo),
+        };
+        let conf = layered_config(&sources, vars.into_iter()).unwrap();
+        assert_eq!(conf.gitlab_token_mapping["gitlab.com"], "glpat-x");
+        assert_eq!(conf.remote.as_deref(), Some("upstream"));
+        assert_eq!(conf.cooldown, Some(2.0));
+        assert_eq!(conf.hyperlinks, Some(false));
+        let history = conf.history.unwrap();
+        assert_eq!(history.pipelines, Some(5));
+        assert_eq!(history.coverage_drop, Some(2.0));
+        assert_eq!(history.duration_increase, Some(50.0));
+
+        // Nothing configured at all
+        let conf = layered_config(&ConfigSources::default(), std::iter::empty()).unwrap();
+        assert!(conf.gitlab_token_mapping.is_empty());
+        assert!(conf.unknown_keys().is_empty());
+
+        let evil = write(
+            &dir,
+            "repo/.gpv.yaml",
+            "token-command: {gitlab.com: 'curl evil'}\n",
+        );
+        let repo_only = |repo: PathBuf| ConfigSources {
+            user: None,
+            repo: Some(repo),
+        };
+        assert!(layered_config(&repo_only(evil), std::iter::empty())
+            .unwrap_err()
+            .contains("must not set token-command"));
+        let evil = write(
+            &dir,
+            "repo/.gpv.yaml",
+            "notifications: {command: 'rm -rf ~'}\n",
+        );
+        assert!(layered_config(&repo_only(evil), std::iter::empty())
+            .unwrap_err()
+            .contains("must not set notifications.command"));
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_config_watcher() {
+        let dir = env::temp_dir().join(format!("gpv-config-watch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.yaml");
+        let mut watcher = ConfigWatcher { seen: Vec::new() };
+        assert!(watcher.changed(vec![path.clone()]));
+        assert!(!watcher.changed(vec![path.clone()]));
+
+        // Created, then modified
+        let file = fs::File::create(&path).unwrap();
+        assert!(watcher.changed(vec![path.clone()]));
+        file.set_modified(SystemTime::UNIX_EPOCH).unwrap();
+        assert!(watcher.changed(vec![path.clone()]));
+        assert!(!watcher.changed(vec![path.clone()]));
+
+        fs::remove_dir_all(&dir).ok();
     }
 }
@@ -0,0 +1,437 @@
+/*
+`gpv config check`, `init` and `show`.
+
+check reads every source on its own so that an error names its file,
+then asks each host who its token belongs to. init writes the user
+config readable only by its owner, as it may hold a token.
+*/
+use crate::config::{
+    check_repo_config, env_config, layered_config, merged_config, read_yaml, user_config_paths,
+    ConfigSources, Configuration,
+};
+use crate::emoji::{FAILED, GREEN_CHECK, GREY_EXCLAMATION};
+use crate::gitlabbing::{check_token, gitlab_client, repository_target, TokenInfo};
+use crate::{git, run, tokens};
+use clap::Subcommand;
+use crossterm::event::{self, Event, KeyCode, KeyModifiers};
+use crossterm::terminal;
+use gitlab::Gitlab;
+use serde_yaml::Value;
+use std::fs;
+use std::io::{BufRead, IsTerminal, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+pub enum ConfigAction {
+    /// Validate the configuration and test the token of every host
+    Check,
+    /// Ask for a host and token and write the user config
+    Init,
+    /// Print the merged configuration, tokens redacted
+    Show,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    Invalid(String),
+    Unknown { source: String, key: String },
+}
+
+fn unknown_keys(source: &str, value: Value) -> Vec<Problem> {
+    match serde_yaml::from_value::<Configuration>(value) {
+        Err(e) => vec![Problem::Invalid(format!("{}: {}", source, e))],
+        Ok(conf) => conf
+            .unknown_keys()
+            .into_iter()
+            .map(|key| Problem::Unknown {
+                source: source.to_string(),
+                key,
+            })
+            .collect(),
+    }
+}
+
+/// Problems of the config files and GPV_* variables, each checked on its own
+pub fn layer_problems<I>(sources: &ConfigSources, vars: I) -> Vec<Problem>
+where
+    I: Iterator<Item = (String, String)>,
+{
+    let mut problems = Vec::new();
+    if let Some(path) = &sources.user {
+        match read_yaml(path) {
+            Ok(value) => problems.extend(unknown_keys(&path.display().to_string(), value)),
+            Err(e) => problems.push(Problem::Invalid(e)),
+        }
+    }
+    if let Some(path) = &sources.repo {
+        match read_yaml(path).and_then(|v| check_repo_config(&v, path).map(|_| v)) {
+            Ok(value) => problems.extend(unknown_keys(&path.display().to_string(), value)),
+            Err(e) => problems.push(Problem::Invalid(e)),
+        }
+    }
+    match env_config(vars) {
+        Ok(value) => problems.extend(unknown_keys("GPV_* variables", value)),
+        Err(e) => problems.push(Problem::Invalid(e)),
+    }
+    problems
+}
+
+pub fn describe_token(host: &str, info: &Result<TokenInfo, String>) -> String {
+    let info = match info {
+        Err(e) => return format!("{} {}: {}", FAILED, host, e),
+        Ok(info) => info,
+    };
+    let details = match &info.details {
+        // OAuth tokens cannot look at themselves, job tokens fail before
+        None => return format!("{} {}: token of {}", GREEN_CHECK, host, info.username),
+        Some(details) => details,
+    };
+    let mut line = format!(
+        "{} {}: token \"{}\" of {} with {}",
+        GREEN_CHECK,
+        host,
+        details.name,
+        info.username,
+        details.scopes.join(", ")
+    );
+    if let Some(expires_at) = &details.expires_at {
+        line += &format!(", expires {}", expires_at);
+    }
+    let readable = ["api", "read_api"]
+        .iter()
+        .any(|scope| details.scopes.iter().any(|s| s == scope));
+    if !readable {
+        line += &format!(
+            "\n{} {} needs the read_api scope, api to run pipelines",
+            GREY_EXCLAMATION, host
+        );
+    }
+    line
+}
+
+/// Prints what is wrong, false if gpv would not start like this
+pub fn check<I>(sources: &ConfigSources, vars: I) -> bool
+where
+    I: Iterator<Item = (String, String)> + Clone,
+{
+    match &sources.user {
+        Some(path) => println!("User config: {}", path.display()),
+        None => println!("User config: none"),
+    }
+    match &sources.repo {
+        Some(path) => println!("Repository config: {}", path.display()),
+        None => println!("Repository config: none"),
+    }
+
+    let mut valid = true;
+    for problem in layer_problems(sources, vars.clone()) {
+        match problem {
+            Problem::Invalid(e) => {
+                valid = false;
+                println!("{} {}", FAILED, e);
+            }
+            Problem::Unknown { source, key } => {
+                println!("{} {}: unknown key {}", GREY_EXCLAMATION, source, key)
+            }
+        }
+    }
+    if !valid {
+        return false;
+    }
+    let conf = match layered_config(sources, vars) {
+        Ok(conf) => conf,
+        Err(e) => {
+            println!("{} {}", FAILED, e);
+            return false;
+        }
+    };
+
+    let mut hosts = tokens::known_hosts(&conf);
+    let repo = git::get_repository_at(Path::new("./"), &conf);
+    if let Ok(target) = repo.and_then(|repo| repository_target(&repo)) {
+        if !hosts.contains(&target.host) {
+            hosts.push(target.host);
+        }
+    }
+    if hosts.is_empty() {
+        println!("{} No tokens configured", FAILED);
+        return false;
+    }
+    for host in hosts {
+        let info = gitlab_client(&host, &conf).and_then(|client| check_token(&client));
+        valid &= info.is_ok();
+        println!("{}", describe_token(&host, &info));
+    }
+    valid
+}
+
+/// "glpat-***", enough to tell tokens apart from commands
+fn redact_token(token: &str) -> String {
+    match token.split_once('-') {
+        Some((prefix, _)) if prefix.len() <= 6 => format!("{}-***", prefix),
+        _ => "***".to_string(),
+    }
+}
+
+fn get_mut<'a>(value: &'a mut Value, key: &str) -> Option<&'a mut Value> {
+    value
+        .as_mapping_mut()?
+        .get_mut(&Value::String(key.to_string()))
+}
+
+pub fn redact(config: &mut Value) {
+    let tokens = get_mut(config, "gitlab-tokens").and_then(|t| t.as_mapping_mut());
+    for (_, token) in tokens.into_iter().flatten() {
+        *token = Value::String(redact_token(token.as_str().unwrap_or_default()));
+    }
+    if let Some(secret) = get_mut(config, "webhook").and_then(|w| get_mut(w, "secret")) {
+        *secret = Value::String("***".to_string());
+    }
+}
+
+pub fn show<I>(sources: &ConfigSources, vars: I) -> Result<String, String>
+where
+    I: Iterator<Item = (String, String)>,
+{
+    let mut config = merged_config(sources, vars)?;
+    redact(&mut config);
+    let mut shown = String::new();
+    for path in [&sources.user, &sources.repo].into_iter().flatten() {
+        shown += &format!("# {}\n", path.display());
+    }
+    shown += "# GPV_* variables\n";
+    shown += &serde_yaml::to_string(&config).map_err(|e| e.to_string())?;
+    Ok(shown)
+}
+
+/// The user config written by init, the token commented out if there is none
+pub fn init_content(host: &str, token: Option<&str>) -> String {
+    let quote = |text: &str| {
+        serde_yaml::to_string(text)
+            .map(|s| s.trim_start_matches("---").trim().to_string())
+            .unwrap_or_default()
+    };
+    let mut content =
+        "---\n# Written by gpv config init, see the README for all settings.\n\n".to_string();
+    match token {
+        Some(token) => {
+            content += &format!("gitlab-tokens:\n  {}: {}\n", quote(host), quote(token));
+        }
+        None => {
+            content += "# No token here, it comes from GITLAB_TOKEN, token-command,\n";
+            content += "# the keyring or glab.\n";
+            content += &format!("# gitlab-tokens:\n#   {}: glpat-...\n", quote(host));
+        }
+    }
+    content += "\n# Seconds between refreshes.\ncooldown: 5\n";
+    content
+}
+
+/// Reads a line without echoing it when in a terminal
+fn read_secret<R: BufRead, W: Write>(
+    prompt: &str,
+    input: &mut R,
+    output: &mut W,
+) -> Result<String, String> {
+    write!(output, "{}", prompt).ok();
+    output.flush().ok();
+    if !std::io::stdin().is_terminal() {
+        return run::read_line(input);
+    }
+    terminal::enable_raw_mode().map_err(|e| e.to_string())?;
+    let mut secret = String::new();
+    let result = loop {
+        let key = match event::read() {
+            Ok(Event::Key(key)) => key,
+            Ok(_) => continue,
+            Err(e) => break Err(e.to_string()),
+        };
+        match key.code {
+            KeyCode::Enter => break Ok(secret),
+            KeyCode::Esc => break Err("Aborted".to_string()),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                break Err("Aborted".to_string())
+            }
+            KeyCode::Char(c) => secret.push(c),
+            KeyCode::Backspace => {
+                secret.pop();
+            }
+            _ => (),
+        }
+    };
+    terminal::disable_raw_mode().ok();
+    writeln!(output).ok();
+    result.map(|s| s.trim().to_string())
+}
+
+fn write_private(path: &Path, content: &str) -> Result<(), String> {
+    let failed = |e: std::io::Error| format!("Could not write {}: {}", path.display(), e);
+    if let Some(dir) = path.parent() {
+        let created = !dir.exists();
+        fs::create_dir_all(dir).map_err(failed)?;
+        if created {
+            fs::set_permissions(dir, fs::Permissions::from_mode(0o700)).map_err(failed)?;
+        }
+    }
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .map_err(failed)?;
+    // An existing file keeps its mode otherwise
+    file.set_permissions(fs::Permissions::from_mode(0o600))
+        .map_err(failed)?;
+    file.write_all(content.as_bytes()).map_err(failed)
+}
+
+pub fn init<R: BufRead, W: Write>(
+    sources: &ConfigSources,
+    input: &mut R,
+    output: &mut W,
+) -> Result<PathBuf, String> {
+    let path = match &sources.user {
+        Some(path) => path.clone(),
+        None => user_config_paths()
+            .into_iter()
+            .next()
+            .ok_or("Neither XDG_CONFIG_HOME nor HOME is set")?,
+    };
+    if path.exists() {
+        let question = format!("{} exists, overwrite it?", path.display());
+        if !run::confirm(&question, false, input, output)? {
+            return Err("Left the config as it is".to_string());
+        }
+    }
+
+    write!(output, "GitLab host [gitlab.com]: ").ok();
+    output.flush().ok();
+    let host = run::read_line(input)?;
+    let host = match host.trim() {
+        "" => "gitlab.com".to_string(),
+        host => host.to_string(),
+    };
+    writeln!(
+        output,
+        "Create a token with the read_api scope (api to run pipelines) at\n\
+         https://{}/-/user_settings/personal_access_tokens?name=gpv&scopes=read_api",
+        host
+    )
+    .ok();
+    let token = read_secret("Token, empty to keep it out of the file: ", input, output)?;
+    let token = Some(token).filter(|t| !t.is_empty());
+
+    if let Some(token) = &token {
+        let info = Gitlab::new(&host, token)
+            .map_err(|e| format!("The token is not accepted: {}", e))
+            .and_then(|client| check_token(&client));
+        writeln!(output, "{}", describe_token(&host, &info)).ok();
+        if info.is_err() && !run::confirm("Save it anyway?", false, input, output)? {
+            return Err("Nothing written".to_string());
+        }
+    }
+    write_private(&path, &init_content(&host, token.as_deref()))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gitlabbing::TokenDetails;
+    use std::env;
+
+    #[test]
+    fn test_layer_problems() {
+        let dir = env::temp_dir().join(format!("gpv-config-check-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let user = dir.join("config.yaml");
+        fs::write(
+            &user,
+            "cooldown: 3\nhistroy: {pipelines: 3}\nhooks: [{on: job, command: x, statu: failed}]\n",
+        )
+        .unwrap();
+        let repo = dir.join(".gpv.yaml");
+        fs::write(&repo, "cooldown: [3]\n").unwrap();
+        let sources = ConfigSources {
+            user: Some(user.clone()),
+            repo: Some(repo),
+        };
+        let vars = vec![("GPV_PIPELINES__LSIT".to_string(), "20".to_string())];
+        let problems = layer_problems(&sources, vars.into_iter());
+        let user = user.display().to_string();
+        assert_eq!(problems.len(), 4);
+        assert_eq!(
+            problems[..2],
+            [
+                Problem::Unknown {
+                    source: user.clone(),
+                    key: "histroy".to_string()
+                },
+                Problem::Unknown {
+                    source: user,
+                    key: "hooks[0].statu".to_string()
+                },
+            ]
+        );
+        assert!(matches!(&problems[2], Problem::Invalid(e) if e.contains(".gpv.yaml: cooldown")));
+        assert_eq!(
+            problems[3],
+            Problem::Unknown {
+                source: "GPV_* variables".to_string(),
+                key: "pipelines.lsit".to_string()
+            }
+        );
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_describe_token() {
+        let info = Ok(TokenInfo {
+            username: "julian".to_string(),
+            details: Some(TokenDetails {
+                name: "gpv".to_string(),
+                scopes: vec!["read_user".to_string()],
+                expires_at: Some("2025-01-01".to_string()),
+            }),
+        });
+        assert_eq!(
+            describe_token("gitlab.com", &info),
+            format!(
+                "{} gitlab.com: token \"gpv\" of julian with read_user, expires 2025-01-01\n\
+                 {} gitlab.com needs the read_api scope, api to run pipelines",
+                GREEN_CHECK, GREY_EXCLAMATION
+            )
+        );
+        assert_eq!(
+            describe_token("gitlab.com", &Err("401".to_string())),
+            format!("{} gitlab.com: 401", FAILED)
+        );
+    }
+
+    #[test]
+    fn test_redact() {
+        let mut config: Value = serde_yaml::from_str(
+            "gitlab-tokens: {gitlab.com: glpat-abc123, other.com: secret}\ncooldown: 3\nwebhook: {listen: ':8642', secret: hunter2}\n",
+        )
+        .unwrap();
+        redact(&mut config);
+        let tokens = &config["gitlab-tokens"];
+        assert_eq!(tokens["gitlab.com"].as_str(), Some("glpat-***"));
+        assert_eq!(tokens["other.com"].as_str(), Some("***"));
+        assert_eq!(config["cooldown"].as_u64(), Some(3));
+        assert_eq!(config["webhook"]["secret"].as_str(), Some("***"));
+        assert_eq!(config["webhook"]["listen"].as_str(), Some(":8642"));
+    }
+
+    #[test]
+    fn test_init_content() {
+        let conf: Configuration =
+            serde_yaml::from_str(&init_content("gitlab.com", Some("glpat-a: b"))).unwrap();
+        assert_eq!(conf.gitlab_token_mapping["gitlab.com"], "glpat-a: b");
+        assert!(conf.unknown_keys().is_empty());
+        let conf: Configuration = serde_yaml::from_str(&init_content("gitlab.com", None)).unwrap();
+        assert!(conf.gitlab_token_mapping.is_empty());
+    }
+}
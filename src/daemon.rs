@@ -0,0 +1,569 @@
+/*
+Background daemon sharing pipeline snapshots over a Unix socket.
+
+`gpv daemon` polls GitLab for every repository a client asked about,
+so any number of viewers, prompts and editor integrations on the
+machine cost only one poller. Repositories nobody asked about for
+IDLE_TIMEOUT are dropped again.
+
+Protocol: newline delimited JSON. Every request is one line,
+every response is one line.
+
+  -> {"request":"ping"}
+  <- {"type":"pong"}
+
+  -> {"request":"list"}
+  <- {"type":"repos","repos":["/home/me/src/project"]}
+
+  -> {"request":"snapshot","repo":"/home/me/src/project"}
+  <- {"type":"snapshot","repo":"...","fetched_at":"...","snapshot":{...}}
+  <- {"type":"error","repo":"...","message":"No token for origin ..."}
+
+  -> {"request":"subscribe","repo":"/home/me/src/project"}
+  <- one snapshot or error line now and after every refresh,
+     until the client disconnects. No further requests are read.
+
+`repo` is the working directory of the repository.
+`snapshot` is the same structure the terminal view renders.
+
+Anyone who can connect makes the daemon fetch with our tokens,
+so the socket is only accessible by its owner and lives in
+$XDG_RUNTIME_DIR or a private directory in /tmp. Clients only
+talk to a socket owned by themselves.
+*/
+use crate::config::Configuration;
+use crate::gitlabbing::GitlabProjectPipelines;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "request", rename_all = "kebab-case")]
+pub enum Request {
+    Ping,
+    List,
+    Snapshot { repo: PathBuf },
+    Subscribe { repo: PathBuf },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Response {
+    Pong,
+    Repos {
+        repos: Vec<PathBuf>,
+    },
+    Snapshot {
+        repo: PathBuf,
+        fetched_at: DateTime<Utc>,
+        snapshot: Box<GitlabProjectPipelines>,
+    },
+    Error {
+        repo: Option<PathBuf>,
+        message: String,
+    },
+}
+
+pub type Fetcher = Arc<dyn Fn(&Path) -> Result<GitlabProjectPipelines, String> + Send + Sync>;
+
+pub fn socket_path(conf: &Configuration) -> PathBuf {
+    if let Some(socket) = &conf.daemon_socket {
+        return PathBuf::from(socket);
+    }
+    match env::var("XDG_RUNTIME_DIR") {
+        Ok(dir) if !dir.is_empty() => Path::new(&dir).join("gitlab-pipeline-viewer.sock"),
+        _ => env::temp_dir()
+            .join(format!("gitlab-pipeline-viewer-{}", uid()))
+            .join("daemon.sock"),
+    }
+}
+
+fn uid() -> u32 {
+    // SAFETY: getuid has no preconditions and cannot fail
+    unsafe { libc::getuid() }
+}
+
+/// Whether `path` itself (not what a symlink points to) belongs to us
+fn owned_by_us(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|m| m.uid() == uid())
+}
+
+/// Create `dir` accessible only by us, or check that it is
+fn private_dir(dir: &Path) -> Result<(), String> {
+    if !dir.exists() {
+        fs::DirBuilder::new()
+            .mode(0o700)
+            .create(dir)
+            .map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+    }
+    let meta = fs::symlink_metadata(dir)
+        .map_err(|e| format!("Could not check {}: {}", dir.display(), e))?;
+    if !meta.is_dir() || meta.uid() != uid() || meta.mode() & 0o077 != 0 {
+        return Err(format!(
+            "{} must be a directory only accessible by you",
+            dir.display()
+        ));
+    }
+    Ok(())
+}
+
+struct WatchedRepo {
+    last_requested: Instant,
+    fetched_at: DateTime<Utc>,
+    result: Result<GitlabProjectPipelines, String>,
+    subscribers: Vec<Sender<String>>,
+}
+
+impl WatchedRepo {
+    fn response_line(&self, repo: &Path) -> String {
+        let response = match &self.result {
+            Ok(snapshot) => Response::Snapshot {
+                repo: repo.to_path_buf(),
+                fetched_at: self.fetched_at,
+                snapshot: Box::new(snapshot.clone()),
+            },
+            Err(e) => Response::Error {
+                repo: Some(repo.to_path_buf()),
+                message: e.clone(),
+            },
+        };
+        serde_json::to_string(&response).unwrap()
+    }
+}
+
+type Repos = Arc<Mutex<HashMap<PathBuf, WatchedRepo>>>;
+
+/// Marks `repo` as requested, false if it is not watched yet
+fn touch(repos: &Repos, repo: &Path) -> bool {
+    match repos.lock().unwrap().get_mut(repo) {
+        Some(watched) => {
+            watched.last_requested = Instant::now();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Make sure `repo` is watched and mark it as requested.
+/// The first request for a repository fetches it right away,
+/// clients asking at the same time wait for that fetch.
+fn watch(repos: &Repos, fetcher: &Fetcher, repo: &Path) {
+    static FIRST_FETCH: Mutex<()> = Mutex::new(());
+    if touch(repos, repo) {
+        return;
+    }
+    let _fetching = FIRST_FETCH.lock().unwrap();
+    if touch(repos, repo) {
+        return;
+    }
+    let result = fetcher(repo);
+    repos
+        .lock()
+        .unwrap()
+        .entry(repo.to_path_buf())
+        .or_insert(WatchedRepo {
+            last_requested: Instant::now(),
+            fetched_at: Utc::now(),
+            result,
+            subscribers: Vec::new(),
+        });
+}
+
+fn write_line(stream: &mut UnixStream, line: &str) -> bool {
+    stream.write_all(line.as_bytes()).is_ok() && stream.write_all(b"\n").is_ok()
+}
+
+/// The poller dropped the repository between watching and answering
+fn dropped_line(repo: &Path) -> String {
+    let response = Response::Error {
+        repo: Some(repo.to_path_buf()),
+        message: format!("{} was dropped by the daemon, ask again", repo.display()),
+    };
+    serde_json::to_string(&response).unwrap()
+}
+
+fn handle_connection(stream: UnixStream, repos: Repos, fetcher: Fetcher) {
+    let mut writer = match stream.try_clone() {
+        Ok(s) => s,
+        Err(_) => return,
+    };
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(l) => l,
+            Err(_) => return,
+        };
+        let request: Request = match serde_json::from_str(&line) {
+            Ok(r) => r,
+            Err(e) => {
+                let response = Response::Error {
+                    repo: None,
+                    message: format!("Invalid request: {}", e),
+                };
+                if !write_line(&mut writer, &serde_json::to_string(&response).unwrap()) {
+                    return;
+                }
+                continue;
+            }
+        };
+        let answer = match request {
+            Request::Ping => serde_json::to_string(&Response::Pong).unwrap(),
+            Request::List => {
+                let mut list: Vec<PathBuf> = repos.lock().unwrap().keys().cloned().collect();
+                list.sort();
+                serde_json::to_string(&Response::Repos { repos: list }).unwrap()
+            }
+            Request::Snapshot { repo } => {
+                watch(&repos, &fetcher, &repo);
+                match repos.lock().unwrap().get(&repo) {
+                    Some(watched) => watched.response_line(&repo),
+                    None => dropped_line(&repo),
+                }
+            }
+            Request::Subscribe { repo } => {
+                watch(&repos, &fetcher, &repo);
+                let (sender, receiver) = channel();
+                let current = {
+                    let mut repos = repos.lock().unwrap();
+                    match repos.get_mut(&repo) {
+                        Some(watched) => {
+                            watched.subscribers.push(sender);
+                            watched.response_line(&repo)
+                        }
+                        None => {
+                            write_line(&mut writer, &dropped_line(&repo));
+                            return;
+                        }
+                    }
+                };
+                if !write_line(&mut writer, &current) {
+                    return;
+                }
+                for update in receiver {
+                    if !write_line(&mut writer, &update) {
+                        return;
+                    }
+                }
+                return;
+            }
+        };
+        if !write_line(&mut writer, &answer) {
+            return;
+        }
+    }
+}
+
+/// Refreshes every watched repository each `cooldown`,
+/// watching one fetched it already
+fn poll(repos: Repos, fetcher: Fetcher, cooldown: Duration, running: Arc<AtomicBool>) {
+    loop {
+        let started = Instant::now();
+        while started.elapsed() < cooldown {
+            if !running.load(Ordering::SeqCst) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        let paths: Vec<PathBuf> = repos.lock().unwrap().keys().cloned().collect();
+        for path in paths {
+            let result = fetcher(&path);
+            let mut repos = repos.lock().unwrap();
+            if let Some(watched) = repos.get_mut(&path) {
+                watched.result = result;
+                watched.fetched_at = Utc::now();
+                let line = watched.response_line(&path);
+                watched
+                    .subscribers
+                    .retain(|subscriber| subscriber.send(line.clone()).is_ok());
+                if !watched.subscribers.is_empty() {
+                    watched.last_requested = Instant::now();
+                }
+            }
+        }
+        repos
+            .lock()
+            .unwrap()
+            .retain(|_, watched| watched.last_requested.elapsed() < IDLE_TIMEOUT);
+    }
+}
+
+/// Serve on `socket` until `running` turns false.
+pub fn serve(
+    socket: &Path,
+    fetcher: Fetcher,
+    cooldown: Duration,
+    running: Arc<AtomicBool>,
+) -> Result<(), String> {
+    if UnixStream::connect(socket).is_ok() {
+        return Err(format!(
+            "A gpv daemon is already listening on {}",
+            socket.display()
+        ));
+    }
+    // A directory of its own in /tmp, see socket_path
+    let dir = socket.parent().unwrap_or(Path::new("/"));
+    if dir.starts_with(env::temp_dir()) && dir != env::temp_dir() {
+        private_dir(dir)?;
+    }
+    // Left over from a daemon which did not shut down cleanly
+    if socket.exists() {
+        if !owned_by_us(socket) {
+            return Err(format!(
+                "{} belongs to another user, not replacing it",
+                socket.display()
+            ));
+        }
+        fs::remove_file(socket).ok();
+    }
+    // Created accessible by us only, there is no moment anyone else could
+    // connect. The umask is process wide, so it is set back right away.
+    // SAFETY: umask has no preconditions and cannot fail
+    let umask = unsafe { libc::umask(0o077) };
+    let listener = UnixListener::bind(socket);
+    unsafe { libc::umask(umask) };
+    let listener =
+        listener.map_err(|e| format!("Could not listen on {}: {}", socket.display(), e))?;
+    listener
+        .set_nonblocking(true)
+        .map_err(|e| format!("Could not listen on {}: {}", socket.display(), e))?;
+
+    let repos: Repos = Arc::new(Mutex::new(HashMap::new()));
+    {
+        let (repos, fetcher, running) = (repos.clone(), fetcher.clone(), running.clone());
+        thread::spawn(move || poll(repos, fetcher, cooldown, running));
+    }
+
+    while running.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(false).ok();
+                let (repos, fetcher) = (repos.clone(), fetcher.clone());
+                thread::spawn(move || handle_connection(stream, repos, fetcher));
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(50));
+            }
+            Err(e) => return Err(format!("Could not accept connection: {}", e)),
+        }
+    }
+    fs::remove_file(socket).ok();
+    Ok(())
+}
+
+pub fn request(socket: &Path, request: &Request) -> Result<Response, String> {
+    if !owned_by_us(socket) {
+        return Err(format!(
+            "{} belongs to another user, not asking it",
+            socket.display()
+        ));
+    }
+    let mut stream = UnixStream::connect(socket)
+        .map_err(|e| format!("Could not connect to {}: {}", socket.display(), e))?;
+    stream.set_read_timeout(Some(Duration::from_secs(60))).ok();
+    let line = serde_json::to_string(request).unwrap();
+    if !write_line(&mut stream, &line) {
+        return Err("Could not send request to gpv daemon".to_string());
+    }
+    let mut answer = String::new();
+    BufReader::new(stream)
+        .read_line(&mut answer)
+        .map_err(|e| format!("No answer from gpv daemon: {}", e))?;
+    serde_json::from_str(&answer).map_err(|e| format!("Invalid answer from gpv daemon: {}", e))
+}
+
+/// The latest snapshot of `repo` from a running daemon,
+/// or None if there is no daemon to ask.
+pub fn fetch_via_daemon(
+    conf: &Configuration,
+    repo: &Path,
+) -> Option<Result<GitlabProjectPipelines, String>> {
+    let socket = socket_path(conf);
+    if !socket.exists() {
+        return None;
+    }
+    let snapshot = Request::Snapshot {
+        repo: repo.to_path_buf(),
+    };
+    match request(&socket, &snapshot) {
+        Err(_) => None,
+        Ok(Response::Snapshot { snapshot, .. }) => Some(Ok(*snapshot)),
+        Ok(Response::Error { message, .. }) => Some(Err(message)),
+        Ok(_) => Some(Err("Unexpected answer from gpv daemon".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::*;
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::atomic::AtomicUsize;
+
+    fn wait_for(socket: &Path) {
+        let started = Instant::now();
+        while UnixStream::connect(socket).is_err() {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_daemon_protocol() {
+        let socket = env::temp_dir().join(format!("gpv-test-{}.sock", std::process::id()));
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let counter = fetches.clone();
+        let fetcher: Fetcher = Arc::new(move |path: &Path| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            if path == Path::new("/broken") {
+                return Err("No Git repository found in /broken".to_string());
+            }
+            Ok(project_pipelines(vec![(pipeline(), jobs())]))
+        });
+        let running = Arc::new(AtomicBool::new(true));
+        {
+            let (socket, running) = (socket.clone(), running.clone());
+            thread::spawn(move || {
+                serve(&socket, fetcher, Duration::from_secs(3600), running).unwrap()
+            });
+        }
+        wait_for(&socket);
+
+        assert!(matches!(
+            request(&socket, &Request::Ping).unwrap(),
+            Response::Pong
+        ));
+        let repo = PathBuf::from("/home/me/src/gpv");
+        match request(&socket, &Request::Snapshot { repo: repo.clone() }).unwrap() {
+            Response::Snapshot { snapshot, .. } => {
+                assert_eq!(snapshot.pipelines[0].0.id.value(), 569633322);
+                assert_eq!(snapshot.pipelines[0].1.len(), 3);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        // Served from the daemon's state, not fetched again,
+        // also to clients asking at the same time
+        let clients: Vec<_> = (0..8)
+            .map(|_| {
+                let (socket, repo) = (socket.clone(), repo.clone());
+                thread::spawn(move || request(&socket, &Request::Snapshot { repo }).unwrap())
+            })
+            .collect();
+        for client in clients {
+            assert!(matches!(client.join().unwrap(), Response::Snapshot { .. }));
+        }
+        let other = PathBuf::from("/home/me/src/other");
+        let clients: Vec<_> = (0..8)
+            .map(|_| {
+                let (socket, repo) = (socket.clone(), other.clone());
+                thread::spawn(move || request(&socket, &Request::Snapshot { repo }).unwrap())
+            })
+            .collect();
+        for client in clients {
+            assert!(matches!(client.join().unwrap(), Response::Snapshot { .. }));
+        }
+        assert!(matches!(
+            request(
+                &socket,
+                &Request::Snapshot {
+                    repo: "/broken".into()
+                }
+            )
+            .unwrap(),
+            Response::Error { .. }
+        ));
+        match request(&socket, &Request::List).unwrap() {
+            Response::Repos { repos } => {
+                assert_eq!(repos, vec![PathBuf::from("/broken"), repo, other])
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // One fetch per repository, the poller waits for the cooldown
+        assert_eq!(fetches.load(Ordering::SeqCst), 3);
+
+        let mode = fs::metadata(&socket).unwrap().mode();
+        assert_eq!(mode & 0o077, 0);
+
+        running.store(false, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_subscribe() {
+        let socket = env::temp_dir().join(format!("gpv-test-sub-{}.sock", std::process::id()));
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let counter = fetches.clone();
+        let fetcher: Fetcher = Arc::new(move |_: &Path| {
+            let mut pipeline = pipeline();
+            pipeline.id = gitlab::PipelineId::new(counter.fetch_add(1, Ordering::SeqCst) as u64);
+            Ok(project_pipelines(vec![(pipeline, jobs())]))
+        });
+        let running = Arc::new(AtomicBool::new(true));
+        {
+            let (socket, running) = (socket.clone(), running.clone());
+            thread::spawn(move || {
+                serve(&socket, fetcher, Duration::from_millis(100), running).unwrap()
+            });
+        }
+        wait_for(&socket);
+
+        let mut stream = UnixStream::connect(&socket).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let subscribe = Request::Subscribe {
+            repo: "/home/me/src/gpv".into(),
+        };
+        assert!(write_line(
+            &mut stream,
+            &serde_json::to_string(&subscribe).unwrap()
+        ));
+        let ids: Vec<u64> = BufReader::new(stream)
+            .lines()
+            .take(2)
+            .map(|line| match serde_json::from_str(&line.unwrap()).unwrap() {
+                Response::Snapshot { snapshot, .. } => snapshot.pipelines[0].0.id.value(),
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect();
+        // The current snapshot, then the one pushed after the next poll
+        assert_eq!(ids, vec![0, 1]);
+
+        running.store(false, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_private_dir() {
+        let dir = env::temp_dir().join(format!("gpv-test-private-{}", std::process::id()));
+        private_dir(&dir).unwrap();
+        assert_eq!(fs::metadata(&dir).unwrap().mode() & 0o777, 0o700);
+        private_dir(&dir).unwrap();
+
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(private_dir(&dir).is_err());
+        fs::remove_dir(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_foreign_socket() {
+        // Owned by root, or at least not by us unless we are root
+        let foreign = Path::new("/");
+        assert_eq!(owned_by_us(foreign), uid() == 0);
+        if uid() != 0 {
+            assert!(request(foreign, &Request::Ping)
+                .unwrap_err()
+                .contains("belongs to another user"));
+        }
+    }
+}
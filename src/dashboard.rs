@@ -0,0 +1,202 @@
+/*
+Dashboard over several repositories.
+
+Shows one line per repository with the latest pipeline of its
+checked out branch. Enter expands the selected repository into
+the regular detailed view, Esc goes back to the table.
+*/
+use crate::config::Configuration;
+use crate::gitlabbing;
+use crate::hooks::Hooks;
+use crate::keys::{self, Action};
+use crate::notifications::Notifications;
+use crate::render::{self, DashboardEntry};
+use crate::schedule::{has_active, Intervals, Scheduler};
+use crate::transitions::StatusTracker;
+use crossterm::event::{KeyCode, KeyEvent};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+#[derive(Default, Debug, PartialEq)]
+pub struct DashboardState {
+    pub selected: usize,
+    pub expanded: bool,
+}
+
+impl DashboardState {
+    pub fn handle_key(&mut self, key: KeyEvent, entries: usize) -> Action {
+        if keys::is_quit(&key) {
+            return Action::Quit;
+        }
+        match key.code {
+            KeyCode::Char('j') | KeyCode::Down if !self.expanded => {
+                if self.selected + 1 < entries {
+                    self.selected += 1;
+                }
+                Action::Redraw
+            }
+            KeyCode::Char('k') | KeyCode::Up if !self.expanded => {
+                self.selected = self.selected.saturating_sub(1);
+                Action::Redraw
+            }
+            KeyCode::Enter if !self.expanded && entries > 0 => {
+                self.expanded = true;
+                Action::Redraw
+            }
+            KeyCode::Esc | KeyCode::Backspace if self.expanded => {
+                self.expanded = false;
+                Action::Redraw
+            }
+            _ => Action::Nothing,
+        }
+    }
+}
+
+/// "~/src/gpv" becomes "$HOME/src/gpv"
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), env::var("HOME")) {
+        (Some(rest), Ok(home)) => Path::new(&home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Repositories given on the command line, or the `projects` of the config
+pub fn dashboard_paths(args: &[PathBuf], conf: &Configuration) -> Result<Vec<PathBuf>, String> {
+    if !args.is_empty() {
+        return Ok(args.to_vec());
+    }
+    match &conf.projects {
+        Some(projects) if !projects.is_empty() => {
+            Ok(projects.iter().map(|p| expand_home(p)).collect())
+        }
+        _ => Err(
+            "No repositories given. Pass them to gpv dashboard or list them under projects: in the config file"
+                .to_string(),
+        ),
+    }
+}
+
+fn draw(entries: &[DashboardEntry], state: &DashboardState, conf: &Configuration) {
+    let text = if state.expanded {
+        match &entries[state.selected].result {
+            Ok(project) => render::render(project, conf) + "\nEsc back   q quit\n",
+            Err(e) => format!("Error\n{}\n\nEsc back   q quit\n", e),
+        }
+    } else {
+        render::render_dashboard(entries, state.selected, render::get_terminal_width())
+    };
+    render::print_screen(&text);
+}
+
+pub fn run<F>(
+    conf: &Configuration,
+    paths: Vec<PathBuf>,
+    running: &Arc<AtomicBool>,
+    mut fetch: F,
+) -> Result<(), String>
+where
+    F: FnMut(&Path) -> DashboardEntry,
+{
+    let mut scheduler = Scheduler::new(Intervals::from_config(conf)?, Instant::now());
+    let mut trackers: Vec<StatusTracker> = paths.iter().map(|_| StatusTracker::default()).collect();
+    let mut notifications = Notifications::from_config(conf);
+    let mut hooks = Hooks::new(conf.hooks.clone().unwrap_or_default());
+    let mut state = DashboardState::default();
+
+    keys::enter_interactive();
+
+    while running.load(Ordering::SeqCst) {
+        let entries: Vec<DashboardEntry> = paths.iter().map(|p| fetch(p)).collect();
+        for (entry, tracker) in entries.iter().zip(trackers.iter_mut()) {
+            if let Ok(project) = &entry.result {
+                let transitions = tracker.update_with(project, |id| {
+                    gitlabbing::get_project_pipeline(project, id, conf)
+                });
+                notifications.dispatch(&transitions);
+                hooks.run(&transitions);
+                if !transitions.is_empty() {
+                    scheduler.touch(Instant::now());
+                }
+            }
+        }
+        hooks.failures(); // only logged
+        scheduler.set_active(
+            entries
+                .iter()
+                .any(|e| e.result.as_ref().is_ok_and(has_active)),
+        );
+        draw(&entries, &state, conf);
+
+        let mut pressed = false;
+        let interval = scheduler.interval(Instant::now());
+        let keep_running = keys::wait_for_keys(interval, running, |key| {
+            pressed = true;
+            let action = state.handle_key(key, entries.len());
+            if action == Action::Redraw {
+                draw(&entries, &state, conf);
+            }
+            action
+        });
+        if !keep_running {
+            break;
+        }
+        let now = Instant::now();
+        scheduler.set_focused(keys::is_focused(), now);
+        if pressed {
+            scheduler.touch(now);
+        }
+    }
+
+    keys::leave_interactive();
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crossterm::event::KeyModifiers;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn test_handle_key() {
+        let mut state = DashboardState::default();
+        assert_eq!(state.handle_key(key(KeyCode::Up), 3), Action::Redraw);
+        assert_eq!(state.selected, 0);
+        state.handle_key(key(KeyCode::Char('j')), 3);
+        state.handle_key(key(KeyCode::Down), 3);
+        state.handle_key(key(KeyCode::Down), 3);
+        assert_eq!(state.selected, 2);
+
+        state.handle_key(key(KeyCode::Enter), 3);
+        assert!(state.expanded);
+        // The selection does not move behind the expanded view
+        assert_eq!(state.handle_key(key(KeyCode::Up), 3), Action::Nothing);
+        state.handle_key(key(KeyCode::Esc), 3);
+        assert_eq!(
+            state,
+            DashboardState {
+                selected: 2,
+                expanded: false
+            }
+        );
+
+        assert_eq!(state.handle_key(key(KeyCode::Char('q')), 3), Action::Quit);
+        assert_eq!(
+            state.handle_key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL), 3),
+            Action::Quit
+        );
+    }
+
+    #[test]
+    fn test_expand_home() {
+        let home = env::var("HOME").unwrap();
+        assert_eq!(expand_home("~/src/gpv"), Path::new(&home).join("src/gpv"));
+        assert_eq!(expand_home("/src/gpv"), PathBuf::from("/src/gpv"));
+    }
+}
@@ -0,0 +1,164 @@
+/*
+Deployments of the watched pipelines and review apps of the branch.
+
+GitLab cannot list deployments by pipeline, so the latest deployments
+of the project are fetched and matched by the pipeline of their job,
+or by ref for review apps deployed from other pipelines of the branch.
+*/
+use chrono::{DateTime, Utc};
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+pub enum EnvironmentAction {
+    /// Stop an environment by running its on_stop job
+    Stop { name: String },
+    /// Deploy the previous successful deployment again
+    Rollback { name: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DeploymentUser {
+    pub username: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DeploymentEnvironment {
+    pub id: u64,
+    pub name: String,
+    pub external_url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DeployablePipeline {
+    pub id: u64,
+}
+
+/// The job which deployed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Deployable {
+    pub id: u64,
+    pub name: String,
+    pub pipeline: Option<DeployablePipeline>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Deployment {
+    pub id: u64,
+    pub status: String, // created, running, success, failed, canceled, skipped or blocked
+    #[serde(rename = "ref")]
+    pub ref_: String,
+    pub sha: String,
+    pub created_at: Option<DateTime<Utc>>,
+    pub user: Option<DeploymentUser>,
+    pub environment: DeploymentEnvironment,
+    pub deployable: Option<Deployable>,
+}
+
+impl Deployment {
+    fn pipeline(&self) -> Option<u64> {
+        self.deployable
+            .as_ref()
+            .and_then(|d| d.pipeline.as_ref())
+            .map(|p| p.id)
+    }
+}
+
+/// The latest deployment per environment, out of `deployments` (newest first),
+/// made by one of `pipelines` or from `ref_`.
+pub fn relevant_deployments(
+    deployments: Vec<Deployment>,
+    pipelines: &[u64],
+    ref_: Option<&str>,
+) -> Vec<Deployment> {
+    let mut result: Vec<Deployment> = Vec::new();
+    for deployment in deployments {
+        let by_pipeline = deployment
+            .pipeline()
+            .is_some_and(|p| pipelines.contains(&p));
+        let by_ref = ref_ == Some(deployment.ref_.as_str());
+        if !(by_pipeline || by_ref)
+            || result
+                .iter()
+                .any(|d| d.environment.id == deployment.environment.id)
+        {
+            continue;
+        }
+        result.push(deployment);
+    }
+    result
+}
+
+/// What a rollback deploys again: the latest successful
+/// deployment before `latest`
+pub fn rollback_target<'a>(
+    latest: &Deployment,
+    successful: &'a [Deployment],
+) -> Option<&'a Deployment> {
+    successful.iter().find(|d| d.id < latest.id)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn deployment(id: u64, environment: u64, pipeline: u64, ref_: &str) -> Deployment {
+        Deployment {
+            id,
+            status: "success".to_string(),
+            ref_: ref_.to_string(),
+            sha: "0ab8f3c2e4d5".to_string(),
+            created_at: None,
+            user: None,
+            environment: DeploymentEnvironment {
+                id: environment,
+                name: format!("env-{}", environment),
+                external_url: None,
+            },
+            deployable: Some(Deployable {
+                id: id * 10,
+                name: "deploy".to_string(),
+                pipeline: Some(DeployablePipeline { id: pipeline }),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_relevant_deployments() {
+        let deployments = vec![
+            deployment(6, 1, 300, "main"),
+            deployment(5, 2, 200, "feature"),
+            deployment(4, 1, 200, "feature"),
+            deployment(3, 3, 100, "feature"),
+            deployment(2, 2, 100, "feature"),
+            deployment(1, 4, 100, "other"),
+        ];
+        let ids = |deployments: Vec<Deployment>| -> Vec<u64> {
+            deployments.iter().map(|d| d.id).collect()
+        };
+        assert_eq!(
+            ids(relevant_deployments(deployments.clone(), &[200], None)),
+            vec![5, 4]
+        );
+        // Review apps deployed by older pipelines of the branch
+        assert_eq!(
+            ids(relevant_deployments(deployments, &[200], Some("feature"))),
+            vec![5, 4, 3]
+        );
+    }
+
+    #[test]
+    fn test_rollback_target() {
+        let successful = vec![deployment(5, 1, 200, "main"), deployment(3, 1, 100, "main")];
+        assert_eq!(
+            rollback_target(&successful[0], &successful).map(|d| d.id),
+            Some(3)
+        );
+        let failed = Deployment {
+            status: "failed".to_string(),
+            ..deployment(7, 1, 300, "main")
+        };
+        assert_eq!(rollback_target(&failed, &successful).map(|d| d.id), Some(5));
+        assert_eq!(rollback_target(&successful[1], &successful), None);
+    }
+}
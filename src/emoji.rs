@@ -1,35 +1,126 @@
 use unicode_segmentation::UnicodeSegmentation;
 
-pub const PAUSE: &'static str = "⏸";
-pub const PLAY: &'static str = "▶️";
-pub const FAILED: &'static str = "❌";
-pub const GREEN_CHECK: &'static str = "✅";
-pub const STOP: &'static str = "⏹";
-pub const PAUSE_TOGGLE: &'static str = "⏯";
-pub const GREY_EXCLAMATION: &'static str = "❕";
-pub const _GREY_QUESTION_MARK: &'static str = "❔";
-pub const FAST_FORWARD: &'static str = "⏩";
-pub const ALARM: &'static str = "⏰";
+pub const PAUSE: &str = "⏸";
+pub const PLAY: &str = "▶️";
+pub const FAILED: &str = "❌";
+pub const GREEN_CHECK: &str = "✅";
+pub const STOP: &str = "⏹";
+pub const PAUSE_TOGGLE: &str = "⏯";
+pub const GREY_EXCLAMATION: &str = "❕";
+pub const _GREY_QUESTION_MARK: &str = "❔";
+pub const FAST_FORWARD: &str = "⏩";
+pub const ALARM: &str = "⏰";
+pub const NO_ENTRY: &str = "⛔";
+pub const HOURGLASS: &str = "⌛";
+pub const HOURGLASS_FLOWING: &str = "⏳";
+pub const RED_EXCLAMATION: &str = "❗";
 
 pub trait EmojiLength {
     fn emoji_len(&self) -> usize;
     fn emoji_truncate(&self, n: usize) -> String;
 }
 
+fn grapheme_width(c: &str) -> usize {
+    // box drawing and block elements share the first byte with emojis
+    if ('\u{2500}'..='\u{259f}').contains(&c.chars().next().unwrap()) {
+        return 1;
+    }
+    // emojis are rendered with width 2
+    if c.as_bytes()[0] == PAUSE.as_bytes()[0] {
+        return 2;
+    }
+    1
+}
+
+/// Splits off terminal escape sequences (OSC hyperlinks, CSI colors),
+/// which take no space on screen. Returns (is_escape, part) pairs.
+fn split_escapes(text: &str) -> Vec<(bool, &str)> {
+    let mut parts = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('\x1b') {
+        if start > 0 {
+            parts.push((false, &rest[..start]));
+        }
+        let sequence = &rest[start..];
+        let end = if sequence.starts_with("\x1b]") {
+            // OSC, terminated by ST or BEL
+            match (sequence.find("\x1b\\"), sequence.find('\x07')) {
+                (Some(st), Some(bel)) if bel < st => bel + 1,
+                (Some(st), _) => st + 2,
+                (None, Some(bel)) => bel + 1,
+                (None, None) => sequence.len(),
+            }
+        } else if let Some(parameters) = sequence.strip_prefix("\x1b[") {
+            // CSI, terminated by a byte in @..~
+            parameters
+                .find(|c: char| ('@'..='~').contains(&c))
+                .map(|i| i + 3)
+                .unwrap_or(sequence.len())
+        } else {
+            1
+        };
+        parts.push((true, &sequence[..end]));
+        rest = &sequence[end..];
+    }
+    if !rest.is_empty() {
+        parts.push((false, rest));
+    }
+    parts
+}
+
 impl EmojiLength for String {
     fn emoji_len(&self) -> usize {
-        self.graphemes(true)
-            .map(|c| {
-                // emojis are rendered with width 2
-                if c.as_bytes()[0] == PAUSE.as_bytes()[0] {
-                    return 2;
-                }
-                1
-            })
+        split_escapes(self)
+            .into_iter()
+            .filter(|(escape, _)| !escape)
+            .flat_map(|(_, text)| text.graphemes(true))
+            .map(grapheme_width)
             .sum()
     }
 
+    /// Keeps at most `n` columns. Escape sequences are always kept,
+    /// so a hyperlink cut in the middle is still closed.
     fn emoji_truncate(&self, n: usize) -> String {
-        self.graphemes(true).take(n).collect()
+        let mut result = String::new();
+        let mut width = 0;
+        for (escape, part) in split_escapes(self) {
+            if escape {
+                result += part;
+                continue;
+            }
+            for c in part.graphemes(true) {
+                width += grapheme_width(c);
+                if width > n {
+                    break;
+                }
+                result += c;
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_emoji_len() {
+        assert_eq!("abc".to_string().emoji_len(), 3);
+        assert_eq!(format!("{} ok", GREEN_CHECK).emoji_len(), 5);
+        let link = "\x1b]8;;https://gitlab.com\x1b\\gitlab\x1b]8;;\x1b\\".to_string();
+        assert_eq!(link.emoji_len(), 6);
+        assert_eq!("\x1b[31mred\x1b[0m".to_string().emoji_len(), 3);
+        assert_eq!("▁▄█".to_string().emoji_len(), 3);
+    }
+
+    #[test]
+    fn test_emoji_truncate() {
+        assert_eq!(format!("{}{}", FAILED, FAILED).emoji_truncate(3), FAILED);
+        let link = "\x1b]8;;https://gitlab.com\x1b\\gitlab\x1b]8;;\x1b\\".to_string();
+        assert_eq!(
+            link.emoji_truncate(3),
+            "\x1b]8;;https://gitlab.com\x1b\\git\x1b]8;;\x1b\\"
+        );
     }
 }
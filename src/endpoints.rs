@@ -0,0 +1,134 @@
+/*
+Endpoints which are not (yet) provided by the gitlab crate.
+
+They implement the crate's `Endpoint` trait, so they can be
+queried exactly like the builtin ones.
+*/
+use gitlab::api::endpoint_prelude::*;
+
+/// Online runners available to a project, optionally
+/// filtered by the tags a runner must have.
+pub struct ProjectOnlineRunners {
+    pub project: u64,
+    pub tag_list: Vec<String>,
+}
+
+impl Endpoint for ProjectOnlineRunners {
+    fn method(&self) -> Method {
+        Method::GET
+    }
+
+    fn endpoint(&self) -> Cow<'static, str> {
+        format!("projects/{}/runners", self.project).into()
+    }
+
+    fn parameters(&self) -> QueryParams<'_> {
+        let mut params = QueryParams::default();
+        params.push("status", "online");
+        if !self.tag_list.is_empty() {
+            params.push("tag_list", self.tag_list.join(","));
+        }
+        params
+    }
+}
+
+/// Test counts of a pipeline, per test suite and in total.
+pub struct PipelineTestReportSummary {
+    pub project: u64,
+    pub pipeline: u64,
+}
+
+impl Endpoint for PipelineTestReportSummary {
+    fn method(&self) -> Method {
+        Method::GET
+    }
+
+    fn endpoint(&self) -> Cow<'static, str> {
+        format!(
+            "projects/{}/pipelines/{}/test_report_summary",
+            self.project, self.pipeline
+        )
+        .into()
+    }
+}
+
+/// All test cases of a pipeline, parsed from JUnit reports.
+pub struct PipelineTestReport {
+    pub project: u64,
+    pub pipeline: u64,
+}
+
+impl Endpoint for PipelineTestReport {
+    fn method(&self) -> Method {
+        Method::GET
+    }
+
+    fn endpoint(&self) -> Cow<'static, str> {
+        format!(
+            "projects/{}/pipelines/{}/test_report",
+            self.project, self.pipeline
+        )
+        .into()
+    }
+}
+
+/// Deployments of a project, newest first.
+pub struct ProjectDeployments {
+    pub project: u64,
+    pub environment: Option<String>,
+    pub status: Option<String>,
+}
+
+impl Endpoint for ProjectDeployments {
+    fn method(&self) -> Method {
+        Method::GET
+    }
+
+    fn endpoint(&self) -> Cow<'static, str> {
+        format!("projects/{}/deployments", self.project).into()
+    }
+
+    fn parameters(&self) -> QueryParams<'_> {
+        let mut params = QueryParams::default();
+        params.push("order_by", "id");
+        params.push("sort", "desc");
+        params.push_opt("environment", self.environment.as_ref());
+        params.push_opt("status", self.status.as_ref());
+        params
+    }
+}
+
+impl Pageable for ProjectDeployments {}
+
+/// Runs the on_stop job of an environment.
+pub struct StopEnvironment {
+    pub project: u64,
+    pub environment: u64,
+}
+
+impl Endpoint for StopEnvironment {
+    fn method(&self) -> Method {
+        Method::POST
+    }
+
+    fn endpoint(&self) -> Cow<'static, str> {
+        format!(
+            "projects/{}/environments/{}/stop",
+            self.project, self.environment
+        )
+        .into()
+    }
+}
+
+/// The personal access token used for the request.
+pub struct PersonalAccessTokenSelf;
+
+impl Endpoint for PersonalAccessTokenSelf {
+    fn method(&self) -> Method {
+        Method::GET
+    }
+
+    fn endpoint(&self) -> Cow<'static, str> {
+        "personal_access_tokens/self".into()
+    }
+}
@@ -0,0 +1,42 @@
+/*
+Recorded GitLab API responses, shared by the tests.
+*/
+use crate::gitlabbing::{GitlabProjectPipelines, PipelineJob};
+use gitlab::{Pipeline, StatusState};
+
+pub fn pipeline() -> Pipeline {
+    serde_json::from_str(include_str!("../tests/fixtures/pipeline.json")).unwrap()
+}
+
+pub fn jobs() -> Vec<PipelineJob> {
+    serde_json::from_str(include_str!("../tests/fixtures/jobs.json")).unwrap()
+}
+
+pub fn pipeline_with_status(id: u64, status: StatusState) -> Pipeline {
+    let mut pipeline = pipeline();
+    pipeline.id = gitlab::PipelineId::new(id);
+    pipeline.status = status;
+    pipeline
+}
+
+pub fn job_with_status(id: u64, name: &str, stage: &str, status: StatusState) -> PipelineJob {
+    let mut job = jobs().remove(0);
+    job.job.id = gitlab::JobId::new(id);
+    job.job.name = name.to_string();
+    job.job.stage = stage.to_string();
+    job.job.status = status;
+    job
+}
+
+pub fn project_pipelines(pipelines: Vec<(Pipeline, Vec<PipelineJob>)>) -> GitlabProjectPipelines {
+    GitlabProjectPipelines {
+        project_id: 36813125,
+        name: "gitlab-pipeline-viewer".to_string(),
+        web_url: "https://gitlab.com/julianbuettner/gitlab-pipeline-viewer".to_string(),
+        description: Some("View your GitLab pipelines from within your terminal".to_string()),
+        pipelines,
+        history: None,
+        deployments: vec![],
+        deployments_error: None,
+    }
+}
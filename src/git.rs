@@ -1,8 +1,12 @@
 use super::config::Configuration;
 use git2::Repository;
+use std::fs;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug)]
+#[allow(dead_code)]
 pub struct RepositoryDetails {
+    pub path: PathBuf, // working directory, or .git dir for bare repos
     pub origin: String,
     pub branch_or_ref: String, // "HEAD" if tag is checked out
     pub commit: String,
@@ -10,35 +14,38 @@ pub struct RepositoryDetails {
     pub tag: Option<String>,
 }
 
-pub fn get_local_repository(conf: &Configuration) -> Result<RepositoryDetails, String> {
-    let repo = Repository::discover("./");
+pub fn get_repository_at(path: &Path, conf: &Configuration) -> Result<RepositoryDetails, String> {
+    let repo = Repository::discover(path);
     if repo.is_err() {
-        return Err(format!(
-            "No Git repository found in current working directory or above"
-        ));
+        if path == Path::new("./") {
+            return Err(
+                "No Git repository found in current working directory or above".to_string(),
+            );
+        }
+        return Err(format!("No Git repository found in {}", path.display()));
     }
     let repo = repo.unwrap();
     let head = repo.head();
     if head.is_err() {
-        return Err(format!("No Git head found in current git project"));
+        return Err("No Git head found in current git project".to_string());
     }
     let head = head.unwrap();
     if head.shorthand().is_none() {
-        return Err(format!("Could not get branch of current git project"));
+        return Err("Could not get branch of current git project".to_string());
     }
     if head.target().is_none() {
-        return Err(format!("Could not get HEAD of current git project"));
+        return Err("Could not get HEAD of current git project".to_string());
     }
     let oid = head.target().unwrap();
     let commit = repo.find_commit(oid);
     if commit.is_err() {
-        return Err(format!("Could somehow not find commit of current HEAD "));
+        return Err("Could somehow not find commit of current HEAD ".to_string());
     }
 
     let remote_name = conf.remote.clone().unwrap_or("origin".to_string());
     let remote = repo.find_remote(&remote_name);
     if remote.is_err() {
-        return Err(format!("Could not get remote \"origin\""));
+        return Err(format!("Could not get remote \"{}\"", remote_name));
     }
     let remote_origin = remote.unwrap();
 
@@ -49,6 +56,7 @@ pub fn get_local_repository(conf: &Configuration) -> Result<RepositoryDetails, S
     };
 
     let ok = Ok(RepositoryDetails {
+        path: repo.workdir().unwrap_or_else(|| repo.path()).to_path_buf(),
         origin: remote_origin.url().unwrap().to_string().clone(),
         branch_or_ref: head.shorthand().unwrap().to_string(),
         commit: oid.to_string(),
@@ -57,3 +65,31 @@ pub fn get_local_repository(conf: &Configuration) -> Result<RepositoryDetails, S
     });
     ok
 }
+
+/// Where HEAD lives, and refs and packed-refs,
+/// which differ for linked worktrees
+pub fn git_dirs(path: &Path) -> Result<(PathBuf, PathBuf), String> {
+    let repo = Repository::discover(path)
+        .map_err(|_| format!("No Git repository found in {}", path.display()))?;
+    let git_dir = repo.path().to_path_buf();
+    let common_dir = match fs::read_to_string(git_dir.join("commondir")) {
+        Ok(common_dir) => git_dir.join(common_dir.trim()),
+        Err(_) => git_dir.clone(),
+    };
+    Ok((git_dir, common_dir))
+}
+
+/// The working directory and checked out branch, without
+/// looking at remotes or commits. Cheap enough for shell prompts.
+pub fn get_local_branch() -> Result<(PathBuf, String), String> {
+    let repo = Repository::discover("./")
+        .map_err(|_| "No Git repository found in current working directory or above".to_string())?;
+    let head = repo
+        .head()
+        .map_err(|_| "No Git head found in current git project".to_string())?;
+    let branch = head
+        .shorthand()
+        .ok_or_else(|| "Could not get branch of current git project".to_string())?;
+    let path = repo.workdir().unwrap_or_else(|| repo.path()).to_path_buf();
+    Ok((path, branch.to_string()))
+}
@@ -0,0 +1,151 @@
+/*
+Refreshing right away on checkouts, commits and pushes.
+
+HEAD, refs and packed-refs are watched with inotify (kqueue or FSEvents
+elsewhere). Git writes them as .lock files renamed into place, so their
+directories are watched, not the files. A moved remote tracking branch
+means a push (or a fetch), which the scheduler answers with a burst of
+fast polling until GitLab created the pipeline.
+*/
+use crate::git;
+use crate::schedule::Waker;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GitEvent {
+    Checkout, // HEAD moved
+    Commit,   // a local branch moved
+    Push,     // a remote tracking branch moved
+}
+
+/// What a change of `path` means. HEAD is in `git_dir`, the refs are in
+/// `common_dir`, which is a different one for linked worktrees.
+pub fn classify(path: &Path, git_dir: &Path, common_dir: &Path, remote: &str) -> Option<GitEvent> {
+    if path.extension().is_some_and(|e| e == "lock") {
+        return None;
+    }
+    if path == git_dir.join("HEAD") {
+        return Some(GitEvent::Checkout);
+    }
+    if path == common_dir.join("packed-refs") {
+        return Some(GitEvent::Commit);
+    }
+    let name = path.strip_prefix(common_dir.join("refs")).ok()?;
+    if name.starts_with(Path::new("remotes").join(remote)) {
+        Some(GitEvent::Push)
+    } else if name.starts_with("heads") {
+        Some(GitEvent::Commit)
+    } else {
+        None
+    }
+}
+
+pub struct GitWatcher {
+    _watcher: RecommendedWatcher, // stops watching when dropped
+    events: Receiver<GitEvent>,
+}
+
+impl GitWatcher {
+    /// `waker` is woken on every event
+    pub fn new(repo: &Path, remote: &str, waker: Waker) -> Result<GitWatcher, String> {
+        let (git_dir, common_dir) = git::git_dirs(repo)?;
+        let (sender, events) = mpsc::channel();
+        let remote = remote.to_string();
+        let dirs: (PathBuf, PathBuf) = (git_dir.clone(), common_dir.clone());
+        let failed = |e: notify::Error| format!("Could not watch {}: {}", git_dir.display(), e);
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            // Reading HEAD, as every refresh does, is no change
+            let event = match event {
+                Ok(event) if !matches!(event.kind, EventKind::Access(_)) => event,
+                _ => return,
+            };
+            for path in &event.paths {
+                if let Some(git_event) = classify(path, &dirs.0, &dirs.1, &remote) {
+                    sender.send(git_event).ok();
+                    waker.wake();
+                }
+            }
+        })
+        .map_err(failed)?;
+        watcher
+            .watch(&git_dir, RecursiveMode::NonRecursive)
+            .map_err(failed)?;
+        if common_dir != git_dir {
+            watcher
+                .watch(&common_dir, RecursiveMode::NonRecursive)
+                .map_err(failed)?;
+        }
+        watcher
+            .watch(&common_dir.join("refs"), RecursiveMode::Recursive)
+            .map_err(failed)?;
+        Ok(GitWatcher {
+            _watcher: watcher,
+            events,
+        })
+    }
+
+    /// The events since the last call
+    pub fn events(&self) -> Vec<GitEvent> {
+        self.events.try_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_classify() {
+        let git_dir = Path::new("/src/gpv/.git/worktrees/feature");
+        let common_dir = Path::new("/src/gpv/.git");
+        let classify = |path: &str| classify(Path::new(path), git_dir, common_dir, "origin");
+        assert_eq!(
+            classify("/src/gpv/.git/worktrees/feature/HEAD"),
+            Some(GitEvent::Checkout)
+        );
+        assert_eq!(
+            classify("/src/gpv/.git/refs/heads/feature"),
+            Some(GitEvent::Commit)
+        );
+        assert_eq!(
+            classify("/src/gpv/.git/refs/remotes/origin/feature"),
+            Some(GitEvent::Push)
+        );
+        assert_eq!(
+            classify("/src/gpv/.git/packed-refs"),
+            Some(GitEvent::Commit)
+        );
+        assert_eq!(classify("/src/gpv/.git/refs/heads/feature.lock"), None);
+        assert_eq!(classify("/src/gpv/.git/refs/remotes/upstream/main"), None);
+        assert_eq!(classify("/src/gpv/.git/refs/tags/v1"), None);
+        // HEAD of the main worktree
+        assert_eq!(classify("/src/gpv/.git/HEAD"), None);
+        assert_eq!(classify("/src/gpv/.git/worktrees/feature/index"), None);
+    }
+
+    #[test]
+    fn test_watcher() {
+        let dir = std::env::temp_dir().join(format!("gpv-git-events-{}", std::process::id()));
+        git2::Repository::init(&dir).unwrap();
+        let pushed = dir.join(".git/refs/remotes/origin");
+        std::fs::create_dir_all(&pushed).unwrap();
+        let waker = Waker::default();
+        let watcher = GitWatcher::new(&dir, "origin", waker.clone()).unwrap();
+        assert_eq!(watcher.events(), vec![]);
+
+        std::fs::write(pushed.join("main"), "0ab8f3c2e4d5\n").unwrap();
+        let started = Instant::now();
+        let mut events = Vec::new();
+        while events.is_empty() && started.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(10));
+            events = watcher.events();
+        }
+        assert!(events.contains(&GitEvent::Push));
+        assert!(waker.take());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
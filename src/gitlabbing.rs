@@ -1,18 +1,99 @@
 use super::git::RepositoryDetails;
-use crate::config::Configuration;
-use gitlab::api::{projects, Query};
-use gitlab::{types, Gitlab, Job, Pipeline, PipelineBasic, Project};
+use crate::config::{Backend, Configuration, PipelineSelection};
+use crate::deployments::{relevant_deployments, rollback_target, Deployment};
+use crate::endpoints::{
+    PersonalAccessTokenSelf, PipelineTestReport, PipelineTestReportSummary, ProjectDeployments,
+    ProjectOnlineRunners, StopEnvironment,
+};
+use crate::graphql;
+use crate::history::{belongs_in_history, History, HistoryEntry};
+use crate::test_reports::{counts_by_job, TestCounts, TestReport, TestReportSummary};
+use crate::tokens::{token_for, TokenKind};
+use crate::transitions::is_finished;
+use chrono::{DateTime, Utc};
+use gitlab::api::common::SortOrder;
+use gitlab::api::projects::pipelines::{PipelineOrderBy, PipelineStatus};
+use gitlab::api::{self, groups, paged, projects, users, Pagination, Query};
+use gitlab::{types, Gitlab, GitlabBuilder, Job, Pipeline, PipelineBasic, Project, StatusState};
 use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct GitlabProjectPipelines {
     pub project_id: u64,
     pub name: String,
     pub web_url: String,
     pub description: Option<String>,
-    pub pipelines: Vec<(Pipeline, Vec<Job>)>,
+    pub pipelines: Vec<(Pipeline, Vec<PipelineJob>)>,
+    #[serde(default)]
+    pub history: Option<History>,
+    #[serde(default)]
+    pub deployments: Vec<Deployment>,
+    #[serde(default)]
+    pub deployments_error: Option<String>,
 }
 
-fn parse_origin(origin: &String) -> Option<(String, String)> {
+/// A job as returned by the pipeline jobs endpoint, including
+/// the fields the gitlab crate does not know about.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PipelineJob {
+    #[serde(flatten)]
+    pub job: Job,
+    #[serde(default)]
+    pub tag_list: Vec<String>,
+    pub queued_duration: Option<f64>,
+    /// Whether an online runner can pick up this job.
+    /// Only checked for queued jobs, None if unknown.
+    #[serde(default)]
+    pub runner_available: Option<bool>,
+    /// Counts of the JUnit report, None if the job has none.
+    #[serde(default)]
+    pub test_counts: Option<TestCounts>,
+}
+
+impl GitlabProjectPipelines {
+    /// The latest attempt of a job of the latest pipeline
+    pub fn find_job(&self, name: &str) -> Result<&PipelineJob, String> {
+        let (_, jobs) = self
+            .pipelines
+            .first()
+            .ok_or_else(|| "There is no pipeline".to_string())?;
+        jobs.iter()
+            .filter(|j| j.job.name == name)
+            .max_by_key(|j| j.job.id.value())
+            .ok_or_else(|| format!("No job {} in the latest pipeline", name))
+    }
+}
+
+impl PipelineJob {
+    pub fn is_queued(&self) -> bool {
+        matches!(
+            self.job.status,
+            StatusState::WaitingForResource | StatusState::Preparing | StatusState::Pending
+        )
+    }
+}
+
+#[derive(Deserialize)]
+struct RunnerId {
+    #[allow(dead_code)]
+    id: u64,
+}
+
+/// Check whether any online runner of the project has all the given tags.
+/// Listing runners requires maintainer access, so None is returned
+/// if we are not allowed to know.
+fn online_runner_available(client: &Gitlab, project: u64, tag_list: &[String]) -> Option<bool> {
+    let runners_call = ProjectOnlineRunners {
+        project,
+        tag_list: tag_list.to_vec(),
+    };
+    let runners: Result<Vec<RunnerId>, _> = runners_call.query(client);
+    runners.ok().map(|r| !r.is_empty())
+}
+
+fn parse_origin(origin: &str) -> Option<(String, String)> {
     let origin_match_ssh =
         Regex::new(r"git@(?P<domain>[a-zA-Z0-9\.]+):(?P<path>.*)\.git$").unwrap();
     let origin_match_http =
@@ -21,101 +102,517 @@ fn parse_origin(origin: &String) -> Option<(String, String)> {
         None => (),
         Some(r) => return Some((r["domain"].to_string(), r["path"].to_string())),
     };
-    match origin_match_http.captures(origin) {
-        None => None,
-        Some(r) => Some((r["domain"].to_string(), r["path"].to_string())),
-    }
+    origin_match_http
+        .captures(origin)
+        .map(|r| (r["domain"].to_string(), r["path"].to_string()))
 }
 
-pub fn get_gitlab_pipelines(
-    repo: &RepositoryDetails,
-    conf: &Configuration,
-) -> Result<GitlabProjectPipelines, String> {
-    let domain_path = parse_origin(&repo.origin);
-    if domain_path.is_none() {
-        return Err("Could not parse remote origin".to_string());
+#[cfg(not(test))]
+fn builder(domain: &str, token: String) -> GitlabBuilder {
+    Gitlab::builder(domain, token)
+}
+
+/// The mock GitLab of the tests has no TLS
+#[cfg(test)]
+fn builder(domain: &str, token: String) -> GitlabBuilder {
+    let mut builder = Gitlab::builder(domain, token);
+    if domain.starts_with("127.0.0.1:") {
+        builder.insecure();
     }
-    let (domain, path) = domain_path.unwrap();
+    builder
+}
 
-    let token = conf.gitlab_token_mapping.get(&domain);
-    if token.is_none() {
+pub fn gitlab_client(domain: &str, conf: &Configuration) -> Result<Gitlab, String> {
+    let token = token_for(domain, conf)?;
+    // The gitlab crate only sends PRIVATE-TOKEN or bearer tokens, and job tokens
+    // cannot read the user it checks the connection with anyway
+    if token.kind == TokenKind::Job {
         return Err(format!(
-            "No token for origin \"{}\" found in config (\"gitlab-tokens\")",
+            "CI_JOB_TOKEN only reaches job artifacts and environments, not the projects, \
+            pipelines, jobs and GraphQL API of {} gpv reads. Set GITLAB_TOKEN \
+            (and GITLAB_HOST) to a token with the read_api scope",
             domain
         ));
     }
-    let client_result = Gitlab::new(domain.clone(), token.unwrap());
-    if client_result.is_err() {
-        return Err(format!("Token or GitLab host {} invalid", domain));
+    builder(domain, token.value)
+        .build()
+        .map_err(|_| format!("Token or GitLab host {} invalid", domain))
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct TokenDetails {
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<String>,
+}
+
+/// Who a token belongs to, and its scopes if it is a personal access token
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenInfo {
+    pub username: String,
+    pub details: Option<TokenDetails>,
+}
+
+pub fn check_token(client: &Gitlab) -> Result<TokenInfo, String> {
+    let me: Username = users::CurrentUser::builder()
+        .build()
+        .unwrap()
+        .query(client)
+        .map_err(|e| format!("The token is not accepted: {}", e))?;
+    let details = PersonalAccessTokenSelf.query(client).ok();
+    Ok(TokenInfo {
+        username: me.username,
+        details,
+    })
+}
+
+/// What whoever refreshes keeps between fetches. Finished pipelines
+/// never change, so history entries are kept by host and pipeline ID.
+/// Deployments by host and project, until a job of the pipelines changes.
+#[derive(Default)]
+pub struct FetchCache {
+    history: HashMap<(String, u64), HistoryEntry>,
+    deployments: HashMap<(String, u64), CachedDeployments>,
+}
+
+impl FetchCache {
+    pub fn new() -> Self {
+        Self::default()
     }
-    let client = client_result.unwrap();
+}
+
+struct CachedDeployments {
+    jobs: Vec<(u64, StatusState)>,
+    fetched: std::time::Instant,
+    deployments: Vec<Deployment>,
+}
+
+/// The last `count` finished pipelines out of `pipelines`, oldest first
+fn history_entries(
+    client: &Gitlab,
+    host: &str,
+    project: u64,
+    pipelines: &[PipelineBasic],
+    count: usize,
+    cache: &mut FetchCache,
+) -> Vec<HistoryEntry> {
+    let mut entries = Vec::new();
+    for pipeline in pipelines
+        .iter()
+        .filter(|p| belongs_in_history(p.status))
+        .take(count)
+    {
+        let key = (host.to_string(), pipeline.id.value());
+        if let Some(entry) = cache.history.get(&key) {
+            entries.push(entry.clone());
+            continue;
+        }
+        let details: Result<Pipeline, _> = projects::pipelines::Pipeline::builder()
+            .project(project)
+            .pipeline(pipeline.id.value())
+            .build()
+            .unwrap()
+            .query(client);
+        if let Ok(details) = details {
+            let entry = HistoryEntry::from_pipeline(&details);
+            cache.history.insert(key, entry.clone());
+            entries.push(entry);
+        }
+    }
+    entries.reverse();
+    entries
+}
 
-    let project_call = projects::Project::builder()
-        .project(path.clone())
+fn ref_pipelines(client: &Gitlab, project: u64, ref_: &str) -> Result<Vec<PipelineBasic>, String> {
+    projects::pipelines::Pipelines::builder()
+        .project(project)
+        .ref_(ref_)
         .build()
-        .unwrap();
-    let project_result = project_call.query(&client);
-    if project_result.is_err() {
-        return Err(format!("Could not find {} on {}", path, domain));
+        .unwrap()
+        .query(client)
+        .map_err(|_| format!("Could not get pipelines for {}", ref_))
+}
+
+const RECENT_DEPLOYMENTS: usize = 50;
+/// Stopping an environment from the web runs no watched job, so look again now and then
+const DEPLOYMENTS_TTL: std::time::Duration = std::time::Duration::from_secs(300);
+
+/// Deployments of the pipelines and review apps of the ref,
+/// fetched again only once a job of the pipelines changed.
+/// Without access to deployments a project has none.
+fn get_deployments(
+    client: &Gitlab,
+    host: &str,
+    project: &GitlabProjectPipelines,
+    cache: &mut FetchCache,
+) -> Result<Vec<Deployment>, String> {
+    let jobs: Vec<(u64, StatusState)> = project
+        .pipelines
+        .iter()
+        .flat_map(|(_, jobs)| jobs.iter().map(|j| (j.job.id.value(), j.job.status)))
+        .collect();
+    let key = (host.to_string(), project.project_id);
+    if let Some(cached) = cache.deployments.get(&key) {
+        if cached.jobs == jobs && cached.fetched.elapsed() < DEPLOYMENTS_TTL {
+            return Ok(cached.deployments.clone());
+        }
     }
 
-    let project: Project = project_result.unwrap();
-    let pipeline_call = projects::pipelines::Pipelines::builder()
+    let call = ProjectDeployments {
+        project: project.project_id,
+        environment: None,
+        status: None,
+    };
+    let deployments: Vec<Deployment> =
+        match paged(call, Pagination::Limit(RECENT_DEPLOYMENTS)).query(client) {
+            Ok(deployments) => deployments,
+            Err(api::ApiError::Gitlab { msg }) if msg.starts_with("403") => Vec::new(),
+            Err(e) => return Err(format!("Could not get deployments: {}", e)),
+        };
+    let pipelines: Vec<u64> = project
+        .pipelines
+        .iter()
+        .map(|(p, _)| p.id.value())
+        .collect();
+    let ref_ = project.pipelines.first().and_then(|(p, _)| p.ref_.clone());
+    let deployments = relevant_deployments(deployments, &pipelines, ref_.as_deref());
+    cache.deployments.insert(
+        key,
+        CachedDeployments {
+            jobs,
+            fetched: std::time::Instant::now(),
+            deployments: deployments.clone(),
+        },
+    );
+    Ok(deployments)
+}
+
+/// The latest deployments to an environment with `status`, newest first
+fn environment_deployments(
+    client: &Gitlab,
+    project: u64,
+    environment: &str,
+    status: Option<&str>,
+    count: usize,
+) -> Result<Vec<Deployment>, String> {
+    let call = ProjectDeployments {
+        project,
+        environment: Some(environment.to_string()),
+        status: status.map(|s| s.to_string()),
+    };
+    paged(call, Pagination::Limit(count))
+        .query(client)
+        .map_err(|_| format!("Could not get deployments of {}", environment))
+}
+
+pub fn stop_environment(target: &Target, conf: &Configuration, name: &str) -> Result<(), String> {
+    let client = gitlab_client(&target.host, conf)?;
+    let project = get_project(&client, target)?;
+    let latest = environment_deployments(&client, project.id.value(), name, None, 1)?;
+    let environment = latest
+        .first()
+        .ok_or_else(|| format!("No deployments to {}", name))?
+        .environment
+        .id;
+    let call = StopEnvironment {
+        project: project.id.value(),
+        environment,
+    };
+    api::ignore(call)
+        .query(&client)
+        .map_err(|e| format!("Could not stop {}: {}", name, e))
+}
+
+/// Retries the job of the previous successful deployment,
+/// returns the deployment which is deployed again
+pub fn rollback_environment(
+    target: &Target,
+    conf: &Configuration,
+    name: &str,
+) -> Result<Deployment, String> {
+    let client = gitlab_client(&target.host, conf)?;
+    let project = get_project(&client, target)?;
+    let latest = environment_deployments(&client, project.id.value(), name, None, 1)?;
+    let latest = latest
+        .first()
+        .ok_or_else(|| format!("No deployments to {}", name))?;
+    let successful =
+        environment_deployments(&client, project.id.value(), name, Some("success"), 10)?;
+    let previous = rollback_target(latest, &successful)
+        .ok_or_else(|| format!("No earlier successful deployment to {}", name))?;
+    let job = previous
+        .deployable
+        .as_ref()
+        .ok_or_else(|| format!("Deployment {} has no job to retry", previous.id))?;
+    let call = projects::jobs::RetryJob::builder()
         .project(project.id.value())
-        .ref_(repo.branch_or_ref.clone())
+        .job(job.id)
         .build()
         .unwrap();
+    api::ignore(call)
+        .query(&client)
+        .map_err(|e| format!("Could not retry {}: {}", job.name, e))?;
+    Ok(previous.clone())
+}
 
-    let pipelines_result: Result<Vec<PipelineBasic>, _> = pipeline_call.query(&client);
-    if pipelines_result.is_err() {
-        return Err(format!(
-            "Could not get pipelines for {} ({})",
-            path, repo.branch_or_ref
-        ));
-    }
-    let pipelines = pipelines_result.unwrap();
+/// The latest pipeline, followed by up to `count` others picked by `selection`
+pub fn select_pipelines(
+    pipelines: &[PipelineBasic],
+    selection: PipelineSelection,
+    count: usize,
+) -> Vec<u64> {
+    let others = pipelines.iter().skip(1).filter(|p| match selection {
+        PipelineSelection::Running => p.status == types::StatusState::Running,
+        PipelineSelection::Recent => true,
+        PipelineSelection::Latest => false,
+    });
+    pipelines
+        .iter()
+        .take(1)
+        .chain(others.take(count))
+        .map(|p| p.id.value())
+        .collect()
+}
 
-    let mut pipelines_to_query = Vec::new();
-    match pipelines.get(0) {
+/// What to watch: a project on a host, and optionally
+/// a ref (defaults to the default branch) or a single pipeline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub host: String,
+    pub project: String, // path with namespace
+    pub ref_: Option<String>,
+    pub pipeline: Option<u64>,
+}
+
+pub fn repository_target(repo: &RepositoryDetails) -> Result<Target, String> {
+    let (host, project) = parse_origin(&repo.origin).ok_or("Could not parse remote origin")?;
+    Ok(Target {
+        host,
+        project,
+        ref_: Some(repo.branch_or_ref.clone()),
+        pipeline: None,
+    })
+}
+
+/// Understands project, pipeline (including its tabs), tree and commits
+/// URLs as copied from the browser. Other pages of a project are rejected.
+pub fn parse_gitlab_url(url: &str) -> Result<Target, String> {
+    let url_match = Regex::new(
+        r"^https?://(?P<host>[^/]+)/(?P<project>[^?#]+?)(\.git)?(/-/(?P<kind>[^/?#]+)(/(?P<rest>[^?#]*?))?)?/?([?#].*)?$",
+    )
+    .unwrap();
+    let captures = url_match
+        .captures(url)
+        .ok_or_else(|| format!("{} is not a GitLab URL", url))?;
+    let mut target = Target {
+        host: captures["host"].to_string(),
+        project: captures["project"].to_string(),
+        ref_: None,
+        pipeline: None,
+    };
+    let rest = captures.name("rest").map(|r| r.as_str()).unwrap_or("");
+    match captures.name("kind").map(|k| k.as_str()) {
         None => (),
-        Some(v) => pipelines_to_query.push(v),
-    }
-    pipelines_to_query.append(
-        &mut pipelines
-            .iter()
-            .skip(1)
-            .filter(|p| p.status == types::StatusState::Running)
-            .take(5)
-            .collect(),
-    );
-    let mut full_pipelines: Vec<(Pipeline, Vec<Job>)> = Vec::new();
-    for pipeline in pipelines_to_query {
-        let pipeline_query = projects::pipelines::Pipeline::builder()
-            .project(project.id.value())
-            .pipeline(pipeline.id.value())
-            .build()
-            .unwrap();
-        let pipeline_result = pipeline_query.query(&client);
-        if pipeline_result.is_err() {
+        Some("pipelines") if rest.is_empty() => (),
+        Some("pipelines") => {
+            // The tabs /builds, /failures, /test_report and so on
+            let id = rest.split('/').next().unwrap_or_default();
+            target.pipeline = Some(
+                id.parse()
+                    .map_err(|_| format!("{} is not the URL of a pipeline", url))?,
+            );
+        }
+        Some("tree" | "commits") if !rest.is_empty() => target.ref_ = Some(rest.to_string()),
+        Some("jobs") => {
             return Err(format!(
-                "Could not query details of pipeline {}",
-                pipeline.id.value(),
-            ));
+                "{} is a job URL, pass the URL of its pipeline instead",
+                url
+            ))
         }
-        let jobs_query = projects::pipelines::PipelineJobs::builder()
-            .project(project.id.value())
-            .pipeline(pipeline.id.value())
-            .build()
-            .unwrap();
-        let jobs_result = jobs_query.query(&client);
-        if jobs_result.is_err() {
+        Some("merge_requests") => {
             return Err(format!(
-                "Could not query jobs of pipeline {}",
-                pipeline.id.value(),
-            ));
+                "{} is a merge request URL, pass the URL of its pipeline or branch instead",
+                url
+            ))
+        }
+        Some(_) => return Err(format!("{} is not a project, pipeline or branch URL", url)),
+    }
+    Ok(target)
+}
+
+fn get_project(client: &Gitlab, target: &Target) -> Result<Project, String> {
+    projects::Project::builder()
+        .project(target.project.as_str())
+        .build()
+        .unwrap()
+        .query(client)
+        .map_err(|_| format!("Could not find {} on {}", target.project, target.host))
+}
+
+/// The ref of the target, or the default branch of the project
+fn target_ref(target: &Target, project: &Project) -> Result<String, String> {
+    match target.ref_.as_ref().or(project.default_branch.as_ref()) {
+        Some(r) => Ok(r.clone()),
+        None => Err(format!(
+            "{} has no default branch, pass a ref",
+            target.project
+        )),
+    }
+}
+
+pub fn get_gitlab_pipelines(
+    repo: &RepositoryDetails,
+    conf: &Configuration,
+    cache: &mut FetchCache,
+) -> Result<GitlabProjectPipelines, String> {
+    get_target_pipelines(&repository_target(repo)?, conf, cache)
+}
+
+pub fn get_target_pipelines(
+    target: &Target,
+    conf: &Configuration,
+    cache: &mut FetchCache,
+) -> Result<GitlabProjectPipelines, String> {
+    let client = gitlab_client(&target.host, conf)?;
+    let mut project_pipelines = match conf.backend.unwrap_or_default() {
+        Backend::Rest => rest_pipelines(&client, target, conf, cache)?,
+        Backend::Graphql => graphql::get_project_pipelines(&client, target, conf)?,
+    };
+
+    // Neither of them is in the GraphQL API
+    let project = project_pipelines.project_id;
+    let mut runner_availability: HashMap<Vec<String>, Option<bool>> = HashMap::new();
+    for (_, jobs) in project_pipelines.pipelines.iter_mut() {
+        for job in jobs.iter_mut().filter(|j| j.is_queued()) {
+            let available = runner_availability
+                .entry(job.tag_list.clone())
+                .or_insert_with(|| online_runner_available(&client, project, &job.tag_list));
+            job.runner_available = *available;
+        }
+    }
+    match get_deployments(&client, &target.host, &project_pipelines, cache) {
+        Ok(deployments) => project_pipelines.deployments = deployments,
+        Err(e) => project_pipelines.deployments_error = Some(e),
+    }
+    Ok(project_pipelines)
+}
+
+/// How many finished pipelines the history shows
+pub fn history_count(conf: &Configuration) -> usize {
+    conf.history
+        .as_ref()
+        .and_then(|h| h.pipelines)
+        .unwrap_or(10)
+}
+
+fn pipeline_with_jobs(
+    client: &Gitlab,
+    project: u64,
+    pipeline: u64,
+) -> Result<(Pipeline, Vec<PipelineJob>), String> {
+    let pipeline_query = projects::pipelines::Pipeline::builder()
+        .project(project)
+        .pipeline(pipeline)
+        .build()
+        .unwrap();
+    let pipeline_result = pipeline_query.query(client);
+    if pipeline_result.is_err() {
+        return Err(format!("Could not query details of pipeline {}", pipeline,));
+    }
+    let jobs_query = projects::pipelines::PipelineJobs::builder()
+        .project(project)
+        .pipeline(pipeline)
+        .build()
+        .unwrap();
+    let jobs_result = jobs_query.query(client);
+    if jobs_result.is_err() {
+        return Err(format!("Could not query jobs of pipeline {}", pipeline,));
+    }
+    let mut jobs: Vec<PipelineJob> = jobs_result.unwrap();
+    let summary_call = PipelineTestReportSummary { project, pipeline };
+    let summary: Result<TestReportSummary, _> = summary_call.query(client);
+    if let Ok(summary) = summary {
+        let mut counts = counts_by_job(&summary);
+        for job in jobs.iter_mut() {
+            job.test_counts = counts.remove(&job.job.id.value());
+        }
+    }
+    Ok((pipeline_result.unwrap(), jobs))
+}
+
+/// A single pipeline of an already fetched project, e.g. one which
+/// is not selected anymore but still followed until it finishes.
+pub fn get_project_pipeline(
+    project: &GitlabProjectPipelines,
+    pipeline: u64,
+    conf: &Configuration,
+) -> Result<(Pipeline, Vec<PipelineJob>), String> {
+    let target = parse_gitlab_url(&project.web_url)?;
+    let client = gitlab_client(&target.host, conf)?;
+    pipeline_with_jobs(&client, project.project_id, pipeline)
+}
+
+fn rest_pipelines(
+    client: &Gitlab,
+    target: &Target,
+    conf: &Configuration,
+    cache: &mut FetchCache,
+) -> Result<GitlabProjectPipelines, String> {
+    let (domain, path) = (&target.host, &target.project);
+    let project = get_project(client, target)?;
+
+    let mut pipelines_to_query = Vec::new();
+    let mut history = None;
+    if let Some(pipeline) = target.pipeline {
+        pipelines_to_query.push(pipeline);
+    } else {
+        let ref_ = target_ref(target, &project)?;
+        let pipelines = ref_pipelines(client, project.id.value(), &ref_)
+            .map_err(|_| format!("Could not get pipelines for {} ({})", path, ref_))?;
+
+        let history_count = history_count(conf);
+        if history_count > 0 {
+            let target_ref = project.default_branch.clone().filter(|b| *b != ref_);
+            let target_entries = match &target_ref {
+                None => Vec::new(),
+                Some(branch) => ref_pipelines(client, project.id.value(), branch)
+                    .map(|p| {
+                        history_entries(
+                            client,
+                            domain,
+                            project.id.value(),
+                            &p,
+                            history_count,
+                            cache,
+                        )
+                    })
+                    .unwrap_or_default(),
+            };
+            history = Some(History {
+                ref_: ref_.clone(),
+                entries: history_entries(
+                    client,
+                    domain,
+                    project.id.value(),
+                    &pipelines,
+                    history_count,
+                    cache,
+                ),
+                target_ref,
+                target_entries,
+            });
         }
-        let jobs: Vec<Job> = jobs_result.unwrap();
-        full_pipelines.push((pipeline_result.unwrap(), jobs));
+
+        let pipelines_conf = conf.pipelines.clone().unwrap_or_default();
+        pipelines_to_query = select_pipelines(
+            &pipelines,
+            pipelines_conf.selection.unwrap_or_default(),
+            pipelines_conf.count.unwrap_or(5),
+        );
+    }
+    let mut full_pipelines: Vec<(Pipeline, Vec<PipelineJob>)> = Vec::new();
+    for pipeline in pipelines_to_query {
+        full_pipelines.push(pipeline_with_jobs(client, project.id.value(), pipeline)?);
     }
 
     Ok(GitlabProjectPipelines {
@@ -124,24 +621,466 @@ pub fn get_gitlab_pipelines(
         web_url: project.web_url,
         description: project.description,
         pipelines: full_pipelines,
+        history,
+        deployments: Vec::new(),
+        deployments_error: None,
     })
 }
 
+/// A line of the pipeline history list
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PipelineSummary {
+    pub id: u64,
+    pub sha: String,
+    pub status: StatusState,
+    pub source: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub web_url: String,
+    /// Not in the REST list, filled in from one GraphQL query
+    #[serde(default)]
+    pub author: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PipelineList {
+    #[serde(rename = "ref")]
+    pub ref_: String,
+    pub pipelines: Vec<PipelineSummary>, // newest first
+}
+
+/// The last pipelines of the ref of the target
+pub fn get_pipeline_list(target: &Target, conf: &Configuration) -> Result<PipelineList, String> {
+    let client = gitlab_client(&target.host, conf)?;
+    let project = get_project(&client, target)?;
+    let ref_ = target_ref(target, &project)?;
+    let count = conf.pipelines.as_ref().and_then(|p| p.list).unwrap_or(50);
+    let call = projects::pipelines::Pipelines::builder()
+        .project(project.id.value())
+        .ref_(ref_.clone())
+        .build()
+        .unwrap();
+    let mut pipelines: Vec<PipelineSummary> = paged(call, Pagination::Limit(count))
+        .query(&client)
+        .map_err(|_| format!("Could not get pipelines for {} ({})", target.project, ref_))?;
+
+    // Without GraphQL, e.g. on old instances, the author column stays empty
+    if let Ok(mut authors) = graphql::pipeline_authors(&client, &target.project, &ref_, count) {
+        for pipeline in pipelines.iter_mut() {
+            pipeline.author = authors.remove(&pipeline.id);
+        }
+    }
+    Ok(PipelineList { ref_, pipelines })
+}
+
+/// The CI config of the ref of the target, if it lives in the project
+pub fn get_ci_config(target: &Target, conf: &Configuration) -> Result<String, String> {
+    let client = gitlab_client(&target.host, conf)?;
+    let project = get_project(&client, target)?;
+    let ref_ = target_ref(target, &project)?;
+    let path = match project.ci_config_path.filter(|p| !p.is_empty()) {
+        None => ".gitlab-ci.yml".to_string(),
+        Some(p) if p.contains('@') || p.contains("://") => {
+            return Err(format!("The CI config {} is not part of the project", p))
+        }
+        Some(p) => p,
+    };
+    let call = projects::repository::files::FileRaw::builder()
+        .project(project.id.value())
+        .file_path(path.as_str())
+        .ref_(ref_.as_str())
+        .build()
+        .unwrap();
+    let content = api::raw(call)
+        .query(&client)
+        .map_err(|_| format!("Could not read {} of {}", path, ref_))?;
+    String::from_utf8(content).map_err(|_| format!("{} is not valid UTF-8", path))
+}
+
+/// Starts a pipeline for the ref of the target
+pub fn create_pipeline(
+    target: &Target,
+    conf: &Configuration,
+    variables: &[(String, String)],
+) -> Result<PipelineBasic, String> {
+    let client = gitlab_client(&target.host, conf)?;
+    let project = get_project(&client, target)?;
+    let ref_ = target_ref(target, &project)?;
+    let call = projects::pipelines::CreatePipeline::builder()
+        .project(project.id.value())
+        .ref_(ref_.as_str())
+        .variables(variables.iter().map(|(key, value)| {
+            projects::pipelines::PipelineVariable::builder()
+                .key(key.as_str())
+                .value(value.as_str())
+                .build()
+                .unwrap()
+        }))
+        .build()
+        .unwrap();
+    call.query(&client)
+        .map_err(|e| format!("Could not start a pipeline for {}: {}", ref_, e))
+}
+
+#[derive(Deserialize)]
+struct WebUrl {
+    web_url: String,
+}
+
+/// The open merge request with `branch` as source branch
+pub fn get_merge_request_url(
+    host: &str,
+    project_id: u64,
+    branch: &str,
+    conf: &Configuration,
+) -> Result<String, String> {
+    let client = gitlab_client(host, conf)?;
+    let call = projects::merge_requests::MergeRequests::builder()
+        .project(project_id)
+        .source_branch(branch)
+        .state(projects::merge_requests::MergeRequestState::Opened)
+        .build()
+        .unwrap();
+    let merge_requests: Vec<WebUrl> = call
+        .query(&client)
+        .map_err(|_| format!("Could not get merge requests of {}", branch))?;
+    merge_requests
+        .into_iter()
+        .next()
+        .map(|mr| mr.web_url)
+        .ok_or_else(|| format!("No open merge request for {}", branch))
+}
+
+pub fn get_test_report(
+    host: &str,
+    project_id: u64,
+    pipeline: u64,
+    conf: &Configuration,
+) -> Result<TestReport, String> {
+    let client = gitlab_client(host, conf)?;
+    let call = PipelineTestReport {
+        project: project_id,
+        pipeline,
+    };
+    call.query(&client)
+        .map_err(|_| format!("Could not get the test report of pipeline {}", pipeline))
+}
+
+const OVERVIEW_PROJECTS: usize = 50;
+const OVERVIEW_PIPELINES_PER_PROJECT: usize = 10;
+const OVERVIEW_PROJECTS_TTL: std::time::Duration = std::time::Duration::from_secs(600);
+
+/// Which pipelines to show across projects.
+/// At least one of group and username must be given.
+#[derive(Debug, Clone)]
+pub struct OverviewFilter {
+    pub host: String,
+    pub group: Option<String>,
+    pub username: Option<String>, // "me" is the owner of the token
+    pub status: Option<StatusState>,
+    pub since: Option<chrono::Duration>, // updated within
+}
+
+#[derive(Debug, Clone)]
+pub struct OverviewPipeline {
+    pub project: String, // path with namespace
+    pub pipeline: PipelineBasic,
+}
+
+#[derive(Deserialize, Clone)]
+struct ProjectPath {
+    id: u64,
+    path_with_namespace: String,
+}
+
+/// The projects and user of an overview, kept between refreshes.
+/// Projects are listed again every OVERVIEW_PROJECTS_TTL, so a refresh
+/// only costs one pipeline request per project.
+#[derive(Default)]
+pub struct OverviewProjects {
+    username: Option<String>,
+    listed: Option<(std::time::Instant, Vec<ProjectPath>)>,
+}
+
+#[derive(Deserialize)]
+struct Username {
+    username: String,
+}
+
+fn pipeline_status(status: StatusState) -> Option<PipelineStatus> {
+    match status {
+        StatusState::Running => Some(PipelineStatus::Running),
+        StatusState::Pending => Some(PipelineStatus::Pending),
+        StatusState::Success => Some(PipelineStatus::Success),
+        StatusState::Failed => Some(PipelineStatus::Failed),
+        StatusState::Canceled => Some(PipelineStatus::Canceled),
+        StatusState::Skipped => Some(PipelineStatus::Skipped),
+        StatusState::Created => Some(PipelineStatus::Created),
+        StatusState::Manual => Some(PipelineStatus::Manual),
+        StatusState::Scheduled => Some(PipelineStatus::Scheduled),
+        // Not filterable by the API, filtered afterwards
+        StatusState::Preparing | StatusState::WaitingForResource => None,
+    }
+}
+
+/// Pipelines still going come first, then everything by recency.
+pub fn sort_overview_pipelines(pipelines: &mut [OverviewPipeline]) {
+    pipelines.sort_by_key(|p| {
+        let finished = is_finished(p.pipeline.status);
+        let updated = p.pipeline.updated_at.or(p.pipeline.created_at);
+        (finished, std::cmp::Reverse(updated))
+    });
+}
+
+fn list_overview_projects(
+    client: &Gitlab,
+    filter: &OverviewFilter,
+    username: Option<&String>,
+    updated_after: Option<DateTime<Utc>>,
+) -> Result<Vec<ProjectPath>, String> {
+    match (&filter.group, username) {
+        (Some(group), _) => {
+            let call = groups::projects::GroupProjects::builder()
+                .group(group.as_str())
+                .include_subgroups(true)
+                .archived(false)
+                .order_by(groups::projects::GroupProjectsOrderBy::LastActivityAt)
+                .build()
+                .unwrap();
+            paged(call, Pagination::Limit(OVERVIEW_PROJECTS))
+                .query(client)
+                .map_err(|_| format!("Could not find group {} on {}", group, filter.host))
+        }
+        (None, Some(username)) => {
+            let mut builder = projects::Projects::builder();
+            builder
+                .membership(true)
+                .archived(false)
+                .order_by(projects::ProjectOrderBy::LastActivityAt);
+            if let Some(after) = updated_after {
+                builder.last_activity_after(after);
+            }
+            paged(
+                builder.build().unwrap(),
+                Pagination::Limit(OVERVIEW_PROJECTS),
+            )
+            .query(client)
+            .map_err(|_| format!("Could not list projects of {} on {}", username, filter.host))
+        }
+        (None, None) => Err("Pass a group or a username".to_string()),
+    }
+}
+
+pub fn get_overview_pipelines(
+    filter: &OverviewFilter,
+    conf: &Configuration,
+    projects: &mut OverviewProjects,
+) -> Result<Vec<OverviewPipeline>, String> {
+    let client = gitlab_client(&filter.host, conf)?;
+    // Older than anything GitLab has, as if there was no limit
+    let updated_after = filter
+        .since
+        .and_then(|since| Utc::now().checked_sub_signed(since));
+
+    if projects.username.is_none() {
+        projects.username = match filter.username.as_deref() {
+            Some("me") => {
+                let me: Username = users::CurrentUser::builder()
+                    .build()
+                    .unwrap()
+                    .query(&client)
+                    .map_err(|_| {
+                        format!("Could not get the owner of the token for {}", filter.host)
+                    })?;
+                Some(me.username)
+            }
+            other => other.map(|u| u.to_string()),
+        };
+    }
+    let username = projects.username.clone();
+
+    let project_paths = match &projects.listed {
+        Some((listed_at, paths)) if listed_at.elapsed() < OVERVIEW_PROJECTS_TTL => paths.clone(),
+        _ => {
+            let paths = list_overview_projects(&client, filter, username.as_ref(), updated_after)?;
+            projects.listed = Some((std::time::Instant::now(), paths.clone()));
+            paths
+        }
+    };
+
+    let mut overview = Vec::new();
+    for project in project_paths {
+        let mut builder = projects::pipelines::Pipelines::builder();
+        builder
+            .project(project.id)
+            .order_by(PipelineOrderBy::UpdatedAt)
+            .sort(SortOrder::Descending);
+        if let Some(username) = &username {
+            builder.username(username.as_str());
+        }
+        if let Some(status) = filter.status.and_then(pipeline_status) {
+            builder.status(status);
+        }
+        if let Some(after) = updated_after {
+            builder.updated_after(after);
+        }
+        let pipelines: Result<Vec<PipelineBasic>, _> = paged(
+            builder.build().unwrap(),
+            Pagination::Limit(OVERVIEW_PIPELINES_PER_PROJECT),
+        )
+        .query(&client);
+        // Projects with CI disabled answer with 403, leave them out
+        for pipeline in pipelines.unwrap_or_default() {
+            if filter.status.is_none_or(|s| s == pipeline.status) {
+                overview.push(OverviewPipeline {
+                    project: project.path_with_namespace.clone(),
+                    pipeline,
+                });
+            }
+        }
+    }
+    sort_overview_pipelines(&mut overview);
+    Ok(overview)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::fixtures::*;
+
+    fn overview_pipeline(id: u64, status: StatusState, minutes_ago: i64) -> OverviewPipeline {
+        let mut pipeline: PipelineBasic =
+            serde_json::from_value(serde_json::to_value(pipeline_with_status(id, status)).unwrap())
+                .unwrap();
+        pipeline.updated_at = Some(Utc::now() - chrono::Duration::minutes(minutes_ago));
+        OverviewPipeline {
+            project: "platform/api".to_string(),
+            pipeline,
+        }
+    }
+
+    #[test]
+    fn test_sort_overview_pipelines() {
+        let mut pipelines = vec![
+            overview_pipeline(1, StatusState::Success, 1),
+            overview_pipeline(2, StatusState::Running, 30),
+            overview_pipeline(3, StatusState::Failed, 5),
+            overview_pipeline(4, StatusState::Pending, 2),
+            overview_pipeline(5, StatusState::Skipped, 0),
+        ];
+        sort_overview_pipelines(&mut pipelines);
+        let ids: Vec<u64> = pipelines.iter().map(|p| p.pipeline.id.value()).collect();
+        assert_eq!(ids, vec![4, 2, 5, 1, 3]);
+    }
+
+    #[test]
+    fn test_select_pipelines() {
+        let statuses = [
+            StatusState::Running,
+            StatusState::Success,
+            StatusState::Running,
+            StatusState::Failed,
+            StatusState::Running,
+        ];
+        let pipelines: Vec<PipelineBasic> = statuses
+            .iter()
+            .enumerate()
+            .map(|(i, status)| overview_pipeline(i as u64 + 1, *status, 0).pipeline)
+            .collect();
+        assert_eq!(
+            select_pipelines(&pipelines, PipelineSelection::Running, 5),
+            vec![1, 3, 5]
+        );
+        assert_eq!(
+            select_pipelines(&pipelines, PipelineSelection::Recent, 2),
+            vec![1, 2, 3]
+        );
+        assert_eq!(
+            select_pipelines(&pipelines, PipelineSelection::Latest, 5),
+            vec![1]
+        );
+        assert!(select_pipelines(&[], PipelineSelection::Recent, 5).is_empty());
+    }
+
+    #[test]
+    fn test_parse_gitlab_url() {
+        let target = |project: &str, ref_: Option<&str>, pipeline: Option<u64>| {
+            Ok(Target {
+                host: "gitlab.example.com".to_string(),
+                project: project.to_string(),
+                ref_: ref_.map(|r| r.to_string()),
+                pipeline,
+            })
+        };
+        assert_eq!(
+            parse_gitlab_url("https://gitlab.example.com/group/sub/project/-/pipelines/1234"),
+            target("group/sub/project", None, Some(1234))
+        );
+        assert_eq!(
+            parse_gitlab_url(
+                "https://gitlab.example.com/group/project/-/tree/feature/x?ref_type=heads"
+            ),
+            target("group/project", Some("feature/x"), None)
+        );
+        assert_eq!(
+            parse_gitlab_url("https://gitlab.example.com/group/project/-/commits/main"),
+            target("group/project", Some("main"), None)
+        );
+        assert_eq!(
+            parse_gitlab_url("https://gitlab.example.com/group/project/"),
+            target("group/project", None, None)
+        );
+        assert_eq!(
+            parse_gitlab_url("https://gitlab.example.com/group/project.git"),
+            target("group/project", None, None)
+        );
+        assert!(parse_gitlab_url("group/project")
+            .unwrap_err()
+            .contains("is not a GitLab URL"));
+    }
+
+    #[test]
+    fn test_parse_gitlab_url_pages() {
+        let pipeline = |url: &str| parse_gitlab_url(url).map(|t| (t.project, t.pipeline));
+        for tab in ["builds", "failures", "test_report", "dag", "builds/"] {
+            assert_eq!(
+                pipeline(&format!(
+                    "https://gitlab.example.com/group/project/-/pipelines/1234/{}",
+                    tab
+                )),
+                Ok(("group/project".to_string(), Some(1234)))
+            );
+        }
+        assert_eq!(
+            pipeline("https://gitlab.example.com/group/project/-/pipelines?page=2"),
+            Ok(("group/project".to_string(), None))
+        );
+        let error = |url: &str| parse_gitlab_url(url).unwrap_err();
+        assert!(error("https://gitlab.example.com/group/project/-/jobs/42").contains("job URL"));
+        assert!(
+            error("https://gitlab.example.com/group/project/-/merge_requests/7/pipelines")
+                .contains("merge request URL")
+        );
+        assert!(
+            error("https://gitlab.example.com/group/project/-/pipelines/new")
+                .contains("not the URL of a pipeline")
+        );
+        assert!(error("https://gitlab.example.com/group/project/-/issues/3")
+            .contains("not a project, pipeline or branch URL"));
+        assert!(error("https://gitlab.example.com/group/project/-/tree")
+            .contains("not a project, pipeline or branch URL"));
+    }
 
     #[test]
     fn test_parse_origin() {
         assert_eq!(
-            parse_origin(&"https://gitlab.com/julianbuettner/pipelinetesting".to_string()),
+            parse_origin("https://gitlab.com/julianbuettner/pipelinetesting"),
             Some((
                 "gitlab.com".to_string(),
                 "julianbuettner/pipelinetesting".to_string()
             ))
         );
         assert_eq!(
-            parse_origin(&"git@gitlab.com:julianbuettner/pipelinetesting.git".to_string()),
+            parse_origin("git@gitlab.com:julianbuettner/pipelinetesting.git"),
             Some((
                 "gitlab.com".to_string(),
                 "julianbuettner/pipelinetesting".to_string()
@@ -149,8 +1088,7 @@ mod test {
         );
         assert_eq!(
             parse_origin(
-                &"git@gitlab.com:gitlab-container-release-monitor/release-monitor-frontend.git"
-                    .to_string()
+                "git@gitlab.com:gitlab-container-release-monitor/release-monitor-frontend.git"
             ),
             Some((
                 "gitlab.com".to_string(),
@@ -158,7 +1096,7 @@ mod test {
             ))
         );
         assert_eq!(
-            parse_origin(&"https://gitlab.com/julianbuettner/gitlab-pipeline-viewer".to_string()),
+            parse_origin("https://gitlab.com/julianbuettner/gitlab-pipeline-viewer"),
             Some((
                 "gitlab.com".to_string(),
                 "julianbuettner/gitlab-pipeline-viewer".to_string()
@@ -0,0 +1,1042 @@
+/*
+Fetching pipelines with GraphQL instead of a few REST requests
+per pipeline, selected with `backend: graphql`.
+
+One query lists the pipelines of the ref, another one per selected
+pipeline returns its jobs and test counts, paging through the jobs.
+They are mapped to the JSON the REST API returns, so the result is
+the same `GitlabProjectPipelines` and nothing downstream knows which
+backend was used.
+*/
+use crate::config::Configuration;
+use crate::gitlabbing::{
+    history_count, select_pipelines, GitlabProjectPipelines, PipelineJob, Target,
+};
+use crate::history::{belongs_in_history, History, HistoryEntry};
+use crate::test_reports::{counts_by_job, TestReportSummary, TestSuiteSummary};
+use chrono::{DateTime, Utc};
+use gitlab::{Gitlab, Pipeline, PipelineBasic};
+use graphql_client::{GraphQLQuery, QueryBody};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+// The first page of the REST API, which the selection and history use
+const PIPELINES_PAGE: usize = 20;
+
+const DEFAULT_BRANCH_QUERY: &str = r#"
+query DefaultBranch($fullPath: ID!) {
+  project(fullPath: $fullPath) {
+    repository { rootRef }
+  }
+}
+"#;
+
+// The REST list of pipelines has no user, so the history list takes it from here
+const AUTHORS_QUERY: &str = r#"
+query PipelineAuthors($fullPath: ID!, $ref: String, $first: Int!) {
+  project(fullPath: $fullPath) {
+    pipelines(ref: $ref, first: $first) {
+      nodes { id user { username } }
+    }
+  }
+}
+"#;
+
+// Shared by the queries below, concat! only takes literals
+macro_rules! pipeline_fields {
+    () => {
+        r#"
+fragment PipelineFields on Pipeline {
+  id
+  sha
+  ref
+  status
+  beforeSha
+  createdAt
+  updatedAt
+  startedAt
+  finishedAt
+  committedAt
+  duration
+  coverage
+  detailedStatus { icon text label group tooltip }
+}
+"#
+    };
+}
+
+const PIPELINES_QUERY: &str = concat!(
+    r#"
+query Pipelines(
+  $fullPath: ID!, $ref: String, $first: Int!, $single: Boolean!,
+  $history: Boolean!, $targetRef: String
+) {
+  project(fullPath: $fullPath) {
+    id
+    name
+    webUrl
+    description
+    pipelines(ref: $ref, first: $first) @skip(if: $single) {
+      nodes { ...PipelineFields }
+    }
+    targetPipelines: pipelines(ref: $targetRef, first: $first) @include(if: $history) {
+      nodes { ...PipelineFields }
+    }
+  }
+}
+"#,
+    pipeline_fields!()
+);
+
+// Later pages only need the jobs
+const PIPELINE_QUERY: &str = concat!(
+    r#"
+query Pipeline($fullPath: ID!, $pipeline: CiPipelineID!, $after: String, $firstPage: Boolean!) {
+  project(fullPath: $fullPath) {
+    pipeline(id: $pipeline) {
+      ...PipelineFields
+      user @include(if: $firstPage) { id username name state avatarUrl webUrl }
+      commit @include(if: $firstPage) {
+        sha shortId title message
+        authorName authorEmail authoredDate committedDate
+      }
+      testReportSummary @include(if: $firstPage) {
+        testSuites {
+          nodes { totalCount successCount failedCount skippedCount errorCount buildIds }
+        }
+      }
+      jobs(retried: false, first: 100, after: $after) {
+        pageInfo { hasNextPage endCursor }
+        nodes {
+          id
+          name
+          status
+          stage { name }
+          createdAt
+          startedAt
+          finishedAt
+          duration
+          queuedDuration
+          coverage
+          allowFailure
+          tags
+          runner { id description runnerType }
+          artifacts { nodes { fileType name size } }
+        }
+      }
+    }
+  }
+}
+"#,
+    pipeline_fields!()
+);
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PathVariables {
+    full_path: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct AuthorsVariables {
+    full_path: String,
+    #[serde(rename = "ref")]
+    ref_: String,
+    first: usize,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PipelinesVariables {
+    full_path: String,
+    #[serde(rename = "ref")]
+    ref_: Option<String>,
+    first: usize,
+    single: bool,  // only the project, a single pipeline is queried on its own
+    history: bool, // also the pipelines of `target_ref`
+    target_ref: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PipelineVariables {
+    full_path: String,
+    pipeline: String, // global id
+    after: Option<String>,
+    first_page: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct Nodes<T> {
+    nodes: Vec<T>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Repository {
+    root_ref: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RepositoryNode {
+    repository: Option<Repository>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DefaultBranchData {
+    project: Option<RepositoryNode>,
+}
+
+#[derive(Deserialize, Debug)]
+struct UsernameNode {
+    username: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct AuthorNode {
+    id: String,
+    user: Option<UsernameNode>,
+}
+
+#[derive(Deserialize, Debug)]
+struct AuthorsProject {
+    pipelines: Nodes<AuthorNode>,
+}
+
+#[derive(Deserialize, Debug)]
+struct AuthorsData {
+    project: Option<AuthorsProject>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct UserNode {
+    id: String,
+    username: String,
+    name: String,
+    state: String,
+    avatar_url: Option<String>,
+    web_url: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CommitNode {
+    sha: String,
+    short_id: String,
+    title: Option<String>,
+    message: Option<String>,
+    author_name: Option<String>,
+    author_email: Option<String>,
+    authored_date: Option<DateTime<Utc>>,
+    committed_date: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SuiteNode {
+    total_count: u64,
+    success_count: u64,
+    failed_count: u64,
+    skipped_count: u64,
+    error_count: u64,
+    #[serde(default)]
+    build_ids: Vec<Value>, // IDs, which are strings
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct TestReportSummaryNode {
+    test_suites: Nodes<SuiteNode>,
+}
+
+#[derive(Deserialize, Debug)]
+struct NameNode {
+    name: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RunnerNode {
+    id: String,
+    description: Option<String>,
+    runner_type: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ArtifactNode {
+    file_type: Option<String>,
+    name: Option<String>,
+    size: Option<Value>, // a BigInt, which is a string
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct JobNode {
+    id: String,
+    name: String,
+    status: String,
+    stage: Option<NameNode>,
+    created_at: DateTime<Utc>,
+    started_at: Option<DateTime<Utc>>,
+    finished_at: Option<DateTime<Utc>>,
+    duration: Option<f64>,
+    queued_duration: Option<f64>,
+    coverage: Option<f64>,
+    allow_failure: bool,
+    #[serde(default)]
+    tags: Option<Vec<String>>,
+    runner: Option<RunnerNode>,
+    artifacts: Option<Nodes<ArtifactNode>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PageInfo {
+    has_next_page: bool,
+    end_cursor: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct JobsPage {
+    page_info: PageInfo,
+    nodes: Vec<JobNode>,
+}
+
+impl JobsPage {
+    fn next(&self) -> Option<String> {
+        self.page_info
+            .end_cursor
+            .clone()
+            .filter(|_| self.page_info.has_next_page)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PipelineNode {
+    id: String,
+    sha: String,
+    #[serde(rename = "ref")]
+    ref_: Option<String>,
+    status: String,
+    before_sha: Option<String>,
+    created_at: Option<DateTime<Utc>>,
+    updated_at: Option<DateTime<Utc>>,
+    started_at: Option<DateTime<Utc>>,
+    finished_at: Option<DateTime<Utc>>,
+    committed_at: Option<DateTime<Utc>>,
+    duration: Option<u64>,
+    coverage: Option<f64>,
+    detailed_status: Option<Value>,
+    // Only in the pipelines shown, not in the list
+    user: Option<UserNode>,
+    commit: Option<CommitNode>,
+    test_report_summary: Option<TestReportSummaryNode>,
+    jobs: Option<JobsPage>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ProjectNode {
+    id: String,
+    name: String,
+    web_url: String,
+    description: Option<String>,
+    pipelines: Option<Nodes<PipelineNode>>,
+    target_pipelines: Option<Nodes<PipelineNode>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PipelinesData {
+    project: Option<ProjectNode>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PipelineProject {
+    pipeline: Option<PipelineNode>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PipelineData {
+    project: Option<PipelineProject>,
+}
+
+struct DefaultBranchQuery;
+
+impl GraphQLQuery for DefaultBranchQuery {
+    type Variables = PathVariables;
+    type ResponseData = DefaultBranchData;
+
+    fn build_query(variables: Self::Variables) -> QueryBody<Self::Variables> {
+        QueryBody {
+            variables,
+            query: DEFAULT_BRANCH_QUERY,
+            operation_name: "DefaultBranch",
+        }
+    }
+}
+
+struct AuthorsQuery;
+
+impl GraphQLQuery for AuthorsQuery {
+    type Variables = AuthorsVariables;
+    type ResponseData = AuthorsData;
+
+    fn build_query(variables: Self::Variables) -> QueryBody<Self::Variables> {
+        QueryBody {
+            variables,
+            query: AUTHORS_QUERY,
+            operation_name: "PipelineAuthors",
+        }
+    }
+}
+
+struct PipelinesQuery;
+
+impl GraphQLQuery for PipelinesQuery {
+    type Variables = PipelinesVariables;
+    type ResponseData = PipelinesData;
+
+    fn build_query(variables: Self::Variables) -> QueryBody<Self::Variables> {
+        QueryBody {
+            variables,
+            query: PIPELINES_QUERY,
+            operation_name: "Pipelines",
+        }
+    }
+}
+
+struct PipelineQuery;
+
+impl GraphQLQuery for PipelineQuery {
+    type Variables = PipelineVariables;
+    type ResponseData = PipelineData;
+
+    fn build_query(variables: Self::Variables) -> QueryBody<Self::Variables> {
+        QueryBody {
+            variables,
+            query: PIPELINE_QUERY,
+            operation_name: "Pipeline",
+        }
+    }
+}
+
+/// The number at the end of a global id like gid://gitlab/Ci::Build/42
+fn global_id(id: &str) -> Result<u64, String> {
+    id.rsplit('/')
+        .next()
+        .and_then(|n| n.parse().ok())
+        .ok_or_else(|| format!("Unexpected id {}", id))
+}
+
+fn user_json(user: &Option<UserNode>) -> Result<Value, String> {
+    Ok(match user {
+        // Deleted users, as REST shows them
+        None => json!({
+            "id": 0,
+            "username": "ghost",
+            "name": "Ghost User",
+            "state": "blocked",
+            "avatar_url": null,
+            "web_url": "",
+        }),
+        Some(user) => json!({
+            "id": global_id(&user.id)?,
+            "username": user.username,
+            "name": user.name,
+            "state": user.state.to_lowercase(),
+            "avatar_url": user.avatar_url,
+            "web_url": user.web_url,
+        }),
+    })
+}
+
+/// GraphQL enums are SCREAMING_CASE, REST statuses snake_case. A canceling
+/// pipeline or job still has something to stop, like in webhooks, as
+/// the gitlab crate does not know the status.
+fn rest_status(status: &str) -> String {
+    match status {
+        "CANCELING" => "running".to_string(),
+        status => status.to_lowercase(),
+    }
+}
+
+fn pipeline_json(node: &PipelineNode, project_id: u64, web_url: &str) -> Result<Value, String> {
+    let id = global_id(&node.id)?;
+    let label = rest_status(&node.status);
+    Ok(json!({
+        "id": id,
+        "project_id": project_id,
+        "sha": node.sha,
+        "ref": node.ref_,
+        "status": label,
+        "web_url": format!("{}/-/pipelines/{}", web_url, id),
+        "before_sha": node.before_sha,
+        "tag": false,
+        "yaml_errors": null,
+        "created_at": node.created_at,
+        "updated_at": node.updated_at,
+        "started_at": node.started_at,
+        "finished_at": node.finished_at,
+        "committed_at": node.committed_at,
+        "duration": node.duration,
+        "coverage": node.coverage.map(|c| c.to_string()),
+        "user": user_json(&node.user)?,
+        "detailed_status": node.detailed_status.clone().unwrap_or(json!({ "label": label })),
+    }))
+}
+
+fn commit_json(commit: &CommitNode) -> Value {
+    json!({
+        "id": commit.sha,
+        "short_id": commit.short_id,
+        "title": commit.title,
+        "parent_ids": null,
+        "author_name": commit.author_name,
+        "author_email": commit.author_email.clone().unwrap_or_default(),
+        "authored_date": commit.authored_date,
+        "committer_name": commit.author_name,
+        "committer_email": commit.author_email.clone().unwrap_or_default(),
+        "committed_date": commit.committed_date,
+        "created_at": commit.committed_date,
+        "message": commit.message,
+    })
+}
+
+fn runner_json(runner: &RunnerNode) -> Result<Value, String> {
+    Ok(json!({
+        "id": global_id(&runner.id)?,
+        "description": runner.description,
+        "active": true,
+        "is_shared": runner.runner_type.as_deref() == Some("INSTANCE_TYPE"),
+        "name": runner.description,
+    }))
+}
+
+fn artifact_json(artifact: &ArtifactNode) -> Value {
+    let size = match &artifact.size {
+        Some(Value::String(s)) => s.parse().unwrap_or(0),
+        Some(size) => size.as_u64().unwrap_or(0),
+        None => 0,
+    };
+    json!({
+        "file_type": artifact.file_type.as_deref().unwrap_or_default().to_lowercase(),
+        "file_format": null,
+        "filename": artifact.name.clone().unwrap_or_default(),
+        "size": size,
+    })
+}
+
+fn job_json(
+    node: &JobNode,
+    pipeline: &Value,
+    commit: &Value,
+    web_url: &str,
+) -> Result<Value, String> {
+    let id = global_id(&node.id)?;
+    let runner = node.runner.as_ref().map(runner_json).transpose()?;
+    let artifacts: Vec<Value> = node
+        .artifacts
+        .iter()
+        .flat_map(|a| a.nodes.iter().map(artifact_json))
+        .collect();
+    Ok(json!({
+        "id": id,
+        "status": rest_status(&node.status),
+        "stage": node.stage.as_ref().map(|s| s.name.clone()).unwrap_or_default(),
+        "name": node.name,
+        "ref": pipeline["ref"],
+        "tag": false,
+        "coverage": node.coverage,
+        "created_at": node.created_at,
+        "started_at": node.started_at,
+        "finished_at": node.finished_at,
+        "user": null,
+        "artifacts_file": null,
+        "commit": commit,
+        "runner": runner,
+        "pipeline": pipeline,
+        "allow_failure": node.allow_failure,
+        "duration": node.duration,
+        "artifacts": artifacts,
+        "artifacts_expire_at": null,
+        "web_url": format!("{}/-/jobs/{}", web_url, id),
+        "tag_list": node.tags.clone().unwrap_or_default(),
+        "queued_duration": node.queued_duration,
+    }))
+}
+
+fn test_summary(node: &TestReportSummaryNode) -> TestReportSummary {
+    let suite = |s: &SuiteNode| TestSuiteSummary {
+        total_count: s.total_count,
+        success_count: s.success_count,
+        failed_count: s.failed_count,
+        skipped_count: s.skipped_count,
+        error_count: s.error_count,
+        build_ids: s
+            .build_ids
+            .iter()
+            .filter_map(|id| match id {
+                Value::String(id) => id.parse().ok(),
+                id => id.as_u64(),
+            })
+            .collect(),
+    };
+    TestReportSummary {
+        test_suites: node.test_suites.nodes.iter().map(suite).collect(),
+    }
+}
+
+fn pipeline_jobs(
+    node: &PipelineNode,
+    pipeline: &Value,
+    web_url: &str,
+) -> Result<Vec<PipelineJob>, String> {
+    let id = &pipeline["id"];
+    let commit = match &node.commit {
+        None => return Err(format!("Pipeline {} has no commit", id)),
+        Some(commit) => commit_json(commit),
+    };
+    let mut counts = node
+        .test_report_summary
+        .as_ref()
+        .map(|s| counts_by_job(&test_summary(s)))
+        .unwrap_or_default();
+    let mut jobs = Vec::new();
+    for job_node in node.jobs.iter().flat_map(|j| &j.nodes) {
+        let value = job_json(job_node, pipeline, &commit, web_url)?;
+        let mut job: PipelineJob = serde_json::from_value(value)
+            .map_err(|e| format!("Unexpected job {} of pipeline {}: {}", job_node.name, id, e))?;
+        job.test_counts = counts.remove(&job.job.id.value());
+        jobs.push(job);
+    }
+    // Newest first, like the REST API
+    jobs.sort_by_key(|j| std::cmp::Reverse(j.job.id.value()));
+    Ok(jobs)
+}
+
+/// The last `count` finished pipelines, oldest first
+fn history_entries(pipelines: &[Pipeline], count: usize) -> Vec<HistoryEntry> {
+    let mut entries: Vec<HistoryEntry> = pipelines
+        .iter()
+        .filter(|p| belongs_in_history(p.status))
+        .take(count)
+        .map(HistoryEntry::from_pipeline)
+        .collect();
+    entries.reverse();
+    entries
+}
+
+/// Adds the jobs of a further page to `pipeline`, returns the cursor of the next one
+fn add_jobs(pipeline: &mut PipelineNode, page: PipelineNode) -> Option<String> {
+    let page = page.jobs?;
+    let next = page.next();
+    match &mut pipeline.jobs {
+        Some(jobs) => jobs.nodes.extend(page.nodes),
+        None => pipeline.jobs = Some(page),
+    }
+    next
+}
+
+/// The pipeline of the first page with the jobs of all pages,
+/// `page` fetches the page after a cursor
+fn all_jobs<F>(mut page: F) -> Result<PipelineNode, String>
+where
+    F: FnMut(Option<String>) -> Result<PipelineNode, String>,
+{
+    let mut pipeline = page(None)?;
+    let mut after = pipeline.jobs.as_ref().and_then(|j| j.next());
+    while let Some(cursor) = after {
+        after = add_jobs(&mut pipeline, page(Some(cursor))?);
+    }
+    Ok(pipeline)
+}
+
+/// Maps the pipelines of `project` to those REST would return. `ref_` is
+/// None if the single `pipeline` was asked for, `target_ref` if the history
+/// of the default branch was not. `details` fetches a selected pipeline
+/// with its jobs.
+fn map_project<F>(
+    project: ProjectNode,
+    pipeline: Option<u64>,
+    ref_: Option<&str>,
+    target_ref: Option<&str>,
+    conf: &Configuration,
+    mut details: F,
+) -> Result<GitlabProjectPipelines, String>
+where
+    F: FnMut(u64) -> Result<PipelineNode, String>,
+{
+    let project_id = global_id(&project.id)?;
+    let web_url = project.web_url.as_str();
+    let to_pipelines = |nodes: &[PipelineNode]| -> Result<Vec<(Pipeline, Value)>, String> {
+        nodes
+            .iter()
+            .map(|node| {
+                let value = pipeline_json(node, project_id, web_url)?;
+                serde_json::from_value(value.clone())
+                    .map(|pipeline| (pipeline, value))
+                    .map_err(|e| format!("Unexpected pipeline {}: {}", node.id, e))
+            })
+            .collect()
+    };
+
+    let nodes = project.pipelines.map(|p| p.nodes).unwrap_or_default();
+    let pipelines = to_pipelines(&nodes)?;
+
+    let count = history_count(conf);
+    let history = ref_
+        .filter(|_| count > 0)
+        .map(|ref_| -> Result<History, String> {
+            let target_pipelines = project
+                .target_pipelines
+                .map(|t| t.nodes)
+                .unwrap_or_default();
+            let target: Vec<Pipeline> = to_pipelines(&target_pipelines)?
+                .into_iter()
+                .map(|(p, _)| p)
+                .collect();
+            let shown: Vec<Pipeline> = pipelines.iter().map(|(p, _)| p.clone()).collect();
+            Ok(History {
+                ref_: ref_.to_string(),
+                entries: history_entries(&shown, count),
+                target_ref: target_ref.map(|r| r.to_string()),
+                target_entries: history_entries(&target, count),
+            })
+        });
+
+    let selected = match pipeline {
+        Some(id) => vec![id],
+        None => {
+            let basic: Vec<PipelineBasic> = pipelines
+                .iter()
+                .map(|(_, value)| serde_json::from_value(value.clone()))
+                .collect::<Result<_, _>>()
+                .map_err(|e| format!("Unexpected pipeline: {}", e))?;
+            let pipelines_conf = conf.pipelines.clone().unwrap_or_default();
+            select_pipelines(
+                &basic,
+                pipelines_conf.selection.unwrap_or_default(),
+                pipelines_conf.count.unwrap_or(5),
+            )
+        }
+    };
+    let mut full_pipelines = Vec::new();
+    for id in selected {
+        let node = details(id)?;
+        let value = pipeline_json(&node, project_id, web_url)?;
+        let pipeline: Pipeline = serde_json::from_value(value.clone())
+            .map_err(|e| format!("Unexpected pipeline {}: {}", node.id, e))?;
+        let jobs = pipeline_jobs(&node, &value, web_url)?;
+        full_pipelines.push((pipeline, jobs));
+    }
+
+    Ok(GitlabProjectPipelines {
+        project_id,
+        name: project.name,
+        web_url: project.web_url.clone(),
+        description: project.description,
+        pipelines: full_pipelines,
+        history: history.transpose()?,
+        deployments: Vec::new(),
+        deployments_error: None,
+    })
+}
+
+/// A pipeline with its details and all its jobs
+fn pipeline_details(client: &Gitlab, project: &str, id: u64) -> Result<PipelineNode, String> {
+    all_jobs(|after| {
+        let query = PipelineQuery::build_query(PipelineVariables {
+            full_path: project.to_string(),
+            pipeline: format!("gid://gitlab/Ci::Pipeline/{}", id),
+            first_page: after.is_none(),
+            after,
+        });
+        client
+            .graphql::<PipelineQuery>(&query)
+            .map_err(|e| format!("Could not query pipeline {}: {}", id, e))?
+            .project
+            .and_then(|p| p.pipeline)
+            .ok_or_else(|| format!("Could not query details of pipeline {}", id))
+    })
+}
+
+// Host and project to their default branch
+type DefaultBranches = HashMap<(String, String), Option<String>>;
+
+/// The default branch does not change while watching,
+/// so it is asked for once per project.
+fn default_branch_cache() -> &'static Mutex<DefaultBranches> {
+    static CACHE: OnceLock<Mutex<DefaultBranches>> = OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+fn default_branch(client: &Gitlab, target: &Target) -> Result<Option<String>, String> {
+    let key = (target.host.clone(), target.project.clone());
+    if let Some(branch) = default_branch_cache().lock().unwrap().get(&key) {
+        return Ok(branch.clone());
+    }
+    let query = DefaultBranchQuery::build_query(PathVariables {
+        full_path: target.project.clone(),
+    });
+    let data = client
+        .graphql::<DefaultBranchQuery>(&query)
+        .map_err(|e| format!("Could not query {}: {}", target.project, e))?;
+    let project = data
+        .project
+        .ok_or_else(|| format!("Could not find {} on {}", target.project, target.host))?;
+    let branch = project.repository.and_then(|r| r.root_ref);
+    default_branch_cache()
+        .lock()
+        .unwrap()
+        .insert(key, branch.clone());
+    Ok(branch)
+}
+
+fn authors(data: AuthorsData) -> Result<HashMap<u64, String>, String> {
+    let mut authors = HashMap::new();
+    for node in data.project.map(|p| p.pipelines.nodes).unwrap_or_default() {
+        if let Some(user) = node.user {
+            authors.insert(global_id(&node.id)?, user.username);
+        }
+    }
+    Ok(authors)
+}
+
+/// Who started the last `count` pipelines of `ref_`, by pipeline ID
+pub fn pipeline_authors(
+    client: &Gitlab,
+    project: &str,
+    ref_: &str,
+    count: usize,
+) -> Result<HashMap<u64, String>, String> {
+    let query = AuthorsQuery::build_query(AuthorsVariables {
+        full_path: project.to_string(),
+        ref_: ref_.to_string(),
+        first: count.min(100), // the most GitLab returns at once
+    });
+    let data = client
+        .graphql::<AuthorsQuery>(&query)
+        .map_err(|e| format!("Could not query pipeline authors of {}: {}", project, e))?;
+    authors(data)
+}
+
+/// The pipelines of `target`, without runner availability and deployments
+pub fn get_project_pipelines(
+    client: &Gitlab,
+    target: &Target,
+    conf: &Configuration,
+) -> Result<GitlabProjectPipelines, String> {
+    let (ref_, target_ref) = match target.pipeline {
+        Some(_) => (None, None),
+        None => {
+            let default = default_branch(client, target)?;
+            let ref_ = target
+                .ref_
+                .clone()
+                .or_else(|| default.clone())
+                .ok_or_else(|| format!("{} has no default branch, pass a ref", target.project))?;
+            let target_ref = default.filter(|b| *b != ref_);
+            (Some(ref_), target_ref)
+        }
+    };
+    let history = ref_.is_some() && target_ref.is_some() && history_count(conf) > 0;
+    let query = PipelinesQuery::build_query(PipelinesVariables {
+        full_path: target.project.clone(),
+        ref_: ref_.clone(),
+        first: PIPELINES_PAGE,
+        single: target.pipeline.is_some(),
+        history,
+        target_ref: target_ref.clone(),
+    });
+    let data = client
+        .graphql::<PipelinesQuery>(&query)
+        .map_err(|e| format!("Could not query pipelines of {}: {}", target.project, e))?;
+    let project = data
+        .project
+        .ok_or_else(|| format!("Could not find {} on {}", target.project, target.host))?;
+    map_project(
+        project,
+        target.pipeline,
+        ref_.as_deref(),
+        target_ref.as_deref(),
+        conf,
+        |id| pipeline_details(client, &target.project, id),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::*;
+    use gitlab::StatusState;
+
+    fn conf(yaml: &str) -> Configuration {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn response() -> ProjectNode {
+        let data: PipelinesData =
+            serde_json::from_str(include_str!("../tests/fixtures/graphql.json")).unwrap();
+        data.project.unwrap()
+    }
+
+    /// The pages of the pipeline query, the first pipeline has two of them
+    fn pages() -> Vec<PipelineNode> {
+        let pages: Vec<PipelineData> =
+            serde_json::from_str(include_str!("../tests/fixtures/graphql_pipelines.json")).unwrap();
+        pages
+            .into_iter()
+            .map(|d| d.project.unwrap().pipeline.unwrap())
+            .collect()
+    }
+
+    fn details(id: u64) -> Result<PipelineNode, String> {
+        let mut pages = pages().into_iter().filter(|p| global_id(&p.id) == Ok(id));
+        all_jobs(|_| pages.next().ok_or_else(|| "No more pages".to_string()))
+    }
+
+    fn map(
+        pipeline: Option<u64>,
+        ref_: Option<&str>,
+        target_ref: Option<&str>,
+        yaml: &str,
+    ) -> Result<GitlabProjectPipelines, String> {
+        map_project(response(), pipeline, ref_, target_ref, &conf(yaml), details)
+    }
+
+    #[test]
+    fn test_authors() {
+        let data: AuthorsData = serde_json::from_value(json!({"project": {"pipelines": {"nodes": [
+            {"id": "gid://gitlab/Ci::Pipeline/2", "user": {"username": "julian"}},
+            {"id": "gid://gitlab/Ci::Pipeline/1", "user": null},
+        ]}}}))
+        .unwrap();
+        let authors = authors(data).unwrap();
+        assert_eq!(authors.len(), 1);
+        assert_eq!(authors[&2], "julian");
+    }
+
+    #[test]
+    fn test_global_id() {
+        assert_eq!(
+            global_id("gid://gitlab/Ci::Build/2613012377"),
+            Ok(2613012377)
+        );
+        assert!(global_id("gid://gitlab/Ci::Build/").is_err());
+    }
+
+    #[test]
+    fn test_canceling() {
+        let mut pages = pages();
+        let mut node = pages.remove(2);
+        node.status = "CANCELING".to_string();
+        for job in node.jobs.iter_mut().flat_map(|j| j.nodes.iter_mut()) {
+            job.status = "CANCELING".to_string();
+        }
+        let value = pipeline_json(&node, 36813125, "https://gitlab.com/a/b").unwrap();
+        let pipeline: Pipeline = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(pipeline.status, StatusState::Running);
+        let jobs = pipeline_jobs(&node, &value, "https://gitlab.com/a/b").unwrap();
+        assert!(jobs.iter().all(|j| j.job.status == StatusState::Running));
+        assert_eq!(rest_status("WAITING_FOR_RESOURCE"), "waiting_for_resource");
+    }
+
+    #[test]
+    fn test_same_as_rest() {
+        let project = map(
+            None,
+            Some("add-gitlab-ci"),
+            Some("main"),
+            "pipelines: {selection: latest}",
+        )
+        .unwrap();
+        let rest = project_pipelines(vec![(pipeline(), jobs())]);
+        assert_eq!(project.project_id, rest.project_id);
+        assert_eq!(project.web_url, rest.web_url);
+        assert_eq!(project.pipelines.len(), 1);
+
+        let (graphql_pipeline, graphql_jobs) = &project.pipelines[0];
+        let (rest_pipeline, rest_jobs) = &rest.pipelines[0];
+        assert_eq!(graphql_pipeline.id, rest_pipeline.id);
+        assert_eq!(graphql_pipeline.web_url, rest_pipeline.web_url);
+        assert_eq!(graphql_pipeline.duration, rest_pipeline.duration);
+        assert_eq!(graphql_pipeline.user.username, rest_pipeline.user.username);
+        assert_eq!(
+            graphql_pipeline.detailed_status["label"],
+            rest_pipeline.detailed_status["label"]
+        );
+
+        // The REST jobs, and the trigger job REST leaves out
+        assert_eq!(graphql_jobs.len(), rest_jobs.len() + 1);
+        for (graphql_job, rest_job) in graphql_jobs[1..].iter().zip(rest_jobs) {
+            assert_eq!(graphql_job.job.id, rest_job.job.id);
+            assert_eq!(graphql_job.job.name, rest_job.job.name);
+            assert_eq!(graphql_job.job.stage, rest_job.job.stage);
+            assert_eq!(graphql_job.job.status, rest_job.job.status);
+            assert_eq!(graphql_job.job.web_url, rest_job.job.web_url);
+            assert_eq!(graphql_job.job.duration, rest_job.job.duration);
+            assert_eq!(graphql_job.queued_duration, rest_job.queued_duration);
+            assert_eq!(
+                graphql_job.job.runner.as_ref().map(|r| &r.name),
+                rest_job.job.runner.as_ref().map(|r| &r.name)
+            );
+            assert_eq!(
+                graphql_job.job.artifacts.len(),
+                rest_job.job.artifacts.len()
+            );
+        }
+    }
+
+    #[test]
+    fn test_details() {
+        let project = map(
+            None,
+            Some("add-gitlab-ci"),
+            Some("main"),
+            "pipelines: {selection: latest}",
+        )
+        .unwrap();
+        // The jobs of both pages
+        let jobs = &project.pipelines[0].1;
+        assert_eq!(jobs.len(), 4);
+        let build = jobs.iter().find(|j| j.job.name == "cargo-build").unwrap();
+        assert_eq!(build.test_counts.as_ref().unwrap().total, 42);
+        assert_eq!(project.pipelines[0].0.user.username, "julianbuettner");
+
+        let history = project.history.unwrap();
+        assert_eq!(history.ref_, "add-gitlab-ci");
+        let ids: Vec<u64> = history.entries.iter().map(|e| e.id).collect();
+        assert_eq!(ids, vec![569600001, 569633322]);
+        assert_eq!(history.target_ref.as_deref(), Some("main"));
+        assert_eq!(history.target_entries.len(), 1);
+    }
+
+    #[test]
+    fn test_selection() {
+        let recent = map(
+            None,
+            Some("add-gitlab-ci"),
+            None,
+            "{pipelines: {selection: recent}, history: {pipelines: 0}}",
+        )
+        .unwrap();
+        assert_eq!(recent.pipelines.len(), 2);
+        assert!(recent.history.is_none());
+
+        // A single pipeline, which has no history
+        let mut response = response();
+        response.pipelines = None;
+        let single = map_project(response, Some(569600001), None, None, &conf("{}"), details);
+        let single = single.unwrap();
+        assert!(single.history.is_none());
+        assert_eq!(single.pipelines.len(), 1);
+        assert_eq!(single.pipelines[0].0.status, StatusState::Failed);
+    }
+}
@@ -0,0 +1,158 @@
+/*
+Duration and coverage of the last finished pipelines of a ref,
+compared to the target branch to spot regressions.
+*/
+use crate::transitions::is_finished;
+use gitlab::{Pipeline, StatusState};
+use serde::{Deserialize, Serialize};
+
+/// Finished pipelines worth comparing, skipped ones ran nothing
+pub fn belongs_in_history(status: StatusState) -> bool {
+    is_finished(status) && status != StatusState::Skipped
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub id: u64,
+    pub status: StatusState,
+    pub duration: Option<u64>,
+    pub coverage: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct History {
+    #[serde(rename = "ref")]
+    pub ref_: String,
+    pub entries: Vec<HistoryEntry>, // oldest first
+    /// The branch to compare with, None if `ref_` is the default branch.
+    pub target_ref: Option<String>,
+    pub target_entries: Vec<HistoryEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub latest: f64,
+    pub baseline: f64,
+    pub regression: bool,
+}
+
+impl HistoryEntry {
+    pub fn from_pipeline(pipeline: &Pipeline) -> HistoryEntry {
+        HistoryEntry {
+            id: pipeline.id.value(),
+            status: pipeline.status,
+            duration: pipeline.duration,
+            coverage: pipeline.coverage.as_ref().and_then(|c| c.parse().ok()),
+        }
+    }
+}
+
+fn median(mut values: Vec<f64>) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let middle = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        return Some((values[middle - 1] + values[middle]) / 2.0);
+    }
+    Some(values[middle])
+}
+
+impl History {
+    /// The latest value against the median of the target branch,
+    /// or of the earlier pipelines if there is no target branch.
+    /// Canceled pipelines only ran partly, so they are left out.
+    fn compare<F>(&self, value: F) -> Option<(f64, f64)>
+    where
+        F: Fn(&HistoryEntry) -> Option<f64>,
+    {
+        let completed = |entries: &[HistoryEntry]| -> Vec<HistoryEntry> {
+            entries
+                .iter()
+                .filter(|e| e.status != StatusState::Canceled)
+                .cloned()
+                .collect()
+        };
+        let entries = completed(&self.entries);
+        let (latest, earlier) = entries.split_last()?;
+        let latest = value(latest)?;
+        let baseline_entries = match self.target_ref {
+            Some(_) => completed(&self.target_entries),
+            None => earlier.to_vec(),
+        };
+        let baseline = median(baseline_entries.iter().filter_map(&value).collect())?;
+        Some((latest, baseline))
+    }
+
+    /// Regression if slower by more than `increase` percent
+    pub fn duration(&self, increase: f64) -> Option<Comparison> {
+        let (latest, baseline) = self.compare(|e| e.duration.map(|d| d as f64))?;
+        Some(Comparison {
+            latest,
+            baseline,
+            regression: latest > baseline * (1.0 + increase / 100.0),
+        })
+    }
+
+    /// Regression if coverage dropped by more than `drop` percentage points
+    pub fn coverage(&self, drop: f64) -> Option<Comparison> {
+        let (latest, baseline) = self.compare(|e| e.coverage)?;
+        Some(Comparison {
+            latest,
+            baseline,
+            regression: latest < baseline - drop,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(id: u64, duration: u64, coverage: f64) -> HistoryEntry {
+        HistoryEntry {
+            id,
+            status: StatusState::Success,
+            duration: Some(duration),
+            coverage: Some(coverage),
+        }
+    }
+
+    #[test]
+    fn test_comparison() {
+        let mut history = History {
+            ref_: "feature".to_string(),
+            entries: vec![entry(1, 100, 80.0), entry(2, 130, 78.5)],
+            target_ref: Some("main".to_string()),
+            target_entries: vec![entry(3, 100, 80.0), entry(4, 110, 80.0), entry(5, 90, 79.0)],
+        };
+        let duration = history.duration(20.0).unwrap();
+        assert_eq!((duration.latest, duration.baseline), (130.0, 100.0));
+        assert!(duration.regression);
+        let coverage = history.coverage(1.0).unwrap();
+        assert_eq!(coverage.baseline, 80.0);
+        assert!(coverage.regression);
+
+        // Without a target branch the earlier pipelines are the baseline
+        history.target_ref = None;
+        assert!(history.duration(50.0).is_some_and(|d| !d.regression));
+        assert!(!history.coverage(2.0).unwrap().regression);
+
+        // A canceled pipeline is no regression, nor a baseline
+        let mut canceled = entry(6, 30, 10.0);
+        canceled.status = StatusState::Canceled;
+        history.entries.push(canceled);
+        assert!(!history.coverage(2.0).unwrap().regression);
+
+        history.entries.truncate(1);
+        assert_eq!(history.duration(20.0), None);
+    }
+
+    #[test]
+    fn test_median() {
+        assert_eq!(median(vec![3.0, 1.0, 2.0]), Some(2.0));
+        assert_eq!(median(vec![4.0, 1.0, 2.0, 3.0]), Some(2.5));
+        assert_eq!(median(vec![]), None);
+    }
+}
@@ -0,0 +1,262 @@
+/*
+User commands run on status transitions.
+
+Each hook of the `hooks:` config section matches transitions
+by event and optionally by stage, job name and status.
+A matching hook runs its command with `sh -c`, the transition
+as JSON on stdin and as `GPV_*` environment variables.
+
+Every hook fires at most once per pipeline, subject and status
+until the pipeline finishes, even if a job is retried or the
+status flips back and forth.
+
+Failing hooks are logged with their exit status and stderr
+to hooks.log in the cache directory.
+*/
+use crate::cache::cache_dir;
+use crate::config::{HookConfig, HookEvent};
+use crate::shell::spawn_shell;
+use crate::transitions::{is_finished, status_name, Subject, Transition};
+use gitlab::StatusState;
+use serde_json::json;
+use std::collections::HashSet;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+fn event_matches(event: &HookEvent, transition: &Transition) -> bool {
+    match (event, &transition.subject) {
+        (HookEvent::PipelineStarted, Subject::Pipeline) => transition.to == StatusState::Running,
+        (HookEvent::PipelineFinished, Subject::Pipeline) => is_finished(transition.to),
+        (HookEvent::PipelineFailed, Subject::Pipeline) => transition.to == StatusState::Failed,
+        (HookEvent::PipelineSucceeded, Subject::Pipeline) => transition.to == StatusState::Success,
+        (HookEvent::Stage, Subject::Stage(_)) => true,
+        (HookEvent::Job, Subject::Job(_)) => true,
+        _ => false,
+    }
+}
+
+pub fn hook_matches(hook: &HookConfig, transition: &Transition) -> bool {
+    if !event_matches(&hook.on, transition) {
+        return false;
+    }
+    if let Some(status) = hook.status {
+        if status != transition.to {
+            return false;
+        }
+    }
+    let (stage, job) = match &transition.subject {
+        Subject::Pipeline => (None, None),
+        Subject::Stage(stage) => (Some(stage), None),
+        Subject::Job(job) => (Some(&job.stage), Some(&job.name)),
+    };
+    if hook.stage.is_some() && hook.stage.as_ref() != stage {
+        return false;
+    }
+    if hook.job.is_some() && hook.job.as_ref() != job {
+        return false;
+    }
+    true
+}
+
+pub fn payload(event: &HookEvent, transition: &Transition) -> serde_json::Value {
+    let mut value = transition.to_json();
+    value["event"] = json!(event);
+    value
+}
+
+fn subject_key(subject: &Subject) -> String {
+    match subject {
+        Subject::Pipeline => "".to_string(),
+        Subject::Stage(stage) => format!("stage:{}", stage),
+        Subject::Job(job) => format!("job:{}:{}", job.stage, job.name),
+    }
+}
+
+pub struct Hooks {
+    hooks: Vec<HookConfig>,
+    // Hook index, pipeline ID, subject key and status
+    fired: HashSet<(usize, u64, String, &'static str)>,
+    failures: (Sender<String>, Receiver<String>),
+}
+
+impl Hooks {
+    pub fn new(hooks: Vec<HookConfig>) -> Self {
+        Hooks {
+            hooks,
+            fired: HashSet::new(),
+            failures: channel(),
+        }
+    }
+
+    /// All hooks to run for the given transitions, each at most once
+    /// per pipeline, subject and status until the pipeline finishes.
+    pub fn due(&mut self, transitions: &[Transition]) -> Vec<(HookConfig, Transition)> {
+        let mut due = Vec::new();
+        for transition in transitions {
+            for (i, hook) in self.hooks.iter().enumerate() {
+                if !hook_matches(hook, transition) {
+                    continue;
+                }
+                let key = (
+                    i,
+                    transition.pipeline_id,
+                    subject_key(&transition.subject),
+                    status_name(transition.to),
+                );
+                if self.fired.insert(key) {
+                    due.push((hook.clone(), transition.clone()));
+                }
+            }
+        }
+        // Done with finished pipelines, so the set does not grow forever
+        for transition in transitions {
+            if transition.subject == Subject::Pipeline && is_finished(transition.to) {
+                self.fired
+                    .retain(|(_, pipeline, _, _)| *pipeline != transition.pipeline_id);
+            }
+        }
+        due
+    }
+
+    pub fn run(&mut self, transitions: &[Transition]) {
+        for (hook, transition) in self.due(transitions) {
+            let stdin = payload(&hook.on, &transition).to_string().into_bytes();
+            let failures = Some(self.failures.0.clone());
+            if let Err(e) = spawn_shell(&hook.command, transition.env(), Some(stdin), failures) {
+                self.failures.0.send(e).ok();
+            }
+        }
+    }
+
+    /// Hooks which failed since the last call, also appended to hooks.log
+    pub fn failures(&self) -> Vec<String> {
+        let failures: Vec<String> = self.failures.1.try_iter().collect();
+        if let (false, Some(dir)) = (failures.is_empty(), cache_dir()) {
+            std::fs::create_dir_all(&dir).ok();
+            let log = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(dir.join("hooks.log"));
+            if let Ok(mut log) = log {
+                let now = chrono::Utc::now().to_rfc3339();
+                for failure in &failures {
+                    writeln!(log, "{} {}", now, failure).ok();
+                }
+            }
+        }
+        failures
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transitions::TransitionJob;
+
+    fn transition(subject: Subject, to: StatusState) -> Transition {
+        Transition {
+            project: "gitlab-pipeline-viewer".to_string(),
+            pipeline_id: 1,
+            pipeline_url: "https://gitlab.com/p/-/pipelines/1".to_string(),
+            ref_: Some("main".to_string()),
+            subject,
+            from: Some(StatusState::Running),
+            to,
+        }
+    }
+
+    fn job(name: &str, stage: &str) -> Subject {
+        Subject::Job(TransitionJob {
+            id: 2,
+            name: name.to_string(),
+            stage: stage.to_string(),
+            web_url: "https://gitlab.com/p/-/jobs/2".to_string(),
+        })
+    }
+
+    fn hook(yaml: &str) -> HookConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_hook_matches() {
+        let deploy =
+            hook("{on: stage, stage: build, status: success, command: make deploy-preview}");
+        assert!(hook_matches(
+            &deploy,
+            &transition(Subject::Stage("build".to_string()), StatusState::Success)
+        ));
+        assert!(!hook_matches(
+            &deploy,
+            &transition(Subject::Stage("test".to_string()), StatusState::Success)
+        ));
+        assert!(!hook_matches(
+            &deploy,
+            &transition(job("cargo-build", "build"), StatusState::Success)
+        ));
+
+        let finished = hook("{on: pipeline-finished, command: 'true'}");
+        assert!(hook_matches(
+            &finished,
+            &transition(Subject::Pipeline, StatusState::Canceled)
+        ));
+        assert!(!hook_matches(
+            &finished,
+            &transition(Subject::Pipeline, StatusState::Running)
+        ));
+    }
+
+    #[test]
+    fn test_hooks_fire_once_per_pipeline() {
+        let mut hooks = Hooks::new(vec![hook("{on: job, status: failed, command: 'true'}")]);
+        let failed = transition(job("cargo-build", "build"), StatusState::Failed);
+        assert_eq!(hooks.due(std::slice::from_ref(&failed)).len(), 1);
+        assert_eq!(hooks.due(std::slice::from_ref(&failed)).len(), 0);
+
+        let mut other_pipeline = failed.clone();
+        other_pipeline.pipeline_id = 2;
+        assert_eq!(hooks.due(&[other_pipeline]).len(), 1);
+
+        // Forgotten once the pipeline is final
+        let pipeline_failed = transition(Subject::Pipeline, StatusState::Failed);
+        hooks.due(&[pipeline_failed]);
+        assert!(hooks.fired.iter().all(|(_, pipeline, _, _)| *pipeline != 1));
+        assert_eq!(hooks.fired.len(), 1);
+    }
+
+    #[test]
+    fn test_hooks_fire_per_status() {
+        let mut hooks = Hooks::new(vec![hook("{on: job, command: 'true'}")]);
+        let cargo_build = |to| transition(job("cargo-build", "build"), to);
+        let running = cargo_build(StatusState::Running);
+        let failed = cargo_build(StatusState::Failed);
+        assert_eq!(hooks.due(&[cargo_build(StatusState::Pending)]).len(), 1);
+        assert_eq!(hooks.due(std::slice::from_ref(&running)).len(), 1);
+        assert_eq!(hooks.due(std::slice::from_ref(&failed)).len(), 1);
+        // Retried, the same statuses again
+        assert_eq!(hooks.due(&[running, failed]).len(), 0);
+    }
+
+    #[test]
+    fn test_failures() {
+        let mut hooks = Hooks::new(vec![hook("{on: job, command: 'echo broken >&2; exit 3'}")]);
+        hooks.run(&[transition(job("cargo-build", "build"), StatusState::Failed)]);
+        let failure = hooks.failures.1.recv().unwrap();
+        assert!(failure.contains("exit status: 3"), "{}", failure);
+        assert!(failure.ends_with(": broken"), "{}", failure);
+    }
+
+    #[test]
+    fn test_payload() {
+        let value = payload(
+            &HookEvent::Job,
+            &transition(job("cargo-build", "build"), StatusState::Failed),
+        );
+        assert_eq!(value["event"], "job");
+        assert_eq!(value["pipeline"]["ref"], "main");
+        assert_eq!(value["job"]["name"], "cargo-build");
+        assert_eq!(value["from"], "running");
+        assert_eq!(value["to"], "failed");
+    }
+}
@@ -0,0 +1,96 @@
+/*
+Keyboard input of the interactive views.
+
+They run in raw mode, so Ctrl-C arrives as a key
+instead of a signal and has to be handled like q.
+Terminals supporting focus reporting tell when they are in the background.
+*/
+use crossterm::event::{
+    self, DisableFocusChange, EnableFocusChange, Event, KeyCode, KeyEvent, KeyEventKind,
+    KeyModifiers,
+};
+use crossterm::{cursor, execute, terminal};
+use std::io::stdout;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+static FOCUSED: AtomicBool = AtomicBool::new(true);
+
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    Nothing,
+    Redraw,
+    Refresh, // fetch again right away
+    Quit,
+}
+
+pub fn is_quit(key: &KeyEvent) -> bool {
+    match key.code {
+        KeyCode::Char('q') => true,
+        KeyCode::Char('c') => key.modifiers.contains(KeyModifiers::CONTROL),
+        _ => false,
+    }
+}
+
+/// False while the terminal is in the background, as far as it tells
+pub fn is_focused() -> bool {
+    FOCUSED.load(Ordering::SeqCst)
+}
+
+pub fn enter_interactive() {
+    execute!(stdout(), terminal::EnterAlternateScreen, cursor::Hide)
+        .expect("Your terminal does not support alternate screens.");
+    execute!(stdout(), EnableFocusChange).ok();
+    terminal::enable_raw_mode().expect("Your terminal does not support raw mode.");
+}
+
+pub fn leave_interactive() {
+    terminal::disable_raw_mode().ok();
+    execute!(stdout(), DisableFocusChange).ok();
+    execute!(stdout(), cursor::Show, terminal::LeaveAlternateScreen)
+        .expect("Your terminal does not support alternate screens.");
+}
+
+/// Passes keys to `on_key` until `cooldown` is over
+/// or a key asks for a refresh. Returns false if the user wants to quit.
+pub fn wait_for_keys<F>(cooldown: Duration, running: &Arc<AtomicBool>, on_key: F) -> bool
+where
+    F: FnMut(KeyEvent) -> Action,
+{
+    wait_for_keys_or(cooldown, running, || false, on_key)
+}
+
+/// Like `wait_for_keys`, but also stops waiting once `wake` is true
+/// or the terminal comes back into focus
+pub fn wait_for_keys_or<W, F>(
+    cooldown: Duration,
+    running: &Arc<AtomicBool>,
+    mut wake: W,
+    mut on_key: F,
+) -> bool
+where
+    W: FnMut() -> bool,
+    F: FnMut(KeyEvent) -> Action,
+{
+    let started = Instant::now();
+    while running.load(Ordering::SeqCst) && started.elapsed() < cooldown && !wake() {
+        if !event::poll(Duration::from_millis(50)).unwrap_or(false) {
+            continue;
+        }
+        match event::read() {
+            Ok(Event::Key(key)) if key.kind != KeyEventKind::Release => match on_key(key) {
+                Action::Quit => return false,
+                Action::Refresh => break,
+                _ => (),
+            },
+            Ok(Event::FocusGained) => {
+                FOCUSED.store(true, Ordering::SeqCst);
+                break;
+            }
+            Ok(Event::FocusLost) => FOCUSED.store(false, Ordering::SeqCst),
+            _ => (),
+        }
+    }
+    running.load(Ordering::SeqCst)
+}
@@ -1,62 +1,1001 @@
+mod artifacts;
+mod browser;
+mod cache;
+mod cli;
 mod config;
+mod config_command;
+mod daemon;
+mod dashboard;
+mod deployments;
 mod emoji;
+mod endpoints;
+#[cfg(test)]
+mod fixtures;
 mod git;
+mod git_events;
 mod gitlabbing;
+mod graphql;
+mod history;
+mod hooks;
+mod keys;
+#[cfg(test)]
+mod mock_gitlab;
+mod notifications;
+mod output;
+mod pipeline_list;
+mod prompt;
 mod render;
+mod run;
+mod schedule;
+mod shell;
+mod test_reports;
+mod tokens;
+mod transitions;
+mod webhook;
+use clap::Parser;
+use config::Configuration;
 use crossterm::{execute, terminal};
-use std::io::stdout;
+use gitlabbing::GitlabProjectPipelines;
+use output::OutputFormat;
+use std::io::{stdin, stdout, IsTerminal, Write};
+use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time;
 use std::time::Duration;
-use std::{thread, time};
 
-fn abortable_sleep(dur: Duration, repetitions: usize, running: &Arc<AtomicBool>) {
-    let mut i = 0;
-    while running.load(Ordering::SeqCst) && i < repetitions {
-        thread::sleep(dur);
-        i += 1;
+fn fetch_direct(
+    repo: &git::RepositoryDetails,
+    conf: &Configuration,
+    cache: &mut gitlabbing::FetchCache,
+) -> Result<GitlabProjectPipelines, String> {
+    let stuff = gitlabbing::get_gitlab_pipelines(repo, conf, cache)?;
+    cache::write_prompt_cache(repo, &stuff).ok();
+    Ok(stuff)
+}
+
+/// Ask a running daemon first, poll GitLab ourselves otherwise.
+fn fetch_repo(
+    repo: &git::RepositoryDetails,
+    conf: &Configuration,
+    cache: &mut gitlabbing::FetchCache,
+) -> Result<GitlabProjectPipelines, String> {
+    if conf.use_daemon.unwrap_or(true) {
+        if let Some(result) = daemon::fetch_via_daemon(conf, &repo.path) {
+            return result;
+        }
+    }
+    fetch_direct(repo, conf, cache)
+}
+
+/// Without a target the local checkout is watched.
+fn fetch(
+    conf: &Configuration,
+    target: Option<&gitlabbing::Target>,
+    cache: &mut gitlabbing::FetchCache,
+) -> Result<GitlabProjectPipelines, String> {
+    if let Some(target) = target {
+        return gitlabbing::get_target_pipelines(target, conf, cache);
+    }
+    let repo = git::get_repository_at(Path::new("./"), conf)?;
+    fetch_repo(&repo, conf, cache)
+}
+
+/// The project, ref or pipeline asked for on the command line, if any.
+/// Subcommands take their own target arguments, the URL is for the viewer only.
+fn cli_target(cli: &cli::Cli, conf: &Configuration) -> Result<Option<gitlabbing::Target>, String> {
+    let (url, args) = match &cli.command {
+        None => (cli.url.as_ref(), &cli.target),
+        Some(command) => match command.target() {
+            Some(args) => (None, args),
+            None => return Ok(None),
+        },
+    };
+    let mut target = if let Some(url) = url {
+        gitlabbing::parse_gitlab_url(url)?
+    } else if let Some(project) = &args.project {
+        let host = match &args.host {
+            Some(host) => host.clone(),
+            None => default_host(conf)?,
+        };
+        gitlabbing::Target {
+            host,
+            project: project.trim_matches('/').to_string(),
+            ref_: None,
+            pipeline: None,
+        }
+    } else if args.ref_.is_some() || args.pipeline.is_some() {
+        let repo = git::get_repository_at(Path::new("./"), conf)?;
+        gitlabbing::repository_target(&repo)?
+    } else if args.host.is_some() {
+        return Err("--host needs --project".to_string());
+    } else {
+        return Ok(None);
+    };
+    if args.ref_.is_some() {
+        target.ref_ = args.ref_.clone();
+    }
+    if args.pipeline.is_some() {
+        target.pipeline = args.pipeline;
+    }
+    Ok(Some(target))
+}
+
+fn dashboard_entry(
+    path: &Path,
+    conf: &Configuration,
+    cache: &mut gitlabbing::FetchCache,
+) -> render::DashboardEntry {
+    match git::get_repository_at(path, conf) {
+        Err(e) => render::DashboardEntry {
+            path: path.to_path_buf(),
+            branch: None,
+            result: Err(e),
+        },
+        Ok(repo) => render::DashboardEntry {
+            path: path.to_path_buf(),
+            branch: Some(repo.branch_or_ref.clone()),
+            result: fetch_repo(&repo, conf, cache),
+        },
+    }
+}
+
+fn run_daemon(conf: Configuration, running: Arc<AtomicBool>) {
+    let socket = daemon::socket_path(&conf);
+    let cooldown = exit_on_error(schedule::seconds("cooldown", conf.cooldown.unwrap_or(5.0)));
+    let cache = std::sync::Mutex::new(gitlabbing::FetchCache::new());
+    let fetcher: daemon::Fetcher = Arc::new(move |path: &Path| {
+        let repo = git::get_repository_at(path, &conf)?;
+        let mut cache = cache.lock().unwrap();
+        fetch_direct(&repo, &conf, &mut cache)
+    });
+    println!("Listening on {}", socket.display());
+    if let Err(e) = daemon::serve(&socket, fetcher, cooldown, running) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn print_prompt(format: &str, max_age: f64) {
+    // A prompt must never fail loudly, print nothing instead
+    if let Ok((path, branch)) = git::get_local_branch() {
+        let cache = cache::read_prompt_cache(&path);
+        let segment =
+            prompt::prompt_segment(cache.as_ref(), &branch, format, max_age, chrono::Utc::now());
+        println!("{}", segment);
+    }
+}
+
+fn open(
+    what: &browser::OpenTarget,
+    conf: &Configuration,
+    target: &gitlabbing::Target,
+    project: &GitlabProjectPipelines,
+) -> Result<(), String> {
+    let url = match what {
+        browser::OpenTarget::Mr => {
+            let branch = target
+                .ref_
+                .clone()
+                .or_else(|| project.pipelines.first().and_then(|(p, _)| p.ref_.clone()))
+                .ok_or("No branch to look up merge requests for")?;
+            gitlabbing::get_merge_request_url(&target.host, project.project_id, &branch, conf)?
+        }
+        _ => browser::url_for(what, project)?,
+    };
+    browser::open_url(&url, conf)
+}
+
+/// The target given on the command line, or the one of the local checkout
+fn resolve_target(
+    conf: &Configuration,
+    target: Option<&gitlabbing::Target>,
+) -> Result<gitlabbing::Target, String> {
+    match target {
+        Some(target) => Ok(target.clone()),
+        None => gitlabbing::repository_target(&git::get_repository_at(Path::new("./"), conf)?),
+    }
+}
+
+fn open_once(
+    what: &browser::OpenTarget,
+    conf: &Configuration,
+    target: Option<&gitlabbing::Target>,
+) -> Result<(), String> {
+    let target = resolve_target(conf, target)?;
+    let project =
+        gitlabbing::get_target_pipelines(&target, conf, &mut gitlabbing::FetchCache::new())?;
+    open(what, conf, &target, &project)
+}
+
+fn run_artifacts(
+    job: &str,
+    action: &artifacts::ArtifactAction,
+    conf: &Configuration,
+    target: Option<&gitlabbing::Target>,
+) -> Result<(), String> {
+    let target = resolve_target(conf, target)?;
+    let project =
+        gitlabbing::get_target_pipelines(&target, conf, &mut gitlabbing::FetchCache::new())?;
+    let job = &project.find_job(job)?.job;
+    let job_artifacts = artifacts::JobArtifacts {
+        host: target.host.clone(),
+        project_id: project.project_id,
+        job_id: job.id.value(),
+        job_url: job.web_url.clone(),
+    };
+    match action {
+        artifacts::ArtifactAction::List => {
+            for entry in job_artifacts.entries(conf)? {
+                println!("{:>10}  {}", artifacts::human_size(entry.size), entry.path);
+            }
+        }
+        artifacts::ArtifactAction::Get { path, dir } => {
+            let file = job_artifacts.download_file(path, dir, conf)?;
+            println!("{}", file.display());
+        }
+        artifacts::ArtifactAction::Download { dir } => {
+            let file = job_artifacts.download_archive(dir, conf)?;
+            println!("{}", file.display());
+        }
+        artifacts::ArtifactAction::Open { path } => {
+            let path = match path {
+                Some(path) => path.clone(),
+                None => {
+                    let entries = job_artifacts.entries(conf)?;
+                    artifacts::find_report(&entries)
+                        .ok_or("No JUnit or coverage report in the artifacts")?
+                        .path
+                        .clone()
+                }
+            };
+            browser::open_url(&job_artifacts.open_url(&path, conf)?, conf)?;
+        }
+    }
+    Ok(())
+}
+
+fn test_failures(
+    conf: &Configuration,
+    target: Option<&gitlabbing::Target>,
+    project: &GitlabProjectPipelines,
+    job: Option<&str>,
+) -> Result<String, String> {
+    let target = resolve_target(conf, target)?;
+    let (pipeline, _) = project.pipelines.first().ok_or("There is no pipeline")?;
+    let report =
+        gitlabbing::get_test_report(&target.host, project.project_id, pipeline.id.value(), conf)?;
+    Ok(test_reports::format_test_failures(&report, job))
+}
+
+/// Asks for variables if `ask`, then starts a pipeline for the ref
+fn run_pipeline(
+    conf: &Configuration,
+    target: &gitlabbing::Target,
+    variables: Vec<(String, String)>,
+    ask: bool,
+) -> Result<gitlab::PipelineBasic, String> {
+    let mut variables = variables;
+    if ask {
+        let ci_variables = gitlabbing::get_ci_config(target, conf)
+            .and_then(|config| run::ci_variables(&config))
+            .unwrap_or_else(|e| {
+                eprintln!(
+                    "Could not read the CI variables, not asking for them: {}",
+                    e
+                );
+                Vec::new()
+            });
+        let mut input = stdin().lock();
+        variables = run::prompt_variables(&ci_variables, variables, &mut input, &mut stdout())?;
+        let with: Vec<String> = variables
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect();
+        let mut question = format!(
+            "Run a pipeline for {}",
+            target.ref_.as_deref().unwrap_or("the default branch")
+        );
+        if !with.is_empty() {
+            question += &format!(" with {}", with.join(" "));
+        }
+        if !run::confirm(&(question + "?"), true, &mut input, &mut stdout())? {
+            return Err("Not started".to_string());
+        }
+    }
+    gitlabbing::create_pipeline(target, conf, &variables)
+}
+
+fn run_environment_action(
+    action: &deployments::EnvironmentAction,
+    yes: bool,
+    conf: &Configuration,
+    target: Option<&gitlabbing::Target>,
+) -> Result<String, String> {
+    let target = resolve_target(conf, target)?;
+    let question = match action {
+        deployments::EnvironmentAction::Stop { name } => format!("Stop {}?", name),
+        deployments::EnvironmentAction::Rollback { name } => {
+            format!("Deploy the previous version of {} again?", name)
+        }
+    };
+    if !yes {
+        if !stdin().is_terminal() {
+            return Err("Pass --yes to confirm".to_string());
+        }
+        if !run::confirm(&question, false, &mut stdin().lock(), &mut stdout())? {
+            return Err("Nothing done".to_string());
+        }
+    }
+    match action {
+        deployments::EnvironmentAction::Stop { name } => {
+            gitlabbing::stop_environment(&target, conf, name)?;
+            Ok(format!("Stopping {}", name))
+        }
+        deployments::EnvironmentAction::Rollback { name } => {
+            let deployment = gitlabbing::rollback_environment(&target, conf, name)?;
+            Ok(format!(
+                "Deploying {} of {} to {} again",
+                &deployment.sha[..8.min(deployment.sha.len())],
+                deployment.ref_,
+                name
+            ))
+        }
+    }
+}
+
+/// Asks which of the environments beneath the pipelines to stop or roll back
+fn environment_from_viewer(
+    stop: bool,
+    stuff: &GitlabProjectPipelines,
+    conf: &Configuration,
+    target: Option<&gitlabbing::Target>,
+) -> Result<String, String> {
+    let names: Vec<String> = stuff
+        .deployments
+        .iter()
+        .map(|d| d.environment.name.clone())
+        .collect();
+    let question = match stop {
+        true => "Environment to stop",
+        false => "Environment to roll back",
+    };
+    let index = run::choose(question, &names, &mut stdin().lock(), &mut stdout())?;
+    let name = names[index].clone();
+    let action = match stop {
+        true => deployments::EnvironmentAction::Stop { name },
+        false => deployments::EnvironmentAction::Rollback { name },
+    };
+    run_environment_action(&action, false, conf, target)
+}
+
+fn open_key(key: &crossterm::event::KeyEvent) -> Option<browser::OpenTarget> {
+    match key.code {
+        crossterm::event::KeyCode::Char('p') => Some(browser::OpenTarget::Pipeline),
+        crossterm::event::KeyCode::Char('f') => Some(browser::OpenTarget::Failed),
+        crossterm::event::KeyCode::Char('m') => Some(browser::OpenTarget::Mr),
+        crossterm::event::KeyCode::Char('h') => Some(browser::OpenTarget::Project),
+        _ => None,
+    }
+}
+
+fn print_once(conf: &Configuration, target: Option<&gitlabbing::Target>, format: OutputFormat) {
+    match fetch(conf, target, &mut gitlabbing::FetchCache::new()) {
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        Ok(stuff) => println!("{}", output::format_snapshot(&stuff, format).trim_end()),
+    }
+}
+
+/// How long notices like "config reloaded" stay in the header
+const NOTICE_DURATION: Duration = Duration::from_secs(10);
+
+/// Swaps in the config if a file changed, returns its polling intervals.
+/// An invalid config is reported and the previous one kept.
+fn reload_config(
+    watcher: &mut config::ConfigWatcher,
+    conf: &mut Configuration,
+) -> Option<Result<schedule::Intervals, String>> {
+    let reloaded = watcher.poll()?;
+    match reloaded.and_then(|r| schedule::Intervals::from_config(&r).map(|i| (r, i))) {
+        Ok((reloaded, intervals)) => {
+            *conf = reloaded;
+            tokens::forget_tokens();
+            Some(Ok(intervals))
+        }
+        Err(e) => Some(Err(e)),
+    }
+}
+
+fn exit_on_error<T>(result: Result<T, String>) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    })
+}
+
+/// Checkouts and pushes in the local checkout wake the scheduler
+fn local_git_watcher(
+    conf: &Configuration,
+    target: Option<&gitlabbing::Target>,
+    scheduler: &schedule::Scheduler,
+) -> Option<git_events::GitWatcher> {
+    if target.is_some() {
+        return None;
+    }
+    let remote = conf.remote.as_deref().unwrap_or("origin");
+    git_events::GitWatcher::new(Path::new("./"), remote, scheduler.waker.clone()).ok()
+}
+
+fn start_webhook(
+    conf: &Configuration,
+    scheduler: &schedule::Scheduler,
+    running: &Arc<AtomicBool>,
+) -> Result<Option<webhook::WebhookReceiver>, String> {
+    let webhook = match &conf.webhook {
+        None => return Ok(None),
+        Some(webhook) => webhook,
+    };
+    let receiver = webhook::WebhookReceiver::start(
+        &webhook.listen,
+        webhook.secret.clone(),
+        scheduler.waker.clone(),
+        running.clone(),
+    )?;
+    Ok(Some(receiver))
+}
+
+/// The last snapshot with the statuses of webhook events applied,
+/// None if it has to be fetched because the events changed nothing in it
+/// or are about pipelines or jobs it does not have
+fn pushed_snapshot(
+    last: Option<&GitlabProjectPipelines>,
+    webhook: Option<&webhook::WebhookReceiver>,
+) -> Option<GitlabProjectPipelines> {
+    let events = webhook?.events();
+    if events.is_empty() {
+        return None;
+    }
+    let mut snapshot = last?.clone();
+    match webhook::apply(&mut snapshot, &events) {
+        webhook::Applied::Updated => Some(snapshot),
+        webhook::Applied::Nothing | webhook::Applied::Unknown => None,
+    }
+}
+
+fn watch_ndjson(
+    conf: &Configuration,
+    target: Option<&gitlabbing::Target>,
+    running: &Arc<AtomicBool>,
+) {
+    let mut tracker = transitions::StatusTracker::default();
+    let mut cache = gitlabbing::FetchCache::new();
+    let mut emitter = output::NdjsonEmitter::default();
+    let mut watcher = config::ConfigWatcher::new();
+    let mut conf = conf.clone();
+    let mut scheduler = exit_on_error(schedule::Scheduler::unattended(&conf));
+    let git_watcher = local_git_watcher(&conf, target, &scheduler);
+    let webhook = start_webhook(&conf, &scheduler, running).unwrap_or_else(|e| {
+        println!(
+            "{}",
+            output::ndjson_line(output::Event::Error(&e), chrono::Utc::now())
+        );
+        None
+    });
+    let mut last: Option<GitlabProjectPipelines> = None;
+    while running.load(Ordering::SeqCst) {
+        match reload_config(&mut watcher, &mut conf) {
+            Some(Ok(intervals)) => scheduler.set_intervals(intervals),
+            Some(Err(e)) => {
+                let e = format!("Config invalid, keeping previous: {}", e);
+                println!(
+                    "{}",
+                    output::ndjson_line(output::Event::Error(&e), chrono::Utc::now())
+                );
+            }
+            None => (),
+        }
+        let conf = &conf;
+        let stuff = match pushed_snapshot(last.as_ref(), webhook.as_ref()) {
+            Some(snapshot) => Ok(snapshot),
+            None => fetch(conf, target, &mut cache),
+        };
+        let line = match stuff {
+            Err(e) => Some(output::ndjson_line(
+                output::Event::Error(&e),
+                chrono::Utc::now(),
+            )),
+            Ok(stuff) => {
+                let transitions = tracker.update_with(&stuff, |id| {
+                    gitlabbing::get_project_pipeline(&stuff, id, conf)
+                });
+                last = Some(stuff.clone());
+                scheduler.set_active(schedule::has_active(&stuff));
+                emitter.next_line(&stuff, &transitions, chrono::Utc::now())
+            }
+        };
+        if let Some(line) = line {
+            println!("{}", line);
+            stdout().flush().ok();
+        }
+        scheduler.sleep(running);
+        if let Some(git_watcher) = &git_watcher {
+            scheduler.on_git_events(&git_watcher.events(), time::Instant::now());
+        }
+    }
+}
+
+fn watch_terminal(
+    conf: &Configuration,
+    target: Option<&gitlabbing::Target>,
+    running: &Arc<AtomicBool>,
+) {
+    let mut tracker = transitions::StatusTracker::default();
+    let mut cache = gitlabbing::FetchCache::new();
+    let mut notifications = notifications::Notifications::from_config(conf);
+    let mut hooks = hooks::Hooks::new(conf.hooks.clone().unwrap_or_default());
+    let mut watcher = config::ConfigWatcher::new();
+    let mut conf = conf.clone();
+    let mut notice: Option<(String, time::Instant)> = None;
+    let mut scheduler = schedule::Scheduler::new(
+        exit_on_error(schedule::Intervals::from_config(&conf)),
+        time::Instant::now(),
+    );
+    let git_watcher = local_git_watcher(&conf, target, &scheduler);
+    let webhook = match start_webhook(&conf, &scheduler, running) {
+        Ok(webhook) => webhook,
+        Err(e) => {
+            notice = Some((render::render_error(&e), time::Instant::now()));
+            None
+        }
+    };
+    if let Some(webhook) = &webhook {
+        let text = format!("Receiving webhooks on {}", webhook.address);
+        notice = Some((text, time::Instant::now()));
+    }
+    let mut last: Option<GitlabProjectPipelines> = None;
+    let mut show_tests = false;
+    // A pipeline picked from the history list instead of the latest ones
+    let mut pinned: Option<u64> = None;
+    let mut list_state: Option<pipeline_list::PipelineListState> = None;
+
+    keys::enter_interactive();
+
+    while running.load(Ordering::SeqCst) {
+        match reload_config(&mut watcher, &mut conf) {
+            None => (),
+            Some(Ok(intervals)) => {
+                notifications = notifications::Notifications::from_config(&conf);
+                hooks = hooks::Hooks::new(conf.hooks.clone().unwrap_or_default());
+                scheduler.set_intervals(intervals);
+                let text = format!("{} Config reloaded", emoji::GREEN_CHECK);
+                notice = Some((text, time::Instant::now()));
+            }
+            Some(Err(e)) => {
+                let text = format!(
+                    "{} Config invalid, keeping previous: {}",
+                    emoji::RED_EXCLAMATION,
+                    e
+                );
+                notice = Some((text, time::Instant::now()));
+            }
+        }
+        notice = notice.filter(|(_, since)| since.elapsed() < NOTICE_DURATION);
+        let conf = &conf;
+        let watched = match pinned {
+            None => Ok(target.cloned()),
+            Some(id) => resolve_target(conf, target).map(|t| {
+                Some(gitlabbing::Target {
+                    pipeline: Some(id),
+                    ..t
+                })
+            }),
+        };
+        let stuff = match pushed_snapshot(last.as_ref(), webhook.as_ref()) {
+            Some(snapshot) => Ok(snapshot),
+            None => watched.and_then(|watched| fetch(conf, watched.as_ref(), &mut cache)),
+        };
+        if let Ok(stuff) = &stuff {
+            last = Some(stuff.clone());
+            let transitions = tracker.update_with(stuff, |id| {
+                gitlabbing::get_project_pipeline(stuff, id, conf)
+            });
+            notifications.dispatch(&transitions);
+            hooks.run(&transitions);
+            scheduler.set_active(schedule::has_active(stuff));
+            if !transitions.is_empty() {
+                scheduler.touch(time::Instant::now());
+            }
+        }
+        if let Some(failure) = hooks.failures().pop() {
+            let text = format!("{} Hook failed: {}", emoji::RED_EXCLAMATION, failure);
+            notice = Some((text, time::Instant::now()));
+        }
+        let list = list_state.as_ref().map(|_| {
+            resolve_target(conf, target).and_then(|t| gitlabbing::get_pipeline_list(&t, conf))
+        });
+        let mut help = "p pipeline   f failed job   m merge request   h project   t failed tests   l history   r run   q quit".to_string();
+        let environments = matches!(&stuff, Ok(stuff) if !stuff.deployments.is_empty());
+        if environments {
+            help += "   s stop environment   b roll back";
+        }
+        if pinned.is_some() {
+            help += "   Esc latest";
+        }
+        let screen = |show_tests: bool, list_state: Option<&pipeline_list::PipelineListState>| {
+            if let (Some(state), Some(list)) = (list_state, &list) {
+                return match list {
+                    Ok(list) => render::render_pipeline_list(
+                        list,
+                        Some(state.selected),
+                        render::get_terminal_width(),
+                        render::get_terminal_height(),
+                        chrono::Utc::now(),
+                    ),
+                    Err(e) => format!("{}\nEsc back   q quit\n", render::render_error(e)),
+                };
+            }
+            let text = match &stuff {
+                Err(e) => render::render_error(e),
+                Ok(stuff) if show_tests => test_failures(conf, target, stuff, None)
+                    .unwrap_or_else(|e| render::render_error(&e)),
+                Ok(stuff) => render::render(stuff, conf),
+            };
+            match &notice {
+                Some((notice, _)) => format!("{}\n{}\n{}\n", notice, text, help),
+                None => format!("{}\n{}\n", text, help),
+            }
+        };
+        let mut current = screen(show_tests, list_state.as_ref());
+        render::print_screen(&current);
+
+        let interval = scheduler.interval(time::Instant::now());
+        let waker = scheduler.waker.clone();
+        let mut pressed = false;
+        let mut environment_done: Option<Result<String, String>> = None;
+        let keep_running = keys::wait_for_keys_or(
+            interval,
+            running,
+            || waker.take(),
+            |key| {
+                pressed = true;
+                if let Some(state) = list_state.as_mut() {
+                    let entries = match &list {
+                        Some(Ok(list)) => list.pipelines.len(),
+                        _ => 0,
+                    };
+                    match state.handle_key(key, entries) {
+                        pipeline_list::ListAction::Quit => return keys::Action::Quit,
+                        pipeline_list::ListAction::Nothing => return keys::Action::Nothing,
+                        pipeline_list::ListAction::Redraw => (),
+                        pipeline_list::ListAction::Close => list_state = None,
+                        pipeline_list::ListAction::Select(i) => {
+                            if let Some(Ok(list)) = &list {
+                                pinned = Some(list.pipelines[i].id);
+                            }
+                            list_state = None;
+                            return keys::Action::Refresh;
+                        }
+                    }
+                    current = screen(show_tests, list_state.as_ref());
+                    render::print_screen(&current);
+                    return keys::Action::Redraw;
+                }
+                if keys::is_quit(&key) {
+                    return keys::Action::Quit;
+                }
+                match key.code {
+                    crossterm::event::KeyCode::Char('t') => {
+                        show_tests = !show_tests;
+                        current = screen(show_tests, None);
+                        render::print_screen(&current);
+                        return keys::Action::Redraw;
+                    }
+                    crossterm::event::KeyCode::Char('r') => {
+                        keys::leave_interactive();
+                        let started = resolve_target(conf, target)
+                            .and_then(|t| run_pipeline(conf, &t, Vec::new(), true));
+                        keys::enter_interactive();
+                        match started {
+                            Ok(pipeline) => {
+                                pinned = Some(pipeline.id.value());
+                                return keys::Action::Refresh;
+                            }
+                            Err(e) => {
+                                render::print_screen(&format!("{}{}\n", current, e));
+                                return keys::Action::Nothing;
+                            }
+                        }
+                    }
+                    crossterm::event::KeyCode::Char(c @ ('s' | 'b')) if environments => {
+                        if let Ok(stuff) = &stuff {
+                            keys::leave_interactive();
+                            let done = environment_from_viewer(c == 's', stuff, conf, target);
+                            keys::enter_interactive();
+                            environment_done = Some(done);
+                            return keys::Action::Refresh;
+                        }
+                    }
+                    crossterm::event::KeyCode::Char('l') => {
+                        list_state = Some(pipeline_list::PipelineListState::default());
+                        return keys::Action::Refresh;
+                    }
+                    crossterm::event::KeyCode::Esc if pinned.is_some() => {
+                        pinned = None;
+                        return keys::Action::Refresh;
+                    }
+                    _ => (),
+                }
+                if let (Some(what), Ok(stuff)) = (open_key(&key), &stuff) {
+                    let opened = resolve_target(conf, target)
+                        .and_then(|target| open(&what, conf, &target, stuff));
+                    if let Err(e) = opened {
+                        render::print_screen(&format!("{}{}\n", current, e));
+                    }
+                }
+                keys::Action::Nothing
+            },
+        );
+        if !keep_running {
+            break;
+        }
+        let text = match environment_done {
+            None => None,
+            Some(Ok(done)) => Some(format!("{} {}", emoji::GREEN_CHECK, done)),
+            Some(Err(e)) => Some(format!("{} {}", emoji::RED_EXCLAMATION, e)),
+        };
+        if let Some(text) = text {
+            notice = Some((text, time::Instant::now()));
+        }
+        let now = time::Instant::now();
+        scheduler.set_focused(keys::is_focused(), now);
+        if pressed {
+            scheduler.touch(now);
+        }
+        if let Some(git_watcher) = &git_watcher {
+            scheduler.on_git_events(&git_watcher.events(), now);
+        }
+    }
+
+    keys::leave_interactive();
+}
+
+fn default_host(conf: &Configuration) -> Result<String, String> {
+    let hosts = tokens::known_hosts(conf);
+    match hosts.as_slice() {
+        [host] => Ok(host.to_string()),
+        [] => Err("No host with a token configured, pass one with --host".to_string()),
+        _ => Err("Tokens for several hosts, pick one with --host".to_string()),
+    }
+}
+
+fn watch_overview(
+    conf: &Configuration,
+    filter: &gitlabbing::OverviewFilter,
+    running: &Arc<AtomicBool>,
+) {
+    let intervals = exit_on_error(schedule::Intervals::overview(conf));
+    let scheduler = schedule::Scheduler::new(intervals, time::Instant::now());
+    execute!(stdout(), terminal::EnterAlternateScreen)
+        .expect("Your terminal does not support alternate screens.");
+    let mut projects = gitlabbing::OverviewProjects::default();
+    while running.load(Ordering::SeqCst) {
+        match gitlabbing::get_overview_pipelines(filter, conf, &mut projects) {
+            Err(e) => render::print_screen(&render::render_error(&e)),
+            Ok(pipelines) => {
+                render::print_screen(&render::render_overview(filter, &pipelines));
+            }
+        }
+        scheduler.sleep(running);
+    }
+
+    execute!(stdout(), terminal::LeaveAlternateScreen)
+        .expect("Your terminal does not support alternate screens.");
+}
+
+/// Runs before the config is read, so that check can report its errors
+fn run_config_action(action: &config_command::ConfigAction) {
+    let sources = config::ConfigSources::find();
+    let vars: Vec<(String, String)> = std::env::vars().collect();
+    let result = match action {
+        config_command::ConfigAction::Check => {
+            if !config_command::check(&sources, vars.into_iter()) {
+                std::process::exit(1);
+            }
+            return;
+        }
+        config_command::ConfigAction::Init => {
+            config_command::init(&sources, &mut stdin().lock(), &mut stdout())
+                .map(|path| format!("Wrote {}", path.display()))
+        }
+        config_command::ConfigAction::Show => config_command::show(&sources, vars.into_iter()),
+    };
+    match result {
+        Ok(done) => println!("{}", done.trim_end()),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
     }
 }
 
 fn main() {
+    let cli = cli::Cli::parse();
+
+    if let Some(cli::Command::Prompt { format, max_age }) = &cli.command {
+        print_prompt(format, *max_age);
+        return;
+    }
+
+    if let Some(cli::Command::Config { action }) = &cli.command {
+        run_config_action(action);
+        return;
+    }
+
     let conf = config::read_config();
     if conf.is_err() {
-        println!("Could not read config file: {}", conf.err().unwrap());
-        return;
+        eprintln!("Could not read config file: {}", conf.err().unwrap());
+        eprintln!("Run gpv config check for details");
+        std::process::exit(1);
     }
     let conf = conf.unwrap();
 
+    let target = cli_target(&cli, &conf).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
+    if let Some(format) = cli.output {
+        if format != OutputFormat::Ndjson {
+            print_once(&conf, target.as_ref(), format);
+            return;
+        }
+    }
+
     let running = Arc::new(AtomicBool::new(true));
     let r = running.clone();
+    let quiet = cli.output.is_some();
     ctrlc::set_handler(move || {
-        println!("Quit");
+        if !quiet {
+            println!("Quit");
+        }
         r.store(false, Ordering::SeqCst);
     })
     .expect("Error setting Ctrl-C handler");
 
-    execute!(stdout(), terminal::EnterAlternateScreen)
-        .expect("Your terminal does not support alternate screens.");
-
-    while running.load(Ordering::SeqCst) {
-        let repo = git::get_local_repository(&conf);
-
-        if repo.is_err() {
-            render::render_error(repo.err().unwrap());
-        } else {
-            let stuff = gitlabbing::get_gitlab_pipelines(&repo.unwrap(), &conf);
-            if stuff.is_err() {
-                render::render_error(stuff.err().unwrap());
-            } else {
-                let result = render::render(&stuff.unwrap());
-                render::clear_screen();
-                print!("{}", result);
+    match cli.command {
+        Some(cli::Command::Open { what, .. }) => {
+            let what = what.unwrap_or(browser::OpenTarget::Pipeline);
+            if let Err(e) = open_once(&what, &conf, target.as_ref()) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(cli::Command::Artifacts { job, action, .. }) => {
+            let action = action.unwrap_or(artifacts::ArtifactAction::List);
+            if let Err(e) = run_artifacts(&job, &action, &conf, target.as_ref()) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(cli::Command::Tests { job, .. }) => {
+            let failures = resolve_target(&conf, target.as_ref())
+                .and_then(|t| {
+                    gitlabbing::get_target_pipelines(&t, &conf, &mut gitlabbing::FetchCache::new())
+                })
+                .and_then(|project| {
+                    test_failures(&conf, target.as_ref(), &project, job.as_deref())
+                });
+            match failures {
+                Ok(failures) => print!("{}", failures),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(cli::Command::History { .. }) => {
+            let list = resolve_target(&conf, target.as_ref())
+                .and_then(|t| gitlabbing::get_pipeline_list(&t, &conf));
+            match list {
+                Ok(list) => print!(
+                    "{}",
+                    render::render_pipeline_list(
+                        &list,
+                        None,
+                        render::get_terminal_width(),
+                        0,
+                        chrono::Utc::now()
+                    )
+                ),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(cli::Command::Run { variables, yes, .. }) => {
+            let target = resolve_target(&conf, target.as_ref()).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            });
+            let ask = !yes && stdin().is_terminal();
+            let pipeline = run_pipeline(&conf, &target, variables, ask).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            });
+            println!("Started {}", pipeline.web_url);
+            if stdout().is_terminal() {
+                let watched = gitlabbing::Target {
+                    pipeline: Some(pipeline.id.value()),
+                    ..target
+                };
+                watch_terminal(&conf, Some(&watched), &running);
             }
+            return;
         }
-        let duration = time::Duration::from_millis(50);
-        let repetitions = conf.cooldown.unwrap_or(5.0) / 0.05;
-        abortable_sleep(duration, repetitions.floor().abs() as usize, &running);
+        Some(cli::Command::Environment { action, yes, .. }) => {
+            match run_environment_action(&action, yes, &conf, target.as_ref()) {
+                Ok(done) => println!("{}", done),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(cli::Command::Daemon) => {
+            run_daemon(conf, running);
+            return;
+        }
+        Some(cli::Command::Dashboard { repos }) => {
+            let paths = dashboard::dashboard_paths(&repos, &conf).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            });
+            let mut cache = gitlabbing::FetchCache::new();
+            let ran = dashboard::run(&conf, paths, &running, |path| {
+                dashboard_entry(path, &conf, &mut cache)
+            });
+            exit_on_error(ran);
+            return;
+        }
+        Some(cli::Command::Overview {
+            host,
+            group,
+            user,
+            status,
+            since,
+        }) => {
+            if group.is_none() && user.is_none() {
+                eprintln!("Pass --group or --user");
+                std::process::exit(1);
+            }
+            let host = host.map(Ok).unwrap_or_else(|| default_host(&conf));
+            let host = host.unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            });
+            let filter = gitlabbing::OverviewFilter {
+                host,
+                group,
+                username: user,
+                status,
+                since,
+            };
+            watch_overview(&conf, &filter, &running);
+            return;
+        }
+        _ => (),
+    }
+    match cli.output {
+        Some(_) => watch_ndjson(&conf, target.as_ref(), &running),
+        None => watch_terminal(&conf, target.as_ref(), &running),
     }
-
-    execute!(stdout(), terminal::LeaveAlternateScreen)
-        .expect("Your terminal does not support alternate screens.");
 }
@@ -0,0 +1,267 @@
+/*
+A local GitLab answering with recorded responses, so tests can run
+everything from the first request to the rendered screen.
+
+Screens are compared with snapshots in tests/snapshots. After an
+intended change of the layout, run the tests with UPDATE_SNAPSHOTS=1
+and review the diff of the snapshots.
+*/
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+pub struct MockGitlab {
+    pub host: String, // 127.0.0.1 and the port
+    running: Arc<AtomicBool>,
+}
+
+/// Whether a request of `path` and `query` is answered by `route`.
+/// The query parameters of the route must all be in the request.
+fn matches(route: &str, path: &str, query: &str) -> bool {
+    let (route_path, route_query) = route.split_once('?').unwrap_or((route, ""));
+    route_path == path
+        && route_query
+            .split('&')
+            .filter(|p| !p.is_empty())
+            .all(|p| query.split('&').any(|q| q == p))
+}
+
+fn answer(stream: TcpStream, routes: &[(String, String)]) {
+    stream.set_read_timeout(Some(Duration::from_secs(5))).ok();
+    let mut reader = BufReader::new(&stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).ok();
+    let target = line
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or_default()
+        .to_string();
+    // Only GET requests, which have no body
+    loop {
+        line.clear();
+        match reader.read_line(&mut line) {
+            Ok(n) if n > 0 && !line.trim().is_empty() => (),
+            _ => break,
+        }
+    }
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    let route = routes.iter().find(|(route, _)| matches(route, path, query));
+    let (status, body) = match route {
+        Some((_, body)) => ("200 OK", body.as_str()),
+        None => ("404 Not Found", r#"{"message":"404 Not Found"}"#),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    (&stream).write_all(response.as_bytes()).ok();
+}
+
+impl MockGitlab {
+    /// Answers requests of the paths in `routes` with their JSON, until dropped
+    pub fn start(routes: Vec<(String, String)>) -> MockGitlab {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        let running = Arc::new(AtomicBool::new(true));
+        let still_running = running.clone();
+        thread::spawn(move || {
+            while still_running.load(Ordering::SeqCst) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        stream.set_nonblocking(false).ok();
+                        answer(stream, &routes);
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                        thread::sleep(Duration::from_millis(10));
+                    }
+                    Err(_) => break,
+                }
+            }
+        });
+        MockGitlab { host, running }
+    }
+}
+
+impl Drop for MockGitlab {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Compares with tests/snapshots/`name`, or updates it with UPDATE_SNAPSHOTS=1
+pub fn assert_snapshot(name: &str, actual: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/snapshots")
+        .join(name);
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&path)
+        .unwrap_or_else(|_| panic!("No snapshot {}, run with UPDATE_SNAPSHOTS=1", name));
+    assert!(
+        actual == expected,
+        "{} changed, run with UPDATE_SNAPSHOTS=1 to accept:\n{}",
+        name,
+        actual
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::Configuration;
+    use crate::fixtures::*;
+    use crate::gitlabbing::{get_target_pipelines, FetchCache, Target};
+    use crate::render::render_at;
+    use chrono::{DateTime, Utc};
+
+    const PROJECT: &str = "julianbuettner/gitlab-pipeline-viewer";
+
+    fn recorded(name: &str) -> String {
+        std::fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/fixtures")
+                .join(name),
+        )
+        .unwrap()
+    }
+
+    /// The project with a pipeline on add-gitlab-ci and one on main
+    fn recorded_gitlab() -> MockGitlab {
+        let mut main = pipeline();
+        main.id = gitlab::PipelineId::new(569500001);
+        main.ref_ = Some("main".to_string());
+        main.duration = Some(749);
+        let main = serde_json::to_string(&main).unwrap();
+        let pipeline = recorded("pipeline.json");
+        let api = |path: &str| format!("/api/v4/{}", path);
+        let project = "/api/v4/projects/36813125";
+        MockGitlab::start(vec![
+            (api("user"), recorded("user.json")),
+            (
+                api("projects/julianbuettner%2Fgitlab-pipeline-viewer"),
+                recorded("project.json"),
+            ),
+            (
+                format!("{}/pipelines?ref=add-gitlab-ci", project),
+                format!("[{}]", pipeline),
+            ),
+            (
+                format!("{}/pipelines?ref=main", project),
+                format!("[{}]", main),
+            ),
+            (format!("{}/pipelines/569633322", project), pipeline),
+            (format!("{}/pipelines/569500001", project), main),
+            (
+                format!("{}/pipelines/569633322/jobs", project),
+                recorded("jobs.json"),
+            ),
+            (
+                format!("{}/pipelines/569633322/test_report_summary", project),
+                recorded("test_report_summary.json"),
+            ),
+            (format!("{}/deployments", project), "[]".to_string()),
+        ])
+    }
+
+    fn conf(gitlab: &MockGitlab) -> Configuration {
+        serde_yaml::from_str(&format!(
+            "{{gitlab-tokens: {{'{0}': glpat-test}}, keyring: false, hyperlinks: false}}",
+            gitlab.host
+        ))
+        .unwrap()
+    }
+
+    fn target(gitlab: &MockGitlab, ref_: Option<&str>, pipeline: Option<u64>) -> Target {
+        Target {
+            host: gitlab.host.clone(),
+            project: PROJECT.to_string(),
+            ref_: ref_.map(|r| r.to_string()),
+            pipeline,
+        }
+    }
+
+    fn now() -> DateTime<Utc> {
+        "2022-06-20T18:20:00Z".parse().unwrap()
+    }
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("/api/v4/user", "/api/v4/user", ""));
+        assert!(matches(
+            "/api/v4/projects/1/pipelines?ref=main",
+            "/api/v4/projects/1/pipelines",
+            "page=1&ref=main"
+        ));
+        assert!(!matches(
+            "/api/v4/projects/1/pipelines?ref=main",
+            "/api/v4/projects/1/pipelines",
+            "ref=feature"
+        ));
+        assert!(!matches("/api/v4/user", "/api/v4/users", ""));
+    }
+
+    #[test]
+    fn test_render_snapshots() {
+        let gitlab = recorded_gitlab();
+        let conf = conf(&gitlab);
+        let project = get_target_pipelines(
+            &target(&gitlab, Some("add-gitlab-ci"), None),
+            &conf,
+            &mut FetchCache::new(),
+        )
+        .unwrap();
+        let history = project.history.as_ref().unwrap();
+        assert_eq!(history.entries.len(), 1);
+        assert_eq!(history.target_entries.len(), 1);
+        for width in [80, 120, 160] {
+            let screen = render_at(&project, &conf, width, now());
+            assert_snapshot(&format!("render-{}.txt", width), &screen);
+        }
+    }
+
+    #[test]
+    fn test_single_pipeline() {
+        let gitlab = recorded_gitlab();
+        let conf = conf(&gitlab);
+        let project = get_target_pipelines(
+            &target(&gitlab, None, Some(569633322)),
+            &conf,
+            &mut FetchCache::new(),
+        )
+        .unwrap();
+        assert!(project.history.is_none());
+        assert_snapshot(
+            "render-pipeline-120.txt",
+            &render_at(&project, &conf, 120, now()),
+        );
+    }
+
+    #[test]
+    fn test_missing() {
+        let gitlab = recorded_gitlab();
+        let conf = conf(&gitlab);
+        let unknown = Target {
+            project: "julianbuettner/unknown".to_string(),
+            ..target(&gitlab, None, None)
+        };
+        assert_eq!(
+            get_target_pipelines(&unknown, &conf, &mut FetchCache::new()).unwrap_err(),
+            format!("Could not find julianbuettner/unknown on {}", gitlab.host)
+        );
+        let missing = target(&gitlab, None, Some(1));
+        assert_eq!(
+            get_target_pipelines(&missing, &conf, &mut FetchCache::new()).unwrap_err(),
+            "Could not query details of pipeline 1"
+        );
+    }
+}
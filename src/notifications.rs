@@ -0,0 +1,217 @@
+/*
+Notifications on pipeline and job status transitions.
+
+Each configured way of notifying is a `Notifier`.
+Which transitions are worth a notification is decided
+once in `Notifications::dispatch`, not by the notifiers.
+*/
+use crate::config::{Configuration, NotificationConfig};
+use crate::shell::spawn_shell;
+use crate::transitions::{Subject, Transition};
+use gitlab::StatusState;
+use std::io::{stdout, Write};
+
+pub trait Notifier {
+    fn notify(&mut self, transition: &Transition);
+}
+
+/// Rings the terminal bell.
+pub struct TerminalBell;
+
+impl Notifier for TerminalBell {
+    fn notify(&mut self, _transition: &Transition) {
+        print!("\x07");
+        stdout().flush().ok();
+    }
+}
+
+/// Desktop notification via the OSC 9 (iTerm2, Windows Terminal, ...)
+/// or OSC 777 (urxvt, foot, VTE based) escape sequence.
+pub struct OscNotification {
+    pub code: u16,
+}
+
+impl Notifier for OscNotification {
+    fn notify(&mut self, transition: &Transition) {
+        let title = format!("gpv: {}", transition.project);
+        let body = transition.summary();
+        match self.code {
+            777 => print!("\x1b]777;notify;{};{}\x07", title, body),
+            _ => print!("\x1b]9;{}: {}\x07", title, body),
+        }
+        stdout().flush().ok();
+    }
+}
+
+/// Freedesktop notification via D-Bus.
+pub struct DesktopNotification;
+
+impl Notifier for DesktopNotification {
+    fn notify(&mut self, transition: &Transition) {
+        notify_rust::Notification::new()
+            .appname("gitlab-pipeline-viewer")
+            .summary(&format!("gpv: {}", transition.project))
+            .body(&transition.summary())
+            .show()
+            .ok();
+    }
+}
+
+/// Runs a user command with `sh -c`,
+/// passing the transition in `GPV_*` environment variables.
+pub struct CommandNotification {
+    pub command: String,
+}
+
+impl Notifier for CommandNotification {
+    fn notify(&mut self, transition: &Transition) {
+        spawn_shell(&self.command, transition.env(), None, None).ok();
+    }
+}
+
+pub struct Notifications {
+    notifiers: Vec<Box<dyn Notifier>>,
+    statuses: Vec<StatusState>,
+    jobs: bool,
+}
+
+impl Notifications {
+    pub fn new(notifiers: Vec<Box<dyn Notifier>>, conf: &NotificationConfig) -> Self {
+        Notifications {
+            notifiers,
+            statuses: conf.statuses.clone().unwrap_or_else(|| {
+                vec![
+                    StatusState::Success,
+                    StatusState::Failed,
+                    StatusState::Canceled,
+                ]
+            }),
+            jobs: conf.jobs,
+        }
+    }
+
+    pub fn from_config(conf: &Configuration) -> Self {
+        let notification_conf = conf.notifications.clone().unwrap_or_default();
+        let mut notifiers: Vec<Box<dyn Notifier>> = Vec::new();
+        if notification_conf.bell {
+            notifiers.push(Box::new(TerminalBell));
+        }
+        if let Some(code) = notification_conf.osc {
+            notifiers.push(Box::new(OscNotification { code }));
+        }
+        if notification_conf.desktop {
+            notifiers.push(Box::new(DesktopNotification));
+        }
+        if let Some(command) = &notification_conf.command {
+            notifiers.push(Box::new(CommandNotification {
+                command: command.clone(),
+            }));
+        }
+        Self::new(notifiers, &notification_conf)
+    }
+
+    fn is_wanted(&self, transition: &Transition) -> bool {
+        match transition.subject {
+            Subject::Pipeline => (),
+            Subject::Stage(_) => return false,
+            Subject::Job(_) => {
+                if !self.jobs {
+                    return false;
+                }
+            }
+        }
+        self.statuses.contains(&transition.to)
+    }
+
+    pub fn dispatch(&mut self, transitions: &[Transition]) {
+        for transition in transitions {
+            if !self.is_wanted(transition) {
+                continue;
+            }
+            for notifier in self.notifiers.iter_mut() {
+                notifier.notify(transition);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct RecordingNotifier {
+        events: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl Notifier for RecordingNotifier {
+        fn notify(&mut self, transition: &Transition) {
+            self.events.borrow_mut().push(transition.summary());
+        }
+    }
+
+    fn transition(job: Option<&str>, to: StatusState) -> Transition {
+        Transition {
+            project: "gitlab-pipeline-viewer".to_string(),
+            pipeline_id: 1,
+            pipeline_url: "https://gitlab.com/p/-/pipelines/1".to_string(),
+            ref_: Some("main".to_string()),
+            subject: match job {
+                None => Subject::Pipeline,
+                Some(name) => Subject::Job(crate::transitions::TransitionJob {
+                    id: 2,
+                    name: name.to_string(),
+                    stage: "build".to_string(),
+                    web_url: "https://gitlab.com/p/-/jobs/2".to_string(),
+                }),
+            },
+            from: Some(StatusState::Running),
+            to,
+        }
+    }
+
+    #[test]
+    fn test_dispatch_filters_transitions() {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let recorder = RecordingNotifier {
+            events: events.clone(),
+        };
+        let conf = NotificationConfig {
+            statuses: Some(vec![StatusState::Failed]),
+            jobs: true,
+            ..Default::default()
+        };
+        let mut notifications = Notifications::new(vec![Box::new(recorder)], &conf);
+        notifications.dispatch(&[
+            transition(None, StatusState::Running),
+            transition(Some("cargo-build"), StatusState::Failed),
+            transition(None, StatusState::Failed),
+        ]);
+        assert_eq!(
+            *events.borrow(),
+            vec![
+                "Job cargo-build (build) failed in pipeline 1".to_string(),
+                "Pipeline 1 (main) failed".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dispatch_skips_jobs_by_default() {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let recorder = RecordingNotifier {
+            events: events.clone(),
+        };
+        let mut notifications =
+            Notifications::new(vec![Box::new(recorder)], &NotificationConfig::default());
+        notifications.dispatch(&[
+            transition(Some("cargo-build"), StatusState::Failed),
+            transition(None, StatusState::Success),
+        ]);
+        assert_eq!(
+            *events.borrow(),
+            vec!["Pipeline 1 (main) success".to_string()]
+        );
+    }
+}
@@ -0,0 +1,346 @@
+/*
+Machine readable output.
+
+The structs below are the stable schema of `--output json|yaml|ndjson`.
+They are deliberately decoupled from the gitlab crate types,
+so that updating the crate does not change what scripts see.
+Bump SCHEMA_VERSION on incompatible changes.
+*/
+use crate::gitlabbing::{GitlabProjectPipelines, PipelineJob};
+use crate::test_reports::TestCounts;
+use crate::transitions::Transition;
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use gitlab::{Pipeline, StatusState};
+use serde::Serialize;
+use serde_json::json;
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Json,
+    Yaml,
+    Ndjson,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ArtifactOutput {
+    pub filename: String,
+    pub file_type: String,
+    pub size: u64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct JobOutput {
+    pub id: u64,
+    pub name: String,
+    pub stage: String,
+    pub status: StatusState,
+    pub allow_failure: bool,
+    pub web_url: String,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub duration: Option<f64>,
+    pub queued_duration: Option<f64>,
+    pub tag_list: Vec<String>,
+    pub runner: Option<String>,
+    pub runner_available: Option<bool>,
+    pub coverage: Option<f64>,
+    pub tests: Option<TestCounts>,
+    pub artifacts: Vec<ArtifactOutput>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PipelineOutput {
+    pub id: u64,
+    pub status: StatusState,
+    #[serde(rename = "ref")]
+    pub ref_: Option<String>,
+    pub sha: String,
+    pub web_url: String,
+    pub user: String,
+    pub created_at: Option<DateTime<Utc>>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub duration: Option<u64>,
+    pub coverage: Option<String>,
+    pub jobs: Vec<JobOutput>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ProjectOutput {
+    pub id: u64,
+    pub name: String,
+    pub web_url: String,
+    pub description: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Snapshot {
+    pub schema_version: u32,
+    pub project: ProjectOutput,
+    pub pipelines: Vec<PipelineOutput>,
+}
+
+fn job_output(pipeline_job: &PipelineJob) -> JobOutput {
+    let job = &pipeline_job.job;
+    JobOutput {
+        id: job.id.value(),
+        name: job.name.clone(),
+        stage: job.stage.clone(),
+        status: job.status,
+        allow_failure: job.allow_failure,
+        web_url: job.web_url.clone(),
+        created_at: job.created_at,
+        started_at: job.started_at,
+        finished_at: job.finished_at,
+        duration: job.duration,
+        queued_duration: pipeline_job.queued_duration,
+        tag_list: pipeline_job.tag_list.clone(),
+        runner: job.runner.as_ref().and_then(|r| r.name.clone()),
+        runner_available: pipeline_job.runner_available,
+        coverage: job.coverage,
+        tests: pipeline_job.test_counts.clone(),
+        artifacts: job
+            .artifacts
+            .iter()
+            .map(|a| ArtifactOutput {
+                filename: a.filename.clone(),
+                file_type: a.file_type.clone(),
+                size: a.size,
+            })
+            .collect(),
+    }
+}
+
+fn pipeline_output(pipeline: &Pipeline, jobs: &[PipelineJob]) -> PipelineOutput {
+    PipelineOutput {
+        id: pipeline.id.value(),
+        status: pipeline.status,
+        ref_: pipeline.ref_.clone(),
+        sha: pipeline.sha.value().to_string(),
+        web_url: pipeline.web_url.clone(),
+        user: pipeline.user.username.clone(),
+        created_at: pipeline.created_at,
+        started_at: pipeline.started_at,
+        finished_at: pipeline.finished_at,
+        duration: pipeline.duration,
+        coverage: pipeline.coverage.clone(),
+        jobs: jobs.iter().map(job_output).collect(),
+    }
+}
+
+pub fn snapshot(project: &GitlabProjectPipelines) -> Snapshot {
+    Snapshot {
+        schema_version: SCHEMA_VERSION,
+        project: ProjectOutput {
+            id: project.project_id,
+            name: project.name.clone(),
+            web_url: project.web_url.clone(),
+            description: project.description.clone(),
+        },
+        pipelines: project
+            .pipelines
+            .iter()
+            .map(|(pipeline, jobs)| pipeline_output(pipeline, jobs))
+            .collect(),
+    }
+}
+
+pub fn format_snapshot(project: &GitlabProjectPipelines, format: OutputFormat) -> String {
+    let snapshot = snapshot(project);
+    match format {
+        OutputFormat::Json => serde_json::to_string_pretty(&snapshot).unwrap(),
+        OutputFormat::Yaml => serde_yaml::to_string(&snapshot).unwrap(),
+        OutputFormat::Ndjson => serde_json::to_string(&snapshot).unwrap(),
+    }
+}
+
+/// One line of `--output ndjson` in watch mode.
+pub enum Event<'a> {
+    Snapshot(&'a GitlabProjectPipelines),
+    Diff(&'a [Transition], &'a [serde_json::Value]),
+    Error(&'a str),
+}
+
+pub fn ndjson_line(event: Event, time: DateTime<Utc>) -> String {
+    let value = match event {
+        Event::Snapshot(project) => json!({
+            "type": "snapshot",
+            "time": time,
+            "schema_version": SCHEMA_VERSION,
+            "snapshot": snapshot(project),
+        }),
+        Event::Diff(transitions, pipelines) => json!({
+            "type": "diff",
+            "time": time,
+            "schema_version": SCHEMA_VERSION,
+            "changes": transitions.iter().map(|t| t.to_json()).collect::<Vec<_>>(),
+            "pipelines": pipelines,
+        }),
+        Event::Error(message) => json!({
+            "type": "error",
+            "time": time,
+            "schema_version": SCHEMA_VERSION,
+            "message": message,
+        }),
+    };
+    value.to_string()
+}
+
+/// The fields of `new` which differ from `old`, both JSON objects
+fn changed_fields(
+    old: Option<&serde_json::Value>,
+    new: &serde_json::Value,
+    skip: &str,
+) -> serde_json::Map<String, serde_json::Value> {
+    let mut fields = serde_json::Map::new();
+    for (key, value) in new.as_object().into_iter().flatten() {
+        if key != skip && old.map(|o| &o[key]) != Some(value) {
+            fields.insert(key.clone(), value.clone());
+        }
+    }
+    fields
+}
+
+/// The changed fields of every pipeline and job, as
+/// `{"id": 1, "fields": {..}, "jobs": [{"id": 10, "fields": {..}}]}`.
+/// New jobs, e.g. retries, come with all their fields.
+fn pipeline_changes(
+    old: &[serde_json::Value],
+    new: &[serde_json::Value],
+) -> Vec<serde_json::Value> {
+    let mut changes = Vec::new();
+    for pipeline in new {
+        let previous = old.iter().find(|p| p["id"] == pipeline["id"]);
+        let fields = changed_fields(previous, pipeline, "jobs");
+        let mut jobs = Vec::new();
+        for job in pipeline["jobs"].as_array().into_iter().flatten() {
+            let previous_job = previous
+                .and_then(|p| p["jobs"].as_array())
+                .and_then(|jobs| jobs.iter().find(|j| j["id"] == job["id"]));
+            let job_fields = changed_fields(previous_job, job, "id");
+            if !job_fields.is_empty() {
+                jobs.push(json!({"id": job["id"], "fields": job_fields}));
+            }
+        }
+        if !fields.is_empty() || !jobs.is_empty() {
+            changes.push(json!({"id": pipeline["id"], "fields": fields, "jobs": jobs}));
+        }
+    }
+    changes
+}
+
+/// Keeps track of what was emitted in `--output ndjson` watch mode.
+/// A full snapshot is sent whenever the set of shown pipelines changes,
+/// otherwise a diff with the transitions and changed fields since the
+/// last refresh, and nothing if nothing changed.
+#[derive(Default)]
+pub struct NdjsonEmitter {
+    pipelines: Option<Vec<serde_json::Value>>,
+}
+
+impl NdjsonEmitter {
+    pub fn next_line(
+        &mut self,
+        project: &GitlabProjectPipelines,
+        transitions: &[Transition],
+        time: DateTime<Utc>,
+    ) -> Option<String> {
+        let pipelines: Vec<serde_json::Value> = snapshot(project)
+            .pipelines
+            .iter()
+            .map(|p| serde_json::to_value(p).unwrap())
+            .collect();
+        let ids = |pipelines: &[serde_json::Value]| {
+            pipelines
+                .iter()
+                .map(|p| p["id"].clone())
+                .collect::<Vec<_>>()
+        };
+        let previous = self.pipelines.replace(pipelines.clone());
+        let previous = match previous {
+            Some(previous) if ids(&previous) == ids(&pipelines) => previous,
+            _ => return Some(ndjson_line(Event::Snapshot(project), time)),
+        };
+        let changes = pipeline_changes(&previous, &pipelines);
+        if transitions.is_empty() && changes.is_empty() {
+            return None;
+        }
+        Some(ndjson_line(Event::Diff(transitions, &changes), time))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::*;
+    use crate::transitions::StatusTracker;
+
+    #[test]
+    fn test_snapshot_schema() {
+        let project = project_pipelines(vec![(pipeline(), jobs())]);
+        let value: serde_json::Value =
+            serde_json::from_str(&format_snapshot(&project, OutputFormat::Json)).unwrap();
+        assert_eq!(value["schema_version"], 1);
+        assert_eq!(value["project"]["name"], "gitlab-pipeline-viewer");
+        let pipeline = &value["pipelines"][0];
+        assert_eq!(pipeline["id"], 569633322);
+        assert_eq!(pipeline["status"], "success");
+        assert_eq!(pipeline["ref"], "add-gitlab-ci");
+        assert_eq!(pipeline["duration"], 776);
+        assert_eq!(pipeline["jobs"][0]["name"], "cargo-build");
+        assert_eq!(pipeline["jobs"][0]["runner"], "gitlab-runner");
+        assert_eq!(
+            pipeline["jobs"][0]["artifacts"][0]["filename"],
+            "artifacts.zip"
+        );
+    }
+
+    #[test]
+    fn test_ndjson_emitter() {
+        let time = Utc::now();
+        let mut emitter = NdjsonEmitter::default();
+        let mut tracker = StatusTracker::default();
+
+        let running = project_pipelines(vec![(
+            pipeline_with_status(1, StatusState::Running),
+            vec![job_with_status(10, "build", "build", StatusState::Running)],
+        )]);
+        let line = emitter.next_line(&running, &tracker.update(&running), time);
+        let value: serde_json::Value = serde_json::from_str(&line.unwrap()).unwrap();
+        assert_eq!(value["type"], "snapshot");
+        assert_eq!(
+            emitter.next_line(&running, &tracker.update(&running), time),
+            None
+        );
+
+        let mut done = project_pipelines(vec![(
+            pipeline_with_status(1, StatusState::Success),
+            vec![job_with_status(10, "build", "build", StatusState::Success)],
+        )]);
+        done.pipelines[0].1[0].job.duration = Some(42.0);
+        let line = emitter.next_line(&done, &tracker.update(&done), time);
+        let value: serde_json::Value = serde_json::from_str(&line.unwrap()).unwrap();
+        assert_eq!(value["type"], "diff");
+        assert_eq!(value["changes"][0]["to"], "success");
+        assert_eq!(value["changes"].as_array().unwrap().len(), 3);
+        let pipeline = &value["pipelines"][0];
+        assert_eq!(pipeline["id"], 1);
+        assert_eq!(pipeline["fields"], json!({"status": "success"}));
+        assert_eq!(pipeline["jobs"][0]["id"], 10);
+        assert_eq!(
+            pipeline["jobs"][0]["fields"],
+            json!({"status": "success", "duration": 42.0})
+        );
+
+        let next = project_pipelines(vec![(
+            pipeline_with_status(2, StatusState::Created),
+            vec![],
+        )]);
+        let line = emitter.next_line(&next, &tracker.update(&next), time);
+        assert!(line.unwrap().contains(r#""type":"snapshot""#));
+    }
+}
@@ -0,0 +1,83 @@
+/*
+History list of the pipelines of a ref.
+
+Opened with l from the terminal view. Enter shows the selected
+pipeline in the detailed view, Esc goes back to the latest one.
+*/
+use crate::keys;
+use crossterm::event::{KeyCode, KeyEvent};
+
+const PAGE: usize = 10;
+
+#[derive(Debug, PartialEq)]
+pub enum ListAction {
+    Nothing,
+    Redraw,
+    Select(usize),
+    Close,
+    Quit,
+}
+
+#[derive(Default, Debug, PartialEq)]
+pub struct PipelineListState {
+    pub selected: usize,
+}
+
+impl PipelineListState {
+    pub fn handle_key(&mut self, key: KeyEvent, entries: usize) -> ListAction {
+        if keys::is_quit(&key) {
+            return ListAction::Quit;
+        }
+        let last = entries.saturating_sub(1);
+        match key.code {
+            KeyCode::Char('j') | KeyCode::Down => self.selected = (self.selected + 1).min(last),
+            KeyCode::Char('k') | KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+            KeyCode::PageDown => self.selected = (self.selected + PAGE).min(last),
+            KeyCode::PageUp => self.selected = self.selected.saturating_sub(PAGE),
+            KeyCode::Char('g') | KeyCode::Home => self.selected = 0,
+            KeyCode::Char('G') | KeyCode::End => self.selected = last,
+            KeyCode::Enter if entries > 0 => return ListAction::Select(self.selected),
+            KeyCode::Esc | KeyCode::Backspace | KeyCode::Char('l') => return ListAction::Close,
+            _ => return ListAction::Nothing,
+        }
+        ListAction::Redraw
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crossterm::event::KeyModifiers;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn test_handle_key() {
+        let mut state = PipelineListState::default();
+        assert_eq!(state.handle_key(key(KeyCode::Up), 30), ListAction::Redraw);
+        assert_eq!(state.selected, 0);
+        state.handle_key(key(KeyCode::Char('j')), 30);
+        state.handle_key(key(KeyCode::PageDown), 30);
+        assert_eq!(state.selected, 11);
+        state.handle_key(key(KeyCode::Char('G')), 30);
+        assert_eq!(state.selected, 29);
+        state.handle_key(key(KeyCode::Down), 30);
+        assert_eq!(state.selected, 29);
+
+        assert_eq!(
+            state.handle_key(key(KeyCode::Enter), 30),
+            ListAction::Select(29)
+        );
+        assert_eq!(
+            state.handle_key(key(KeyCode::Enter), 0),
+            ListAction::Nothing
+        );
+        assert_eq!(state.handle_key(key(KeyCode::Esc), 30), ListAction::Close);
+        assert_eq!(
+            state.handle_key(key(KeyCode::Char('q')), 30),
+            ListAction::Quit
+        );
+    }
+}
@@ -0,0 +1,101 @@
+/*
+One line status segment for shell prompts and status bars.
+
+Prompts have to be fast, so this never talks to GitLab.
+It only reads the cache written by running gpv processes.
+*/
+use crate::cache::PromptCache;
+use crate::render::{duration_to_short_string, status_to_emoji};
+use crate::transitions::status_name;
+use chrono::{DateTime, Utc};
+
+pub const DEFAULT_FORMAT: &str = "{icon} #{id} {duration}{stale}";
+
+fn elapsed(cache: &PromptCache, now: DateTime<Utc>) -> Option<f64> {
+    let pipeline = cache.pipeline.as_ref()?;
+    if let Some(d) = pipeline.duration {
+        return Some(d as f64);
+    }
+    let start = pipeline.started_at.or(pipeline.created_at)?;
+    Some((now - start).num_milliseconds() as f64 / 1000.0)
+}
+
+/// The segment for the given cache entry, or an empty string
+/// if there is nothing (trustworthy) to show for `branch`.
+pub fn prompt_segment(
+    cache: Option<&PromptCache>,
+    branch: &str,
+    format: &str,
+    max_age: f64,
+    now: DateTime<Utc>,
+) -> String {
+    let cache = match cache {
+        Some(c) if c.ref_ == branch => c,
+        _ => return "".to_string(),
+    };
+    let pipeline = match &cache.pipeline {
+        None => return "".to_string(),
+        Some(p) => p,
+    };
+    let age = (now - cache.written_at).num_milliseconds() as f64 / 1000.0;
+    let stale = if age > max_age { "?" } else { "" };
+    let duration = elapsed(cache, now)
+        .map(duration_to_short_string)
+        .unwrap_or_default();
+    format
+        .replace("{icon}", status_to_emoji(pipeline.status))
+        .replace("{id}", &pipeline.id.to_string())
+        .replace("{status}", status_name(pipeline.status))
+        .replace("{duration}", &duration)
+        .replace("{ref}", &cache.ref_)
+        .replace("{project}", &cache.project)
+        .replace("{url}", &pipeline.web_url)
+        .replace("{stale}", stale)
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cache::CachedPipeline;
+    use chrono::Duration;
+    use gitlab::StatusState;
+
+    fn cache(written_at: DateTime<Utc>) -> PromptCache {
+        PromptCache {
+            written_at,
+            project: "gitlab-pipeline-viewer".to_string(),
+            ref_: "main".to_string(),
+            pipeline: Some(CachedPipeline {
+                id: 569633322,
+                status: StatusState::Success,
+                web_url: "https://gitlab.com/p/-/pipelines/569633322".to_string(),
+                created_at: None,
+                started_at: None,
+                duration: Some(776),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_prompt_segment() {
+        let now = Utc::now();
+        let fresh = cache(now - Duration::seconds(3));
+        assert_eq!(
+            prompt_segment(Some(&fresh), "main", DEFAULT_FORMAT, 60.0, now),
+            "✅ #569633322 12m"
+        );
+        assert_eq!(
+            prompt_segment(Some(&fresh), "other-branch", DEFAULT_FORMAT, 60.0, now),
+            ""
+        );
+        assert_eq!(prompt_segment(None, "main", DEFAULT_FORMAT, 60.0, now), "");
+
+        let stale = cache(now - Duration::seconds(600));
+        assert_eq!(
+            prompt_segment(Some(&stale), "main", "{status} {ref}{stale}", 60.0, now),
+            "success main?"
+        );
+    }
+}
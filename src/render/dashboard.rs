@@ -0,0 +1,157 @@
+use super::util::{
+    duration_to_short_string, render_columns, status_to_emoji, RenderColumnsAlignment,
+};
+use crate::gitlabbing::GitlabProjectPipelines;
+use crate::transitions::status_name;
+use chrono::Utc;
+use gitlab::StatusState;
+use std::path::PathBuf;
+
+pub struct DashboardEntry {
+    pub path: PathBuf,
+    pub branch: Option<String>,
+    pub result: Result<GitlabProjectPipelines, String>,
+}
+
+impl DashboardEntry {
+    pub fn name(&self) -> String {
+        match &self.result {
+            Ok(project) => project.name.clone(),
+            Err(_) => self
+                .path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| self.path.display().to_string()),
+        }
+    }
+}
+
+/// Status, duration and failing jobs of the latest pipeline
+fn latest_pipeline_cells(project: &GitlabProjectPipelines) -> (String, String, String) {
+    let (pipeline, jobs) = match project.pipelines.first() {
+        None => return ("no pipeline".to_string(), "".to_string(), "".to_string()),
+        Some(v) => v,
+    };
+    let status = format!(
+        "{} {}",
+        status_to_emoji(pipeline.status),
+        status_name(pipeline.status)
+    );
+    let duration = match (pipeline.duration, pipeline.started_at) {
+        (Some(d), _) => duration_to_short_string(d as f64),
+        (None, Some(started_at)) => {
+            duration_to_short_string((Utc::now() - started_at).num_seconds() as f64)
+        }
+        (None, None) => "".to_string(),
+    };
+    let mut failing: Vec<&str> = Vec::new();
+    for job in jobs {
+        let job = &job.job;
+        if job.status == StatusState::Failed
+            && !job.allow_failure
+            && !failing.contains(&job.name.as_str())
+        {
+            failing.push(&job.name);
+        }
+    }
+    (status, duration, failing.join(", "))
+}
+
+pub fn render_dashboard(entries: &[DashboardEntry], selected: usize, width: usize) -> String {
+    let mut columns = vec![
+        vec!["".to_string()],
+        vec!["Project".to_string()],
+        vec!["Branch".to_string()],
+        vec!["Status".to_string()],
+        vec!["Duration".to_string()],
+        vec!["Failing jobs".to_string()],
+    ];
+    for (i, entry) in entries.iter().enumerate() {
+        let (status, duration, failing) = match &entry.result {
+            Ok(project) => latest_pipeline_cells(project),
+            Err(e) => ("error".to_string(), "".to_string(), e.clone()),
+        };
+        let cells = [
+            if i == selected { ">" } else { "" }.to_string(),
+            entry.name(),
+            entry.branch.clone().unwrap_or_default(),
+            status,
+            duration,
+            failing,
+        ];
+        for (column, cell) in columns.iter_mut().zip(cells) {
+            // One line per repository, whatever the content
+            column.push(cell.replace('\n', " "));
+        }
+    }
+
+    let project_width = width / 4;
+    let branch_width = width / 5;
+    let status_width = width * 3 / 20;
+    let duration_width = width / 10;
+    let failing_width =
+        width.saturating_sub(2 + project_width + branch_width + status_width + duration_width);
+
+    let mut overview = render_columns(
+        vec![vec!["====   Dashboard   ====".to_string(), "".to_string()]],
+        vec![width],
+        vec![RenderColumnsAlignment::Center],
+    );
+    overview += &render_columns(
+        columns,
+        vec![
+            2,
+            project_width,
+            branch_width,
+            status_width,
+            duration_width,
+            failing_width,
+        ],
+        (0..6).map(|_| RenderColumnsAlignment::Left).collect(),
+    );
+    overview += "\nj/k select   Enter expand   Esc back   q quit\n";
+    overview
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::*;
+
+    #[test]
+    fn test_render_dashboard() {
+        let mut failing_jobs = jobs();
+        failing_jobs[1].job.status = StatusState::Failed;
+        let entries = vec![
+            DashboardEntry {
+                path: PathBuf::from("/src/gpv"),
+                branch: Some("add-gitlab-ci".to_string()),
+                result: Ok(project_pipelines(vec![(pipeline(), jobs())])),
+            },
+            DashboardEntry {
+                path: PathBuf::from("/src/other"),
+                branch: Some("main".to_string()),
+                result: Ok(project_pipelines(vec![(
+                    pipeline_with_status(2, StatusState::Failed),
+                    failing_jobs,
+                )])),
+            },
+            DashboardEntry {
+                path: PathBuf::from("/src/broken"),
+                branch: None,
+                result: Err("Could not get remote \"origin\"".to_string()),
+            },
+        ];
+        let rendering = render_dashboard(&entries, 1, 100);
+        let lines: Vec<&str> = rendering.lines().collect();
+        assert!(lines[2].starts_with("  Project"));
+        assert!(lines[3].starts_with("  gitlab-pipeline-viewer"));
+        assert!(lines[3].contains("success"));
+        assert!(lines[3].contains("12m"));
+        assert!(lines[4].starts_with("> gitlab-pipeline-viewer"));
+        assert!(lines[4].contains("cargo-format"));
+        assert!(lines[5].starts_with("  broken"));
+        assert!(lines[5].contains("Could not get remote"));
+        assert_eq!(lines[2].len(), 100);
+    }
+}
@@ -0,0 +1,163 @@
+use super::util::{
+    center_truncate, duration_to_short_string, hyperlink, render_columns, status_to_emoji,
+    RenderColumnsAlignment,
+};
+use crate::deployments::Deployment;
+use crate::emoji::PAUSE_TOGGLE;
+use chrono::{DateTime, Utc};
+use gitlab::StatusState;
+
+fn deployment_status(status: &str) -> String {
+    let state = match status {
+        "created" => Some(StatusState::Created),
+        "running" => Some(StatusState::Running),
+        "success" => Some(StatusState::Success),
+        "failed" => Some(StatusState::Failed),
+        "canceled" => Some(StatusState::Canceled),
+        "skipped" => Some(StatusState::Skipped),
+        // blocked waits for an approval, much like a manual job
+        _ => None,
+    };
+    let emoji = state.map(status_to_emoji).unwrap_or(PAUSE_TOGGLE);
+    format!("{} {}", emoji, status)
+}
+
+fn title(width: usize) -> String {
+    render_columns(
+        vec![vec!["====   Deployments   ====".to_string()]],
+        vec![width],
+        vec![RenderColumnsAlignment::Center],
+    )
+}
+
+/// One line per environment: URL, deploy status and who deployed when
+pub fn render_deployments(
+    deployments: &[Deployment],
+    error: Option<&str>,
+    width: usize,
+    links: bool,
+    now: DateTime<Utc>,
+) -> String {
+    if let Some(error) = error {
+        return format!(
+            "{}{}\n",
+            title(width),
+            center_truncate(&error.to_string(), width)
+        );
+    }
+    if deployments.is_empty() {
+        return String::new();
+    }
+    let mut columns = vec![
+        vec!["Environment".to_string()],
+        vec!["URL".to_string()],
+        vec!["Status".to_string()],
+        vec!["Deployed".to_string()],
+        vec!["Job".to_string()],
+    ];
+    for deployment in deployments {
+        let environment = &deployment.environment;
+        let mut deployed = deployment
+            .user
+            .as_ref()
+            .map(|u| format!("by {}", u.username))
+            .unwrap_or_default();
+        if let Some(created_at) = deployment.created_at {
+            deployed += &format!(
+                " {} ago",
+                duration_to_short_string((now - created_at).num_seconds() as f64)
+            );
+        }
+        let cells = [
+            environment.name.clone(),
+            environment
+                .external_url
+                .as_ref()
+                .map(|url| hyperlink(url, url, links))
+                .unwrap_or_default(),
+            deployment_status(&deployment.status),
+            deployed.trim().to_string(),
+            deployment
+                .deployable
+                .as_ref()
+                .map(|job| job.name.clone())
+                .unwrap_or_default(),
+        ];
+        for (column, cell) in columns.iter_mut().zip(cells) {
+            column.push(cell);
+        }
+    }
+
+    let environment_width = width / 5;
+    let status_width = width * 3 / 20;
+    let deployed_width = width / 5;
+    let job_width = width * 3 / 20;
+    let url_width = width - environment_width - status_width - deployed_width - job_width;
+    let mut overview = title(width);
+    overview += &render_columns(
+        columns,
+        vec![
+            environment_width,
+            url_width,
+            status_width,
+            deployed_width,
+            job_width,
+        ],
+        (0..5).map(|_| RenderColumnsAlignment::Left).collect(),
+    );
+    overview
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::deployments::{DeploymentEnvironment, DeploymentUser};
+
+    #[test]
+    fn test_render_deployments() {
+        let created_at: DateTime<Utc> = "2024-05-01T12:00:00Z".parse().unwrap();
+        let deployment = Deployment {
+            id: 1,
+            status: "blocked".to_string(),
+            ref_: "main".to_string(),
+            sha: "0ab8f3c2e4d5".to_string(),
+            created_at: Some(created_at),
+            user: Some(DeploymentUser {
+                username: "julian".to_string(),
+            }),
+            environment: DeploymentEnvironment {
+                id: 1,
+                name: "production".to_string(),
+                external_url: Some("https://gpv.example.com".to_string()),
+            },
+            deployable: None,
+        };
+        let rendered = render_deployments(
+            &[deployment],
+            None,
+            100,
+            false,
+            created_at + chrono::Duration::minutes(12),
+        );
+        let line = rendered.lines().nth(2).unwrap();
+        assert!(line.starts_with("production"));
+        assert!(line.contains("https://gpv.example.com"));
+        assert!(line.contains(&format!("{} blocked", PAUSE_TOGGLE)));
+        assert!(line.contains("by julian 12m ago"));
+
+        assert_eq!(deployment_status("success"), "✅ success");
+        assert_eq!(
+            deployment_status("weird"),
+            format!("{} weird", PAUSE_TOGGLE)
+        );
+        assert_eq!(render_deployments(&[], None, 100, false, created_at), "");
+        let failed = render_deployments(
+            &[],
+            Some("Could not get deployments"),
+            100,
+            false,
+            created_at,
+        );
+        assert!(failed.contains("Could not get deployments"));
+    }
+}
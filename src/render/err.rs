@@ -1,7 +1,6 @@
-use super::util::{center_truncate, clear_screen, get_terminal_width};
+use super::util::{center_truncate, get_terminal_width};
 
-pub fn render_error(err: String) {
+pub fn render_error(err: &str) -> String {
     let width = get_terminal_width();
-    clear_screen();
-    println!("Error\n{}", center_truncate(&err, width));
+    format!("Error\n{}\n", center_truncate(&err.to_string(), width))
 }
@@ -1,17 +1,17 @@
-use super::util::{render_columns, RenderColumnsAlignment};
+use super::util::{hyperlink, render_columns, RenderColumnsAlignment};
 use crate::gitlabbing::GitlabProjectPipelines;
 
-pub fn render_header(project: &GitlabProjectPipelines, width: usize) -> String {
+pub fn render_header(project: &GitlabProjectPipelines, width: usize, links: bool) -> String {
     let mut left = Vec::new();
     left.push(format!("====   {}   ====", project.name));
-    left.push(project.web_url.clone());
+    left.push(hyperlink(&project.web_url, &project.web_url, links));
     if project.description.is_some() {
         left.push(project.description.clone().unwrap());
     }
     if project.pipelines.is_empty() {
-        left.push(format!(
-            "There are no pipelines runing for the remote head of the current branch"
-        ));
+        left.push(
+            "There are no pipelines runing for the remote head of the current branch".to_string(),
+        );
     }
     left.push("".to_string());
     render_columns(
@@ -0,0 +1,135 @@
+use super::util::{duration_to_short_string, render_columns, RenderColumnsAlignment};
+use crate::emoji::RED_EXCLAMATION;
+use crate::history::History;
+
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// One bar per value, scaled between the smallest and the largest.
+/// Missing values are left blank.
+pub fn sparkline(values: &[Option<f64>]) -> String {
+    let known = values.iter().flatten();
+    let min = known.clone().cloned().fold(f64::INFINITY, f64::min);
+    let max = known.cloned().fold(f64::NEG_INFINITY, f64::max);
+    values
+        .iter()
+        .map(|value| match value {
+            None => ' ',
+            Some(_) if max <= min => BARS[BARS.len() / 2],
+            Some(v) => BARS[((v - min) / (max - min) * (BARS.len() - 1) as f64).round() as usize],
+        })
+        .collect()
+}
+
+fn history_line(
+    title: &str,
+    values: &[Option<f64>],
+    latest: String,
+    difference: Option<(String, bool)>,
+    baseline: &str,
+) -> String {
+    let mut line = format!("{} {} {}", title, sparkline(values), latest);
+    if let Some((difference, regression)) = difference {
+        line += &format!(" ({} vs {})", difference, baseline);
+        if regression {
+            line += &format!(" {}", RED_EXCLAMATION);
+        }
+    }
+    line
+}
+
+/// Duration and coverage of the last pipelines of the ref, with
+/// regressions against the target branch marked. Empty without history.
+pub fn render_history(
+    history: &History,
+    duration_increase: f64,
+    coverage_drop: f64,
+    width: usize,
+) -> String {
+    let baseline = history.target_ref.as_deref().unwrap_or("earlier");
+    let mut lines = Vec::new();
+
+    let durations: Vec<Option<f64>> = history
+        .entries
+        .iter()
+        .map(|e| e.duration.map(|d| d as f64))
+        .collect();
+    if let Some(Some(latest)) = durations.last() {
+        let difference = history.duration(duration_increase).map(|c| {
+            let percent = (c.latest - c.baseline) / c.baseline.max(1.0) * 100.0;
+            (format!("{:+.0}%", percent), c.regression)
+        });
+        lines.push(history_line(
+            "Duration",
+            &durations,
+            duration_to_short_string(*latest),
+            difference,
+            baseline,
+        ));
+    }
+
+    let coverages: Vec<Option<f64>> = history.entries.iter().map(|e| e.coverage).collect();
+    if let Some(Some(latest)) = coverages.last() {
+        let difference = history
+            .coverage(coverage_drop)
+            .map(|c| (format!("{:+.1}", c.latest - c.baseline), c.regression));
+        lines.push(history_line(
+            "Coverage",
+            &coverages,
+            format!("{:.1}%", latest),
+            difference,
+            baseline,
+        ));
+    }
+
+    if lines.is_empty() {
+        return String::new();
+    }
+    lines.push("".to_string());
+    render_columns(
+        vec![lines],
+        vec![width],
+        vec![RenderColumnsAlignment::Center],
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::history::HistoryEntry;
+    use gitlab::StatusState;
+
+    #[test]
+    fn test_sparkline() {
+        assert_eq!(sparkline(&[Some(1.0), Some(8.0), None, Some(4.5)]), "▁█ ▅");
+        assert_eq!(sparkline(&[Some(3.0), Some(3.0)]), "▅▅");
+        assert_eq!(sparkline(&[]), "");
+    }
+
+    #[test]
+    fn test_render_history() {
+        let entry = |id, duration, coverage| HistoryEntry {
+            id,
+            status: StatusState::Success,
+            duration: Some(duration),
+            coverage,
+        };
+        let history = History {
+            ref_: "feature".to_string(),
+            entries: vec![entry(1, 600, None), entry(2, 840, None)],
+            target_ref: Some("main".to_string()),
+            target_entries: vec![entry(3, 600, None)],
+        };
+        let rendered = render_history(&history, 20.0, 1.0, 40);
+        assert!(rendered.contains(&format!(
+            "Duration ▁█ 14m (+40% vs main) {}",
+            RED_EXCLAMATION
+        )));
+        assert!(!rendered.contains("Coverage"));
+
+        let empty = History {
+            entries: vec![],
+            ..history
+        };
+        assert_eq!(render_history(&empty, 20.0, 1.0, 40), "");
+    }
+}
@@ -1,6 +1,11 @@
-use super::util::{duration_to_string, render_columns, RenderColumnsAlignment};
+use super::util::{
+    center_truncate, duration_to_string, hyperlink, render_columns, status_to_emoji,
+    RenderColumnsAlignment,
+};
 use crate::emoji::*;
-use gitlab::{Job, Runner, StatusState};
+use crate::gitlabbing::PipelineJob;
+use chrono::{DateTime, Utc};
+use gitlab::{Runner, StatusState};
 use std::collections::HashMap;
 /*
 Graphical logic and generation is done here.
@@ -21,10 +26,10 @@ fn get_runner_name_text(runner: &Option<Runner>) -> String {
     }
 }
 
-fn get_stages(jobs: &Vec<Job>) -> Vec<String> {
+fn get_stages(jobs: &[PipelineJob]) -> Vec<String> {
     let mut stages = Vec::new();
     for job in jobs.iter().rev() {
-        let stage_name = &job.stage;
+        let stage_name = &job.job.stage;
         if !stages.contains(stage_name) {
             stages.push(stage_name.clone());
         }
@@ -32,27 +37,50 @@ fn get_stages(jobs: &Vec<Job>) -> Vec<String> {
     stages
 }
 
-fn get_job_lines(job: &Job) -> Vec<String> {
-    let mut symbol = match job.status {
-        StatusState::Created => PAUSE,
-        StatusState::WaitingForResource => PAUSE,
-        StatusState::Preparing => PAUSE,
-        StatusState::Pending => PAUSE,
-        StatusState::Running => PLAY,
-        StatusState::Success => GREEN_CHECK,
-        StatusState::Failed => FAILED,
-        StatusState::Canceled => STOP,
-        StatusState::Skipped => FAST_FORWARD,
-        StatusState::Manual => PAUSE_TOGGLE,
-        StatusState::Scheduled => ALARM,
-    };
-    if job.status == StatusState::Failed && job.allow_failure == true {
+fn get_queue_lines(
+    queued_seconds: f64,
+    tag_list: &[String],
+    runner_available: Option<bool>,
+    queue_warning: f64,
+) -> Vec<String> {
+    let mut lines = Vec::new();
+    if queued_seconds > queue_warning {
+        lines.push(format!(
+            "{} queued for {}",
+            RED_EXCLAMATION,
+            duration_to_string(queued_seconds)
+        ));
+    } else {
+        lines.push(format!("queued for {}", duration_to_string(queued_seconds)));
+    }
+    if !tag_list.is_empty() {
+        lines.push(format!("Tags: {}", tag_list.join(", ")));
+    }
+    if runner_available == Some(false) {
+        lines.push("No online runner matches".to_string());
+    }
+    lines
+}
+
+fn get_job_lines(
+    pipeline_job: &PipelineJob,
+    queue_warning: f64,
+    links: bool,
+    now: DateTime<Utc>,
+) -> Vec<String> {
+    let job = &pipeline_job.job;
+    let mut symbol = status_to_emoji(job.status);
+    if job.status == StatusState::Failed && job.allow_failure {
         symbol = GREY_EXCLAMATION;
     }
     let mut column = Vec::new();
 
     column.push("".to_string());
-    column.push(format!("{}  {}", symbol, job.name));
+    column.push(format!(
+        "{}  {}",
+        symbol,
+        hyperlink(&job.web_url, &job.name, links)
+    ));
 
     column.push(format!(
         "{} {}",
@@ -60,10 +88,25 @@ fn get_job_lines(job: &Job) -> Vec<String> {
         get_runner_name_text(&job.runner)
     ));
 
+    if pipeline_job.is_queued() {
+        let queued_seconds = pipeline_job
+            .queued_duration
+            .unwrap_or_else(|| (now - job.created_at).num_milliseconds() as f64 / 1000.0);
+        column.append(&mut get_queue_lines(
+            queued_seconds,
+            &pipeline_job.tag_list,
+            pipeline_job.runner_available,
+            queue_warning,
+        ));
+    }
+
     match job.coverage {
         None => (),
         Some(v) => column.push(format!("Coverage: {}%", v)),
     }
+    if let Some(counts) = &pipeline_job.test_counts {
+        column.push(counts.summary());
+    }
 
     for artifact in job
         .artifacts
@@ -71,15 +114,29 @@ fn get_job_lines(job: &Job) -> Vec<String> {
         .filter(|a| a.filename != "job.log")
         .filter(|a| a.filename != "metadata.gz")
     {
-        column.push(format!("Artifact: {}", artifact.filename));
+        let browse_url = format!("{}/artifacts/browse", job.web_url);
+        column.push(format!(
+            "Artifact: {}",
+            hyperlink(&browse_url, &artifact.filename, links)
+        ));
     }
 
     column
 }
 
-pub fn generate_job_overview(jobs: &Vec<Job>, width: usize) -> String {
+pub fn generate_job_overview(
+    jobs: &[PipelineJob],
+    width: usize,
+    queue_warning: f64,
+    links: bool,
+    now: DateTime<Utc>,
+) -> String {
     let stages = get_stages(jobs);
-    let width_per_stage = width as usize / stages.len() - 1;
+    if stages.is_empty() {
+        // E.g. the CI config has errors
+        return format!("{}\n", center_truncate(&"No jobs".to_string(), width));
+    }
+    let width_per_stage = width / stages.len() - 1;
 
     let mut lines_per_stage: HashMap<String, Vec<String>> = HashMap::new();
     for stage in &stages {
@@ -88,9 +145,9 @@ pub fn generate_job_overview(jobs: &Vec<Job>, width: usize) -> String {
 
     for job in jobs.iter().rev() {
         lines_per_stage
-            .get_mut(&job.stage)
+            .get_mut(&job.job.stage)
             .unwrap()
-            .append(&mut get_job_lines(job));
+            .append(&mut get_job_lines(job, queue_warning, links, now));
     }
 
     let mut columns = Vec::new();
@@ -107,3 +164,38 @@ pub fn generate_job_overview(jobs: &Vec<Job>, width: usize) -> String {
         .collect::<Vec<usize>>();
     render_columns(columns, widths, alignments)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Utc;
+
+    #[test]
+    fn test_no_jobs() {
+        assert_eq!(
+            generate_job_overview(&[], 11, 300.0, false, Utc::now()),
+            "  No jobs  \n"
+        );
+    }
+
+    #[test]
+    fn test_queue_lines() {
+        assert_eq!(
+            get_queue_lines(42.0, &[], None, 300.0),
+            vec!["queued for 42 seconds".to_string()],
+        );
+        assert_eq!(
+            get_queue_lines(
+                400.0,
+                &["docker".to_string(), "arm64".to_string()],
+                Some(false),
+                300.0
+            ),
+            vec![
+                format!("{} queued for 6 minutes 40 seconds", RED_EXCLAMATION),
+                "Tags: docker, arm64".to_string(),
+                "No online runner matches".to_string(),
+            ],
+        );
+    }
+}
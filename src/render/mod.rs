@@ -1,20 +1,97 @@
+mod dashboard;
+mod deployments;
 mod err;
 mod header;
+mod history;
 mod jobs;
 mod pipeline;
+mod pipeline_list;
 mod util;
-use crate::gitlabbing::GitlabProjectPipelines;
+use crate::config::Configuration;
+use crate::gitlabbing::{GitlabProjectPipelines, OverviewFilter, OverviewPipeline};
+use crate::transitions::status_name;
+use chrono::{DateTime, Utc};
+pub use dashboard::{render_dashboard, DashboardEntry};
 pub use err::render_error;
 use jobs::generate_job_overview;
-pub use util::clear_screen;
+pub use pipeline_list::render_pipeline_list;
+pub use util::{
+    duration_to_short_string, get_terminal_height, get_terminal_width, print_screen,
+    status_to_emoji,
+};
 
-pub fn render(gitlab_project_pipelines: &GitlabProjectPipelines) -> String {
-    let width = util::get_terminal_width();
+pub fn render(gitlab_project_pipelines: &GitlabProjectPipelines, conf: &Configuration) -> String {
+    render_at(
+        gitlab_project_pipelines,
+        conf,
+        util::get_terminal_width(),
+        chrono::Utc::now(),
+    )
+}
 
-    let mut overview = header::render_header(gitlab_project_pipelines, width);
+/// The screen for a terminal `width` columns wide, with ages relative to `now`
+pub fn render_at(
+    gitlab_project_pipelines: &GitlabProjectPipelines,
+    conf: &Configuration,
+    width: usize,
+    now: DateTime<Utc>,
+) -> String {
+    let queue_warning = conf.queue_warning.unwrap_or(300.0) as f64;
+    let links = conf.hyperlinks.unwrap_or(true);
+
+    let mut overview = header::render_header(gitlab_project_pipelines, width, links);
+    if let Some(history) = &gitlab_project_pipelines.history {
+        let history_conf = conf.history.clone().unwrap_or_default();
+        overview += &history::render_history(
+            history,
+            history_conf.duration_increase.unwrap_or(20.0),
+            history_conf.coverage_drop.unwrap_or(1.0),
+            width,
+        );
+    }
     for (pip, jobs) in &gitlab_project_pipelines.pipelines {
-        overview += &pipeline::generate_pipeline_overview(pip, width as usize);
-        overview += &generate_job_overview(&jobs, width as usize);
+        overview += &pipeline::generate_pipeline_overview(pip, width, links, now);
+        overview += &generate_job_overview(jobs, width, queue_warning, links, now);
+    }
+    overview += &deployments::render_deployments(
+        &gitlab_project_pipelines.deployments,
+        gitlab_project_pipelines.deployments_error.as_deref(),
+        width,
+        links,
+        now,
+    );
+    overview
+}
+
+pub fn render_overview(filter: &OverviewFilter, pipelines: &[OverviewPipeline]) -> String {
+    let width = util::get_terminal_width();
+    let mut title = "Pipelines".to_string();
+    if let Some(group) = &filter.group {
+        title += &format!(" in {}", group);
+    }
+    if let Some(username) = &filter.username {
+        title += &format!(" by {}", username);
+    }
+    if let Some(status) = filter.status {
+        title += &format!(", {} only", status_name(status));
+    }
+    if let Some(since) = filter.since {
+        let since = duration_to_short_string(since.num_seconds() as f64);
+        title += &format!(", last {}", since);
+    }
+    let mut header = vec![format!("====   {}   ====", title), filter.host.clone()];
+    if pipelines.is_empty() {
+        header.push("No pipelines found".to_string());
+    }
+    header.push("".to_string());
+
+    let mut overview = util::render_columns(
+        vec![header],
+        vec![width],
+        vec![util::RenderColumnsAlignment::Center],
+    );
+    if !pipelines.is_empty() {
+        overview += &pipeline::generate_pipeline_rows(pipelines, width, chrono::Utc::now());
     }
     overview
 }
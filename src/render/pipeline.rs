@@ -1,16 +1,25 @@
-use super::util::{duration_to_string, render_columns, status_to_emoji, RenderColumnsAlignment};
-use chrono::Utc;
+use super::util::{
+    duration_to_short_string, duration_to_string, hyperlink, render_columns, status_to_emoji,
+    RenderColumnsAlignment,
+};
+use crate::gitlabbing::OverviewPipeline;
+use crate::transitions::status_name;
+use chrono::{DateTime, Utc};
 use gitlab::Pipeline;
 
-pub fn generate_pipeline_overview(pipeline: &Pipeline, width: usize) -> String {
+pub fn generate_pipeline_overview(
+    pipeline: &Pipeline,
+    width: usize,
+    links: bool,
+    now: DateTime<Utc>,
+) -> String {
     let mut pipeline_col = vec![];
 
     pipeline_col.push(format!("====   Pipeline {}   ====", pipeline.id));
-    pipeline_col.push(pipeline.web_url.clone());
+    pipeline_col.push(hyperlink(&pipeline.web_url, &pipeline.web_url, links));
 
-    if pipeline.created_at.is_some() {
-        let now = Utc::now();
-        let delta = now - pipeline.created_at.unwrap();
+    if let Some(created_at) = pipeline.created_at {
+        let delta = now - created_at;
         let delta_ms = delta.num_milliseconds();
         pipeline_col.push(format!(
             "by {} {} ago",
@@ -59,3 +68,89 @@ pub fn generate_pipeline_overview(pipeline: &Pipeline, width: usize) -> String {
         ],
     )
 }
+
+/// One line per pipeline: status, project, ref, id and last update.
+pub fn generate_pipeline_rows(
+    pipelines: &[OverviewPipeline],
+    width: usize,
+    now: DateTime<Utc>,
+) -> String {
+    let mut columns = vec![
+        vec!["Status".to_string()],
+        vec!["Project".to_string()],
+        vec!["Ref".to_string()],
+        vec!["Pipeline".to_string()],
+        vec!["Updated".to_string()],
+    ];
+    for p in pipelines {
+        let pipeline = &p.pipeline;
+        let updated = match pipeline.updated_at.or(pipeline.created_at) {
+            None => "".to_string(),
+            Some(t) => format!(
+                "{} ago",
+                duration_to_short_string((now - t).num_seconds() as f64)
+            ),
+        };
+        let cells = [
+            format!(
+                "{} {}",
+                status_to_emoji(pipeline.status),
+                status_name(pipeline.status)
+            ),
+            p.project.clone(),
+            pipeline.ref_.clone().unwrap_or_default(),
+            format!("#{}", pipeline.id.value()),
+            updated,
+        ];
+        for (column, cell) in columns.iter_mut().zip(cells) {
+            column.push(cell);
+        }
+    }
+    let status_width = width * 3 / 20;
+    let id_width = width / 8;
+    let updated_width = width / 10;
+    let ref_width = width / 5;
+    let project_width = width - status_width - id_width - updated_width - ref_width;
+    render_columns(
+        columns,
+        vec![
+            status_width,
+            project_width,
+            ref_width,
+            id_width,
+            updated_width,
+        ],
+        (0..5).map(|_| RenderColumnsAlignment::Left).collect(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::*;
+    use gitlab::PipelineBasic;
+
+    #[test]
+    fn test_pipeline_rows() {
+        let pipeline = pipeline();
+        let basic: PipelineBasic =
+            serde_json::from_value(serde_json::to_value(&pipeline).unwrap()).unwrap();
+        let now = pipeline.updated_at.unwrap() + chrono::Duration::minutes(3);
+        let rows = generate_pipeline_rows(
+            &[OverviewPipeline {
+                project: "julianbuettner/gitlab-pipeline-viewer".to_string(),
+                pipeline: basic,
+            }],
+            100,
+            now,
+        );
+        let lines: Vec<&str> = rows.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("Status"));
+        assert!(lines[1].contains("success"));
+        assert!(lines[1].contains("julianbuettner/gitlab-pipeline-viewer"));
+        assert!(lines[1].contains("add-gitlab-ci"));
+        assert!(lines[1].contains("#569633322"));
+        assert!(lines[1].contains("3m ago"));
+    }
+}
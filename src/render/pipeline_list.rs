@@ -0,0 +1,161 @@
+use super::util::{
+    duration_to_short_string, render_columns, status_to_emoji, RenderColumnsAlignment,
+};
+use crate::gitlabbing::PipelineList;
+use crate::transitions::status_name;
+use chrono::{DateTime, Utc};
+
+/// What triggered a pipeline, e.g. "push" or "merge request"
+fn source_name(source: Option<&str>) -> String {
+    match source {
+        None => "".to_string(),
+        Some("merge_request_event") => "merge request".to_string(),
+        Some("external_pull_request_event") => "pull request".to_string(),
+        Some("parent_pipeline") => "parent".to_string(),
+        Some(other) => other.replace('_', " "),
+    }
+}
+
+/// One line per pipeline of the ref. With a selection only the rows
+/// fitting into `height` lines are shown, scrolled to the selected one.
+pub fn render_pipeline_list(
+    list: &PipelineList,
+    selected: Option<usize>,
+    width: usize,
+    height: usize,
+    now: DateTime<Utc>,
+) -> String {
+    let mut overview = render_columns(
+        vec![vec![
+            format!("====   Pipelines of {}   ====", list.ref_),
+            if list.pipelines.is_empty() {
+                "No pipelines found".to_string()
+            } else {
+                "".to_string()
+            },
+        ]],
+        vec![width],
+        vec![RenderColumnsAlignment::Center],
+    );
+
+    // Title, column names and help take 5 lines
+    let rows = height.saturating_sub(5).max(1);
+    let (skip, take) = match selected {
+        Some(selected) => (selected.saturating_sub(rows - 1), rows),
+        None => (0, list.pipelines.len()),
+    };
+
+    let mut columns = vec![
+        vec!["".to_string()],
+        vec!["Status".to_string()],
+        vec!["Pipeline".to_string()],
+        vec!["Source".to_string()],
+        vec!["Author".to_string()],
+        vec!["Commit".to_string()],
+        vec!["Created".to_string()],
+    ];
+    for (i, pipeline) in list.pipelines.iter().enumerate().skip(skip).take(take) {
+        let created = match pipeline.created_at {
+            None => "".to_string(),
+            Some(t) => format!(
+                "{} ago",
+                duration_to_short_string((now - t).num_seconds() as f64)
+            ),
+        };
+        let cells = [
+            if Some(i) == selected { ">" } else { "" }.to_string(),
+            format!(
+                "{} {}",
+                status_to_emoji(pipeline.status),
+                status_name(pipeline.status)
+            ),
+            format!("#{}", pipeline.id),
+            source_name(pipeline.source.as_deref()),
+            pipeline.author.clone().unwrap_or_default(),
+            pipeline.sha.chars().take(8).collect(),
+            created,
+        ];
+        for (column, cell) in columns.iter_mut().zip(cells) {
+            column.push(cell);
+        }
+    }
+
+    let status_width = width * 3 / 20;
+    let id_width = width / 8;
+    let source_width = width * 3 / 20;
+    let commit_width = width / 8;
+    let created_width = width / 8;
+    let author_width = width
+        .saturating_sub(2 + status_width + id_width + source_width + commit_width + created_width);
+    overview += &render_columns(
+        columns,
+        vec![
+            2,
+            status_width,
+            id_width,
+            source_width,
+            author_width,
+            commit_width,
+            created_width,
+        ],
+        (0..7).map(|_| RenderColumnsAlignment::Left).collect(),
+    );
+    if selected.is_some() {
+        overview += "\nj/k select   Enter show   Esc back   q quit\n";
+    }
+    overview
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gitlabbing::PipelineSummary;
+    use gitlab::StatusState;
+
+    fn list() -> PipelineList {
+        let created_at: DateTime<Utc> = "2024-05-01T12:00:00Z".parse().unwrap();
+        PipelineList {
+            ref_: "main".to_string(),
+            pipelines: (0..10)
+                .map(|i| PipelineSummary {
+                    id: 100 - i,
+                    sha: format!("{}ab8f3c2e4d5", i),
+                    status: StatusState::Success,
+                    source: Some("merge_request_event".to_string()),
+                    created_at: Some(created_at - chrono::Duration::hours(i as i64)),
+                    web_url: format!("https://gitlab.com/gpv/-/pipelines/{}", 100 - i),
+                    author: Some("julian".to_string()),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_render_pipeline_list() {
+        let list = list();
+        let now = list.pipelines[0].created_at.unwrap() + chrono::Duration::minutes(5);
+        let all = render_pipeline_list(&list, None, 100, 8, now);
+        assert!(all.contains("#91"));
+        let first = all.lines().nth(3).unwrap();
+        assert!(first.contains("#100"));
+        assert!(first.contains("merge request"));
+        assert!(first.contains("julian"));
+        assert!(first.contains("0ab8f3c2 "));
+        assert!(first.contains("5m ago"));
+
+        // 3 rows fit, scrolled so that the selected one is the last
+        let scrolled = render_pipeline_list(&list, Some(5), 100, 8, now);
+        assert!(!scrolled.contains("#98"));
+        assert!(scrolled.contains("#97"));
+        assert!(scrolled.contains("> ✅ success"));
+        assert!(!scrolled.contains("#94"));
+    }
+
+    #[test]
+    fn test_source_name() {
+        assert_eq!(source_name(Some("push")), "push");
+        assert_eq!(source_name(Some("merge_request_event")), "merge request");
+        assert_eq!(source_name(Some("chat_ops")), "chat ops");
+        assert_eq!(source_name(None), "");
+    }
+}
@@ -1,4 +1,4 @@
-use std::io::stdout;
+use std::io::{stdout, Write};
 
 use crossterm::{cursor, execute, terminal};
 use gitlab::StatusState;
@@ -12,14 +12,22 @@ pub fn clear_screen() {
         .expect("Your terminal does not support moving cursors.");
 }
 
+/// Replaces the screen content. Works in raw mode too,
+/// which does not return the carriage on line feeds.
+pub fn print_screen(text: &str) {
+    clear_screen();
+    print!("{}", text.replace('\n', "\r\n"));
+    stdout().flush().ok();
+}
+
 fn flip<T: Clone>(v: Vec<Vec<T>>) -> Vec<Vec<T>> {
     let height = v.len();
     let width = v[0].len();
     let mut res = Vec::new();
     for i in 0..width {
-        let mut inner = Vec::new();
-        for j in 0..height {
-            inner.push(v[j][i].clone());
+        let mut inner = Vec::with_capacity(height);
+        for row in v.iter() {
+            inner.push(row[i].clone());
         }
         res.push(inner)
     }
@@ -54,21 +62,43 @@ pub fn duration_to_string(d: f64) -> String {
     parts.join(" ")
 }
 
+/// Compact variant for status bars, e.g. "12m" or "1h3m".
+pub fn duration_to_short_string(d: f64) -> String {
+    let duration_seconds: u32 = d as u32;
+    let hours = duration_seconds / 3600;
+    let minutes = (duration_seconds % 3600) / 60;
+    let seconds = duration_seconds % 60;
+    match (hours, minutes) {
+        (0, 0) => format!("{}s", seconds),
+        (0, m) => format!("{}m", m),
+        (h, 0) => format!("{}h", h),
+        (h, m) => format!("{}h{}m", h, m),
+    }
+}
+
 pub fn center_truncate(text: &String, width: usize) -> String {
     let mut text = text.emoji_truncate(width);
-    text.truncate(width);
     let spaces_left = (width - text.emoji_len()) / 2;
     text = " ".repeat(spaces_left).to_string() + &text;
     let spaces_right = width - text.emoji_len();
     text + " ".repeat(spaces_right).to_string().as_ref()
 }
 
+/// OSC 8 hyperlink, clickable in terminals supporting it.
+/// Others show the text only.
+pub fn hyperlink(url: &str, text: &str, enabled: bool) -> String {
+    if !enabled {
+        return text.to_string();
+    }
+    format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", url, text)
+}
+
 pub fn status_to_emoji(status: StatusState) -> &'static str {
     match status {
         StatusState::Created => PAUSE,
-        StatusState::WaitingForResource => PAUSE,
-        StatusState::Preparing => PAUSE,
-        StatusState::Pending => PAUSE,
+        StatusState::WaitingForResource => NO_ENTRY,
+        StatusState::Preparing => HOURGLASS,
+        StatusState::Pending => HOURGLASS_FLOWING,
         StatusState::Running => PLAY,
         StatusState::Success => GREEN_CHECK,
         StatusState::Failed => FAILED,
@@ -83,8 +113,12 @@ pub fn get_terminal_width() -> usize {
     termsize::get().unwrap().cols as usize - 1
 }
 
+pub fn get_terminal_height() -> usize {
+    termsize::get().unwrap().rows as usize
+}
+
 pub enum RenderColumnsAlignment {
-    _Left,
+    Left,
     Center,
     _Right,
 }
@@ -97,7 +131,7 @@ impl RenderColumnsAlignment {
         let spaces_left = pad.repeat(spaces_left_count);
         let spaces_right = pad.repeat(spaces_right_count);
         match self {
-            Self::_Left => text + &spaces_left + &spaces_right,
+            Self::Left => text + &spaces_left + &spaces_right,
             Self::_Right => spaces_left + &spaces_right + &text,
             Self::Center => spaces_left + &text + &spaces_right,
         }
@@ -147,8 +181,7 @@ pub fn render_columns(
 
     let mut result = String::new();
     let flipped = flip(forced_line_breaks);
-    for line_i in 0..flipped.len() {
-        let line = flipped[line_i].clone();
+    for line in flipped {
         for cell_i in 0..line.len() {
             let cell = line[cell_i].clone();
             result += &alignment[cell_i].align(cell, widths[cell_i], None)
@@ -164,6 +197,23 @@ mod test {
 
     use super::*;
 
+    #[test]
+    fn test_duration_to_short_string() {
+        assert_eq!(duration_to_short_string(45.0), "45s");
+        assert_eq!(duration_to_short_string(776.0), "12m");
+        assert_eq!(duration_to_short_string(3600.0), "1h");
+        assert_eq!(duration_to_short_string(3780.0), "1h3m");
+    }
+
+    #[test]
+    fn test_hyperlink() {
+        assert_eq!(
+            hyperlink("https://gitlab.com", "gitlab", true),
+            "\x1b]8;;https://gitlab.com\x1b\\gitlab\x1b]8;;\x1b\\"
+        );
+        assert_eq!(hyperlink("https://gitlab.com", "gitlab", false), "gitlab");
+    }
+
     #[test]
     fn test_flip() {
         let v = vec![vec![1, 2, 3], vec![3, 4, 5]];
@@ -179,7 +229,7 @@ mod test {
         let rendering = render_columns(
             columns,
             vec![11, 10],
-            vec![RenderColumnsAlignment::_Left, RenderColumnsAlignment::Left],
+            vec![RenderColumnsAlignment::Left, RenderColumnsAlignment::Left],
         );
         assert_eq!(
             rendering,
@@ -198,7 +248,7 @@ mod test {
         let rendering = render_columns(
             columns,
             vec![11, 10],
-            vec![RenderColumnsAlignment::_Left, RenderColumnsAlignment::Right],
+            vec![RenderColumnsAlignment::Left, RenderColumnsAlignment::_Right],
         );
         assert_eq!(
             rendering,
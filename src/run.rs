@@ -0,0 +1,310 @@
+/*
+Starting pipelines with variables, for `gpv run` and r in the viewer.
+
+Variables with a description in the `variables:` of the CI config
+are the ones GitLab prefills in its "Run pipeline" form. They are
+asked for with their default and options, more can be added freely.
+*/
+use serde_yaml::Value;
+use std::io::{BufRead, Write};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CiVariable {
+    pub name: String,
+    pub value: String,
+    pub description: String,
+    pub options: Vec<String>,
+}
+
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// The prefilled variables of a CI config, in their order
+pub fn ci_variables(config: &str) -> Result<Vec<CiVariable>, String> {
+    let config: Value =
+        serde_yaml::from_str(config).map_err(|e| format!("Could not parse CI config: {}", e))?;
+    let variables = match config.get("variables").and_then(|v| v.as_mapping()) {
+        None => return Ok(Vec::new()),
+        Some(v) => v,
+    };
+    let mut result = Vec::new();
+    for (name, definition) in variables {
+        let (name, description) = match (scalar(name), definition.get("description")) {
+            (Some(name), Some(description)) => (name, description),
+            _ => continue,
+        };
+        let options = match definition.get("options").and_then(|o| o.as_sequence()) {
+            None => Vec::new(),
+            Some(options) => options.iter().filter_map(scalar).collect(),
+        };
+        result.push(CiVariable {
+            name,
+            value: definition.get("value").and_then(scalar).unwrap_or_default(),
+            description: scalar(description).unwrap_or_default(),
+            options,
+        });
+    }
+    Ok(result)
+}
+
+/// "KEY=VALUE" as given on the command line
+pub fn parse_variable(text: &str) -> Result<(String, String), String> {
+    match text.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.to_string()))
+        }
+        _ => Err(format!("Expected KEY=VALUE, got \"{}\"", text)),
+    }
+}
+
+pub fn read_line<R: BufRead>(input: &mut R) -> Result<String, String> {
+    let mut line = String::new();
+    match input.read_line(&mut line) {
+        Ok(0) => Err("Aborted".to_string()),
+        Ok(_) => Ok(line.trim_end_matches(['\r', '\n']).to_string()),
+        Err(e) => Err(format!("Could not read input: {}", e)),
+    }
+}
+
+/// Asks for the prefilled variables not in `given`, then for any others.
+/// Only values differing from the CI config are returned, next to `given`.
+pub fn prompt_variables<R: BufRead, W: Write>(
+    ci_variables: &[CiVariable],
+    given: Vec<(String, String)>,
+    input: &mut R,
+    output: &mut W,
+) -> Result<Vec<(String, String)>, String> {
+    let mut variables = given;
+    for variable in ci_variables {
+        if variables.iter().any(|(key, _)| *key == variable.name) {
+            continue;
+        }
+        let mut question = format!("{} - {}", variable.name, variable.description);
+        if !variable.options.is_empty() {
+            question += &format!(" ({})", variable.options.join(", "));
+        }
+        question += &format!(" [{}]: ", variable.value);
+        let value = loop {
+            write!(output, "{}", question).ok();
+            output.flush().ok();
+            let answer = read_line(input)?;
+            let value = if answer.is_empty() {
+                variable.value.clone()
+            } else {
+                answer
+            };
+            if variable.options.is_empty() || variable.options.contains(&value) {
+                break value;
+            }
+            writeln!(output, "Pick one of {}", variable.options.join(", ")).ok();
+        };
+        if value != variable.value {
+            variables.push((variable.name.clone(), value));
+        }
+    }
+
+    writeln!(output, "More variables as KEY=VALUE, empty line to go on:").ok();
+    loop {
+        write!(output, "> ").ok();
+        output.flush().ok();
+        let line = read_line(input)?;
+        if line.trim().is_empty() {
+            break;
+        }
+        match parse_variable(&line) {
+            Ok(variable) => variables.push(variable),
+            Err(e) => {
+                writeln!(output, "{}", e).ok();
+            }
+        }
+    }
+    Ok(variables)
+}
+
+/// `default` unless answered with something starting with y or n
+pub fn confirm<R: BufRead, W: Write>(
+    question: &str,
+    default: bool,
+    input: &mut R,
+    output: &mut W,
+) -> Result<bool, String> {
+    let choices = if default { "[Y/n]" } else { "[y/N]" };
+    write!(output, "{} {} ", question, choices).ok();
+    output.flush().ok();
+    let answer = read_line(input)?.trim().to_lowercase();
+    Ok(match answer.chars().next() {
+        Some('y') => true,
+        Some('n') => false,
+        _ => default,
+    })
+}
+
+/// The index of one of `choices`, asked for by number unless there is only one
+pub fn choose<R: BufRead, W: Write>(
+    question: &str,
+    choices: &[String],
+    input: &mut R,
+    output: &mut W,
+) -> Result<usize, String> {
+    match choices.len() {
+        0 => return Err("Nothing to choose from".to_string()),
+        1 => return Ok(0),
+        _ => (),
+    }
+    for (i, choice) in choices.iter().enumerate() {
+        writeln!(output, "{:>3}  {}", i + 1, choice).ok();
+    }
+    loop {
+        write!(output, "{} [1-{}]: ", question, choices.len()).ok();
+        output.flush().ok();
+        let answer = read_line(input)?;
+        if answer.trim().is_empty() {
+            return Err("Nothing done".to_string());
+        }
+        if let Ok(n) = answer.trim().parse::<usize>() {
+            if (1..=choices.len()).contains(&n) {
+                return Ok(n - 1);
+            }
+        }
+        writeln!(output, "Pick a number from 1 to {}", choices.len()).ok();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    const CONFIG: &str = r#"
+variables:
+  CARGO_HOME: .cargo
+  DEPLOY_ENV:
+    value: staging
+    description: Where to deploy
+    options: [staging, production]
+  RETRIES:
+    value: 3
+    description: How often to retry flaky tests
+stages: [build]
+"#;
+
+    #[test]
+    fn test_ci_variables() {
+        let variables = ci_variables(CONFIG).unwrap();
+        assert_eq!(
+            variables,
+            vec![
+                CiVariable {
+                    name: "DEPLOY_ENV".to_string(),
+                    value: "staging".to_string(),
+                    description: "Where to deploy".to_string(),
+                    options: vec!["staging".to_string(), "production".to_string()],
+                },
+                CiVariable {
+                    name: "RETRIES".to_string(),
+                    value: "3".to_string(),
+                    description: "How often to retry flaky tests".to_string(),
+                    options: vec![],
+                },
+            ]
+        );
+        assert_eq!(ci_variables("stages: [build]"), Ok(vec![]));
+        assert!(ci_variables("variables: [").is_err());
+    }
+
+    #[test]
+    fn test_parse_variable() {
+        assert_eq!(
+            parse_variable("DEPLOY_ENV=a=b"),
+            Ok(("DEPLOY_ENV".to_string(), "a=b".to_string()))
+        );
+        assert_eq!(
+            parse_variable("EMPTY="),
+            Ok(("EMPTY".to_string(), "".to_string()))
+        );
+        assert!(parse_variable("=value").is_err());
+        assert!(parse_variable("DEPLOY_ENV").is_err());
+    }
+
+    #[test]
+    fn test_prompt_variables() {
+        let variables = ci_variables(CONFIG).unwrap();
+        // An option which does not exist is asked again, the default is kept
+        let mut input = Cursor::new("qa\nproduction\n\nFOO=bar\nnope\n\n");
+        let mut output = Vec::new();
+        let result = prompt_variables(&variables, vec![], &mut input, &mut output).unwrap();
+        assert_eq!(
+            result,
+            vec![
+                ("DEPLOY_ENV".to_string(), "production".to_string()),
+                ("FOO".to_string(), "bar".to_string()),
+            ]
+        );
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with(
+            "DEPLOY_ENV - Where to deploy (staging, production) [staging]: Pick one of staging, production\n"
+        ));
+        assert!(output.contains("Expected KEY=VALUE, got \"nope\""));
+
+        // Given on the command line, not asked for
+        let given = vec![("DEPLOY_ENV".to_string(), "staging".to_string())];
+        let mut input = Cursor::new("5\n\n");
+        let result = prompt_variables(&variables, given, &mut input, &mut Vec::new()).unwrap();
+        assert_eq!(result[1], ("RETRIES".to_string(), "5".to_string()));
+
+        assert!(
+            prompt_variables(&variables, vec![], &mut Cursor::new(""), &mut Vec::new()).is_err()
+        );
+    }
+
+    #[test]
+    fn test_confirm() {
+        let ask = |answer: &str| confirm("Run?", true, &mut Cursor::new(answer), &mut Vec::new());
+        assert_eq!(ask("\n"), Ok(true));
+        assert_eq!(ask("No\n"), Ok(false));
+        assert!(ask("").is_err());
+        let overwrite = |answer: &str| {
+            confirm(
+                "Overwrite?",
+                false,
+                &mut Cursor::new(answer),
+                &mut Vec::new(),
+            )
+        };
+        assert_eq!(overwrite("\n"), Ok(false));
+        assert_eq!(overwrite("yes\n"), Ok(true));
+    }
+
+    #[test]
+    fn test_choose() {
+        let environments = vec!["production".to_string(), "review/feature".to_string()];
+        let pick = |answer: &str| {
+            let mut output = Vec::new();
+            let picked = choose(
+                "Environment",
+                &environments,
+                &mut Cursor::new(answer),
+                &mut output,
+            );
+            (picked, String::from_utf8(output).unwrap())
+        };
+        let (picked, output) = pick("3\n2\n");
+        assert_eq!(picked, Ok(1));
+        assert!(output.contains("  2  review/feature"));
+        assert!(output.contains("Pick a number from 1 to 2"));
+        assert!(pick("\n").0.is_err());
+        let single = choose(
+            "Environment",
+            &environments[..1],
+            &mut Cursor::new(""),
+            &mut Vec::new(),
+        );
+        assert_eq!(single, Ok(0));
+    }
+}
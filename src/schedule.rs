@@ -0,0 +1,331 @@
+/*
+When to ask GitLab again.
+
+Pipelines with running or pending jobs are polled fast, finished ones
+slowly, and very slowly while nobody is looking: the terminal is in the
+background or nothing happened for a while. Keys, checkouts and pushes
+wake the scheduler early, after a push it polls every second for a bit.
+*/
+use crate::config::Configuration;
+use crate::git_events::GitEvent;
+use crate::gitlabbing::GitlabProjectPipelines;
+use gitlab::StatusState;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const BURST_INTERVAL: Duration = Duration::from_secs(1);
+pub const BURST_LENGTH: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Activity {
+    Active,    // jobs running or pending
+    Finished,  // nothing left to run
+    Unfocused, // terminal in the background
+    Idle,      // nothing happened for a while
+}
+
+pub fn is_active(status: StatusState) -> bool {
+    matches!(
+        status,
+        StatusState::Created
+            | StatusState::WaitingForResource
+            | StatusState::Preparing
+            | StatusState::Pending
+            | StatusState::Running
+    )
+}
+
+/// Whether any pipeline or job is still to run
+pub fn has_active(project: &GitlabProjectPipelines) -> bool {
+    project.pipelines.iter().any(|(pipeline, jobs)| {
+        is_active(pipeline.status) || jobs.iter().any(|j| is_active(j.job.status))
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Intervals {
+    pub active: Duration,
+    pub finished: Duration,
+    pub unfocused: Duration,
+    pub idle: Duration,
+    pub idle_after: Option<Duration>, // None when nobody watches anyway
+}
+
+/// Seconds of the config. Zero would poll in a tight loop.
+pub fn seconds(key: &str, value: f32) -> Result<Duration, String> {
+    let error = || {
+        format!(
+            "Invalid configuration\n{}: {} is no number of seconds",
+            key, value
+        )
+    };
+    if value <= 0.0 {
+        return Err(error());
+    }
+    Duration::try_from_secs_f32(value).map_err(|_| error())
+}
+
+impl Intervals {
+    pub fn from_config(conf: &Configuration) -> Result<Intervals, String> {
+        let polling = conf.polling.clone().unwrap_or_default();
+        let (key, active) = match (polling.active, conf.cooldown) {
+            (Some(active), _) => ("polling.active", active),
+            (None, Some(cooldown)) => ("cooldown", cooldown),
+            (None, None) => ("cooldown", 5.0),
+        };
+        // Defaults are never faster than the interval for running jobs
+        let or_default = |key: &str, value: Option<f32>, default: f32| {
+            seconds(key, value.unwrap_or(default.max(active)))
+        };
+        Ok(Intervals {
+            active: seconds(key, active)?,
+            finished: or_default("polling.finished", polling.finished, 30.0)?,
+            unfocused: or_default("polling.unfocused", polling.unfocused, 60.0)?,
+            idle: or_default("polling.idle", polling.idle, 120.0)?,
+            idle_after: Some(or_default("polling.idle-after", polling.idle_after, 600.0)?),
+        })
+    }
+
+    /// `gpv overview` asks for the pipelines of many projects at once,
+    /// so it polls at one slow pace
+    pub fn overview(conf: &Configuration) -> Result<Intervals, String> {
+        let polling = conf.polling.clone().unwrap_or_default();
+        let overview = seconds("polling.overview", polling.overview.unwrap_or(60.0))?
+            .max(Duration::from_secs(5));
+        Ok(Intervals {
+            active: overview,
+            finished: overview,
+            unfocused: overview,
+            idle: overview,
+            idle_after: None,
+        })
+    }
+}
+
+/// Wakes a waiting scheduler, also from other threads
+#[derive(Debug, Clone, Default)]
+pub struct Waker(Arc<AtomicBool>);
+
+impl Waker {
+    pub fn wake(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether it was woken since the last call
+    pub fn take(&self) -> bool {
+        self.0.swap(false, Ordering::SeqCst)
+    }
+}
+
+pub struct Scheduler {
+    intervals: Intervals,
+    active: bool,
+    focused: bool,
+    last_event: Instant,
+    burst_until: Option<Instant>,
+    pub waker: Waker,
+}
+
+impl Scheduler {
+    pub fn new(intervals: Intervals, now: Instant) -> Scheduler {
+        Scheduler {
+            intervals,
+            active: true,
+            focused: true,
+            last_event: now,
+            burst_until: None,
+            waker: Waker::default(),
+        }
+    }
+
+    /// For output read by scripts, which are never idle
+    pub fn unattended(conf: &Configuration) -> Result<Scheduler, String> {
+        let intervals = Intervals {
+            idle_after: None,
+            ..Intervals::from_config(conf)?
+        };
+        Ok(Scheduler::new(intervals, Instant::now()))
+    }
+
+    pub fn set_intervals(&mut self, intervals: Intervals) {
+        let idle_after = self.intervals.idle_after.and(intervals.idle_after);
+        self.intervals = Intervals {
+            idle_after,
+            ..intervals
+        };
+    }
+
+    pub fn set_active(&mut self, active: bool) {
+        self.active = active;
+    }
+
+    pub fn set_focused(&mut self, focused: bool, now: Instant) {
+        if focused && !self.focused {
+            self.touch(now);
+        }
+        self.focused = focused;
+    }
+
+    /// Something happened: a key, a status change, a checkout
+    pub fn touch(&mut self, now: Instant) {
+        self.last_event = now;
+    }
+
+    /// Polls every second for a while, e.g. for the pipeline of a push
+    pub fn burst(&mut self, now: Instant) {
+        self.touch(now);
+        self.burst_until = Some(now + BURST_LENGTH);
+    }
+
+    /// Checkouts and commits count as something happening,
+    /// pushes start a burst
+    pub fn on_git_events(&mut self, events: &[GitEvent], now: Instant) {
+        if events.contains(&GitEvent::Push) {
+            self.burst(now);
+        } else if !events.is_empty() {
+            self.touch(now);
+        }
+    }
+
+    /// Running jobs are polled fast even when nobody pressed a key for a while
+    pub fn activity(&self, now: Instant) -> Activity {
+        let idle = self
+            .intervals
+            .idle_after
+            .is_some_and(|after| now.duration_since(self.last_event) >= after);
+        if !self.focused {
+            Activity::Unfocused
+        } else if self.active {
+            Activity::Active
+        } else if idle {
+            Activity::Idle
+        } else {
+            Activity::Finished
+        }
+    }
+
+    pub fn interval(&self, now: Instant) -> Duration {
+        let interval = match self.activity(now) {
+            Activity::Active => self.intervals.active,
+            Activity::Finished => self.intervals.finished,
+            Activity::Unfocused => self.intervals.unfocused,
+            Activity::Idle => self.intervals.idle,
+        };
+        match self.burst_until {
+            Some(until) if now < until => interval.min(BURST_INTERVAL),
+            _ => interval,
+        }
+    }
+
+    /// Waits for the current interval, or until woken or stopped
+    pub fn sleep(&self, running: &Arc<AtomicBool>) {
+        let started = Instant::now();
+        let interval = self.interval(started);
+        while running.load(Ordering::SeqCst) && started.elapsed() < interval && !self.waker.take() {
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::*;
+
+    fn conf(yaml: &str) -> Configuration {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_intervals() {
+        let intervals = Intervals::from_config(&conf("cooldown: 3")).unwrap();
+        assert_eq!(intervals.active, Duration::from_secs(3));
+        assert_eq!(intervals.finished, Duration::from_secs(30));
+        assert_eq!(intervals.idle_after, Some(Duration::from_secs(600)));
+        let intervals = Intervals::from_config(&conf(
+            "{cooldown: 3, polling: {active: 2, finished: 10, unfocused: 1}}",
+        ))
+        .unwrap();
+        assert_eq!(intervals.active, Duration::from_secs(2));
+        assert_eq!(intervals.finished, Duration::from_secs(10));
+        assert_eq!(intervals.unfocused, Duration::from_secs(1));
+        // Defaults are never faster than polling running jobs
+        let intervals = Intervals::from_config(&conf("cooldown: 90")).unwrap();
+        assert_eq!(intervals.finished, Duration::from_secs(90));
+        assert_eq!(intervals.idle, Duration::from_secs(120));
+
+        let overview = Intervals::overview(&conf("cooldown: 3")).unwrap();
+        assert_eq!(overview.active, Duration::from_secs(60));
+        assert_eq!(overview.idle_after, None);
+        let overview = Intervals::overview(&conf("polling: {overview: 1}")).unwrap();
+        assert_eq!(overview.active, Duration::from_secs(5));
+
+        // YAML has .inf and .nan, which are no durations
+        let error = Intervals::from_config(&conf("polling: {idle: .inf}")).unwrap_err();
+        assert!(error.contains("polling.idle"));
+        assert!(Intervals::from_config(&conf("cooldown: .nan")).is_err());
+        assert!(Intervals::overview(&conf("polling: {overview: .nan}")).is_err());
+        let error = Intervals::from_config(&conf("polling: {finished: -1}")).unwrap_err();
+        assert!(error.contains("polling.finished: -1 is no number of seconds"));
+        assert!(Intervals::from_config(&conf("polling: {active: 0}")).is_err());
+        assert!(Intervals::from_config(&conf("cooldown: -1")).is_err());
+        assert!(Intervals::overview(&conf("polling: {overview: 0}")).is_err());
+    }
+
+    #[test]
+    fn test_scheduler() {
+        let start = Instant::now();
+        let minutes = |m: u64| start + Duration::from_secs(m * 60);
+        let mut scheduler = Scheduler::new(Intervals::from_config(&conf("{}")).unwrap(), start);
+        assert_eq!(scheduler.activity(start), Activity::Active);
+        assert_eq!(scheduler.interval(start), Duration::from_secs(5));
+
+        let finished = project_pipelines(vec![(
+            pipeline_with_status(1, StatusState::Success),
+            vec![job_with_status(1, "build", "build", StatusState::Success)],
+        )]);
+        scheduler.set_active(has_active(&finished));
+        assert_eq!(scheduler.interval(start), Duration::from_secs(30));
+
+        scheduler.on_git_events(&[GitEvent::Commit, GitEvent::Push], minutes(1));
+        assert_eq!(scheduler.interval(minutes(1)), BURST_INTERVAL);
+        assert_eq!(scheduler.activity(minutes(2)), Activity::Finished);
+        assert_eq!(scheduler.activity(minutes(11)), Activity::Idle);
+        assert_eq!(scheduler.interval(minutes(11)), Duration::from_secs(120));
+        scheduler.touch(minutes(11));
+        assert_eq!(scheduler.activity(minutes(11)), Activity::Finished);
+
+        scheduler.set_focused(false, minutes(12));
+        assert_eq!(scheduler.interval(minutes(12)), Duration::from_secs(60));
+        // Coming back counts as something happening
+        scheduler.set_focused(true, minutes(30));
+        assert_eq!(scheduler.activity(minutes(30)), Activity::Finished);
+
+        let running = project_pipelines(vec![(
+            pipeline_with_status(2, StatusState::Running),
+            vec![job_with_status(2, "test", "test", StatusState::Pending)],
+        )]);
+        assert!(has_active(&running));
+        // Running jobs are polled fast, however long nobody pressed a key
+        scheduler.set_active(has_active(&running));
+        assert_eq!(scheduler.activity(minutes(60)), Activity::Active);
+        assert_eq!(scheduler.interval(minutes(60)), Duration::from_secs(5));
+        let unattended = Scheduler::unattended(&conf("{}")).unwrap();
+        assert_eq!(unattended.activity(minutes(60)), Activity::Active);
+    }
+
+    #[test]
+    fn test_waker() {
+        let scheduler = Scheduler::new(
+            Intervals::from_config(&conf("cooldown: 60")).unwrap(),
+            Instant::now(),
+        );
+        let waker = scheduler.waker.clone();
+        thread::spawn(move || waker.wake());
+        let started = Instant::now();
+        scheduler.sleep(&Arc::new(AtomicBool::new(true)));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
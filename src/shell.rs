@@ -0,0 +1,54 @@
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::Sender;
+use std::thread;
+
+/// Run a user command with `sh -c` in the background.
+/// Its output is discarded, so it does not mess up the rendered screen.
+/// If it fails, its exit status and stderr are sent to `failures`.
+pub fn spawn_shell(
+    command: &str,
+    env: Vec<(&'static str, String)>,
+    stdin: Option<Vec<u8>>,
+    failures: Option<Sender<String>>,
+) -> Result<(), String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(env)
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::null())
+        .stderr(if failures.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .spawn()
+        .map_err(|e| format!("Could not run \"{}\": {}", command, e))?;
+    let input = child.stdin.take();
+    let command = command.to_string();
+    // Feed and reap the process without blocking the refresh loop
+    thread::spawn(move || {
+        if let (Some(mut input), Some(data)) = (input, stdin) {
+            input.write_all(&data).ok();
+        }
+        let mut stderr = String::new();
+        if let Some(mut output) = child.stderr.take() {
+            output.read_to_string(&mut stderr).ok();
+        }
+        let status = child.wait();
+        if let Some(failures) = failures {
+            let failure = match status {
+                Ok(status) if status.success() => return,
+                Ok(status) => format!("\"{}\" {}: {}", command, status, stderr.trim()),
+                Err(e) => format!("\"{}\" failed: {}", command, e),
+            };
+            failures.send(failure).ok();
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,198 @@
+/*
+Test reports GitLab parses from the JUnit artifacts of a pipeline.
+
+The summary is cheap and fetched with every refresh to show counts
+under each job. The full report with all test cases is only fetched
+on demand, to list what failed.
+*/
+use crate::emoji::FAILED;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct TestCounts {
+    pub total: u64,
+    pub success: u64,
+    pub failed: u64,
+    pub skipped: u64,
+    pub error: u64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TestSuiteSummary {
+    pub total_count: u64,
+    pub success_count: u64,
+    pub failed_count: u64,
+    pub skipped_count: u64,
+    pub error_count: u64,
+    #[serde(default)]
+    pub build_ids: Vec<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TestReportSummary {
+    pub test_suites: Vec<TestSuiteSummary>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TestCase {
+    pub status: String,
+    pub name: String,
+    pub classname: Option<String>,
+    pub execution_time: Option<f64>,
+    pub system_output: Option<String>,
+    pub stack_trace: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TestSuite {
+    pub name: String,
+    pub suite_error: Option<String>,
+    #[serde(default)]
+    pub test_cases: Vec<TestCase>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TestReport {
+    pub test_suites: Vec<TestSuite>,
+}
+
+impl TestCounts {
+    pub fn summary(&self) -> String {
+        let mut parts = vec![format!("{} passed", self.success)];
+        if self.failed + self.error > 0 {
+            parts.push(format!("{} failed", self.failed + self.error));
+        }
+        if self.skipped > 0 {
+            parts.push(format!("{} skipped", self.skipped));
+        }
+        format!("Tests: {}", parts.join(", "))
+    }
+}
+
+/// Test counts per job id. Jobs running in parallel share
+/// one suite, each of them gets the counts of the whole suite.
+pub fn counts_by_job(summary: &TestReportSummary) -> HashMap<u64, TestCounts> {
+    let mut counts = HashMap::new();
+    for suite in &summary.test_suites {
+        for build_id in &suite.build_ids {
+            let job: &mut TestCounts = counts.entry(*build_id).or_default();
+            job.total += suite.total_count;
+            job.success += suite.success_count;
+            job.failed += suite.failed_count;
+            job.skipped += suite.skipped_count;
+            job.error += suite.error_count;
+        }
+    }
+    counts
+}
+
+fn indent(text: &str) -> String {
+    text.trim_end()
+        .lines()
+        .map(|l| format!("    {}", l))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Failed test cases with their message and stack trace,
+/// of all suites or only the one of `job`.
+pub fn format_test_failures(report: &TestReport, job: Option<&str>) -> String {
+    let mut result = String::new();
+    for suite in &report.test_suites {
+        if job.is_some_and(|job| job != suite.name) {
+            continue;
+        }
+        let failed: Vec<&TestCase> = suite
+            .test_cases
+            .iter()
+            .filter(|c| c.status == "failed" || c.status == "error")
+            .collect();
+        if failed.is_empty() && suite.suite_error.is_none() {
+            continue;
+        }
+        result += &format!("====   {}   ====\n", suite.name);
+        if let Some(error) = &suite.suite_error {
+            result += &format!("Suite error: {}\n", error);
+        }
+        for case in failed {
+            let name = match &case.classname {
+                Some(classname) => format!("{}.{}", classname, case.name),
+                None => case.name.clone(),
+            };
+            result += &format!("{} {}", FAILED, name);
+            if let Some(time) = case.execution_time {
+                result += &format!(" ({:.2}s)", time);
+            }
+            result += "\n";
+            for text in [&case.system_output, &case.stack_trace]
+                .into_iter()
+                .flatten()
+            {
+                if !text.trim().is_empty() {
+                    result += &indent(text);
+                    result += "\n";
+                }
+            }
+            result += "\n";
+        }
+    }
+    if result.is_empty() {
+        return "No failed tests\n".to_string();
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_counts_by_job() {
+        let summary: TestReportSummary = serde_json::from_str(
+            r#"{"total": {"count": 5}, "test_suites": [
+                {"name": "rspec", "total_time": 1.5, "total_count": 5, "success_count": 3,
+                 "failed_count": 1, "skipped_count": 1, "error_count": 0, "build_ids": [11, 12]},
+                {"name": "lint", "total_time": 0.1, "total_count": 1, "success_count": 1,
+                 "failed_count": 0, "skipped_count": 0, "error_count": 0, "build_ids": [13]}
+            ]}"#,
+        )
+        .unwrap();
+        let counts = counts_by_job(&summary);
+        assert_eq!(
+            counts[&12].summary(),
+            "Tests: 3 passed, 1 failed, 1 skipped"
+        );
+        assert_eq!(counts[&13].summary(), "Tests: 1 passed");
+        assert!(!counts.contains_key(&14));
+    }
+
+    #[test]
+    fn test_format_test_failures() {
+        let report: TestReport = serde_json::from_str(
+            r#"{"total_count": 2, "test_suites": [{"name": "cargo-test", "suite_error": null,
+                "test_cases": [
+                    {"status": "success", "name": "test_flip", "classname": "render::util"},
+                    {"status": "failed", "name": "test_parse_origin", "classname": "gitlabbing",
+                     "execution_time": 0.01, "system_output": "assertion failed: left == right",
+                     "stack_trace": "at src/gitlabbing.rs:42\nat src/main.rs:1"}
+                ]}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            format_test_failures(&report, None),
+            format!(
+                "====   cargo-test   ====\n\
+                 {} gitlabbing.test_parse_origin (0.01s)\n    \
+                 assertion failed: left == right\n    \
+                 at src/gitlabbing.rs:42\n    \
+                 at src/main.rs:1\n\n",
+                FAILED
+            )
+        );
+        assert_eq!(
+            format_test_failures(&report, Some("other")),
+            "No failed tests\n"
+        );
+    }
+}
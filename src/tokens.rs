@@ -0,0 +1,296 @@
+/*
+Where the GitLab token of a host comes from, first match wins:
+
+1. GITLAB_TOKEN, for GITLAB_HOST or gitlab.com like glab does
+2. `token-command` of the config, e.g. `pass show gitlab`
+3. `gitlab-tokens` of the config, in plain text
+4. The system keyring (secret-tool or the macOS keychain)
+5. The config of glab, the GitLab CLI
+6. CI_JOB_TOKEN, for CI_SERVER_HOST inside GitLab CI jobs. It is last
+   as it only reaches artifacts and environments, not the API gpv reads
+
+Commands and the keyring may be slow or ask for a passphrase,
+so resolved tokens are kept for the lifetime of the process.
+*/
+use crate::config::Configuration;
+use serde_yaml::Value;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, OnceLock};
+
+const KEYRING_SERVICE: &str = "gitlab-pipeline-viewer";
+/// Where GITLAB_TOKEN belongs without GITLAB_HOST
+const DEFAULT_HOST: &str = "gitlab.com";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenKind {
+    Personal, // sent as PRIVATE-TOKEN
+    Job,      // CI_JOB_TOKEN, sent as JOB-TOKEN
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub value: String,
+    pub kind: TokenKind,
+}
+
+impl Token {
+    fn personal(value: String) -> Token {
+        Token {
+            value,
+            kind: TokenKind::Personal,
+        }
+    }
+
+    /// Header for requests made without the gitlab crate
+    pub fn header(&self) -> (&'static str, &str) {
+        match self.kind {
+            TokenKind::Personal => ("PRIVATE-TOKEN", &self.value),
+            TokenKind::Job => ("JOB-TOKEN", &self.value),
+        }
+    }
+}
+
+pub trait Keyring {
+    fn get(&self, host: &str) -> Option<String>;
+}
+
+/// The secret service through secret-tool, or the macOS keychain
+pub struct SystemKeyring;
+
+impl Keyring for SystemKeyring {
+    fn get(&self, host: &str) -> Option<String> {
+        let mut command = if cfg!(target_os = "macos") {
+            let mut command = Command::new("security");
+            command.args([
+                "find-generic-password",
+                "-s",
+                KEYRING_SERVICE,
+                "-a",
+                host,
+                "-w",
+            ]);
+            command
+        } else {
+            let mut command = Command::new("secret-tool");
+            command.args(["lookup", "service", KEYRING_SERVICE, "host", host]);
+            command
+        };
+        let output = command.stderr(Stdio::null()).output().ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        non_empty(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+}
+
+fn non_empty(token: String) -> Option<String> {
+    let token = token.trim().to_string();
+    if token.is_empty() {
+        return None;
+    }
+    Some(token)
+}
+
+fn run_token_command(command: &str) -> Result<String, String> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("Could not run \"{}\": {}", command, e))?;
+    if !output.status.success() {
+        return Err(format!("\"{}\" failed with {}", command, output.status));
+    }
+    // Like pass, the token is the first line
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    non_empty(stdout.lines().next().unwrap_or_default().to_string())
+        .ok_or_else(|| format!("\"{}\" printed no token", command))
+}
+
+fn glab_config_path() -> Option<PathBuf> {
+    if let Ok(dir) = env::var("GLAB_CONFIG_DIR") {
+        return Some(PathBuf::from(dir).join("config.yml"));
+    }
+    let config = env::var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|_| env::var("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .ok()?;
+    Some(config.join("glab-cli").join("config.yml"))
+}
+
+/// The token of `host` in the config file of glab
+fn glab_token(config: &str, host: &str) -> Option<String> {
+    let config: Value = serde_yaml::from_str(config).ok()?;
+    let token = config.get("hosts")?.get(host)?.get("token")?.as_str()?;
+    non_empty(token.to_string())
+}
+
+/// Looks up the token of `host` in the order described above
+pub fn resolve_token<E>(
+    host: &str,
+    conf: &Configuration,
+    env: E,
+    keyring: &dyn Keyring,
+    glab_config: Option<&str>,
+) -> Result<Token, String>
+where
+    E: Fn(&str) -> Option<String>,
+{
+    if let Some(token) = env("GITLAB_TOKEN").and_then(non_empty) {
+        if env("GITLAB_HOST").as_deref().unwrap_or(DEFAULT_HOST) == host {
+            return Ok(Token::personal(token));
+        }
+    }
+    if let Some(command) = conf.token_command.as_ref().and_then(|c| c.get(host)) {
+        return run_token_command(command).map(Token::personal);
+    }
+    if let Some(token) = conf.gitlab_token_mapping.get(host) {
+        return Ok(Token::personal(token.clone()));
+    }
+    if conf.keyring.unwrap_or(true) {
+        if let Some(token) = keyring.get(host) {
+            return Ok(Token::personal(token));
+        }
+    }
+    if let Some(token) = glab_config.and_then(|c| glab_token(c, host)) {
+        return Ok(Token::personal(token));
+    }
+    if let Some(token) = env("CI_JOB_TOKEN").and_then(non_empty) {
+        if env("CI_SERVER_HOST").as_deref() == Some(host) {
+            return Ok(Token {
+                value: token,
+                kind: TokenKind::Job,
+            });
+        }
+    }
+    Err(format!(
+        "No token for \"{}\", add it to \"gitlab-tokens\" of the config or set GITLAB_TOKEN",
+        host
+    ))
+}
+
+fn token_cache() -> &'static Mutex<HashMap<String, Token>> {
+    static CACHE: OnceLock<Mutex<HashMap<String, Token>>> = OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+pub fn token_for(host: &str, conf: &Configuration) -> Result<Token, String> {
+    if let Some(token) = token_cache().lock().unwrap().get(host) {
+        return Ok(token.clone());
+    }
+    let glab_config = glab_config_path().and_then(|p| fs::read_to_string(p).ok());
+    let token = resolve_token(
+        host,
+        conf,
+        |name| env::var(name).ok(),
+        &SystemKeyring,
+        glab_config.as_deref(),
+    )?;
+    token_cache()
+        .lock()
+        .unwrap()
+        .insert(host.to_string(), token.clone());
+    Ok(token)
+}
+
+/// Resolves every token again, e.g. after the config changed
+pub fn forget_tokens() {
+    token_cache().lock().unwrap().clear();
+}
+
+/// Hosts a token is configured for, sorted
+pub fn known_hosts(conf: &Configuration) -> Vec<String> {
+    let mut hosts: Vec<String> = conf.gitlab_token_mapping.keys().cloned().collect();
+    if let Some(commands) = &conf.token_command {
+        hosts.extend(commands.keys().cloned());
+    }
+    if env::var("GITLAB_TOKEN").is_ok() {
+        hosts.push(env::var("GITLAB_HOST").unwrap_or(DEFAULT_HOST.to_string()));
+    }
+    hosts.sort();
+    hosts.dedup();
+    hosts
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct MockKeyring(HashMap<String, String>);
+
+    impl Keyring for MockKeyring {
+        fn get(&self, host: &str) -> Option<String> {
+            self.0.get(host).cloned()
+        }
+    }
+
+    fn conf(yaml: &str) -> Configuration {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    const GLAB_CONFIG: &str = "
+git_protocol: ssh
+hosts:
+    gitlab.com:
+        token: glpat-glab
+        api_host: gitlab.com
+";
+
+    #[test]
+    fn test_resolve_token() {
+        let keyring = MockKeyring(HashMap::from([(
+            "gitlab.com".to_string(),
+            "glpat-keyring".to_string(),
+        )]));
+        let no_env = |_: &str| None;
+        let resolve = |conf: &Configuration, env: &dyn Fn(&str) -> Option<String>| {
+            resolve_token("gitlab.com", conf, env, &keyring, Some(GLAB_CONFIG)).map(|t| t.value)
+        };
+
+        let plain = conf("gitlab-tokens: {gitlab.com: glpat-plain}");
+        assert_eq!(resolve(&plain, &no_env), Ok("glpat-plain".to_string()));
+        let command = conf(
+            "{gitlab-tokens: {gitlab.com: glpat-plain}, token-command: {gitlab.com: echo glpat-command}}",
+        );
+        assert_eq!(resolve(&command, &no_env), Ok("glpat-command".to_string()));
+        let empty = conf("{}");
+        assert_eq!(resolve(&empty, &no_env), Ok("glpat-keyring".to_string()));
+        let no_keyring = conf("keyring: false");
+        assert_eq!(resolve(&no_keyring, &no_env), Ok("glpat-glab".to_string()));
+
+        // GITLAB_TOKEN wins, unless it is meant for another host.
+        // A configured token wins over the job token in CI.
+        let env = |name: &str| match name {
+            "GITLAB_TOKEN" => Some("glpat-env".to_string()),
+            "GITLAB_HOST" => Some("gitlab.example.com".to_string()),
+            "CI_JOB_TOKEN" => Some("job".to_string()),
+            "CI_SERVER_HOST" => Some("gitlab.com".to_string()),
+            _ => None,
+        };
+        let token = resolve_token("gitlab.com", &plain, env, &keyring, None).unwrap();
+        assert_eq!(token.header(), ("PRIVATE-TOKEN", "glpat-plain"));
+        let token = resolve_token("gitlab.example.com", &plain, env, &keyring, None).unwrap();
+        assert_eq!(token.header(), ("PRIVATE-TOKEN", "glpat-env"));
+        let token = resolve_token("gitlab.com", &no_keyring, env, &keyring, None).unwrap();
+        assert_eq!(token.kind, TokenKind::Job);
+        assert_eq!(token.header(), ("JOB-TOKEN", "job"));
+
+        assert!(resolve_token("other.com", &plain, no_env, &keyring, Some(GLAB_CONFIG)).is_err());
+        // Without GITLAB_HOST, GITLAB_TOKEN is for gitlab.com only
+        let env = |name: &str| match name {
+            "GITLAB_TOKEN" => Some("glpat-env".to_string()),
+            _ => None,
+        };
+        let token = resolve_token("gitlab.com", &plain, env, &keyring, None).unwrap();
+        assert_eq!(token.value, "glpat-env");
+        let token = resolve_token("gitlab.example.com", &command, env, &keyring, None);
+        assert!(token.is_err());
+        let failing = conf("token-command: {gitlab.com: 'false'}");
+        assert!(resolve(&failing, &no_env).is_err());
+    }
+}
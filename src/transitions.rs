@@ -0,0 +1,403 @@
+/*
+Status tracking between refreshes.
+
+We remember the status of every pipeline, stage and job we have seen.
+After each refresh, the new statuses are compared with the old ones
+and every change is reported as a transition.
+
+A pipeline that leaves the snapshot while still running, e.g. because
+only running pipelines are selected, is fetched on its own until it
+finishes, so its final transition is reported too.
+*/
+use crate::gitlabbing::{GitlabProjectPipelines, PipelineJob};
+use crate::schedule::is_active;
+use gitlab::{Pipeline, StatusState};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransitionJob {
+    pub id: u64,
+    pub name: String,
+    pub stage: String,
+    pub web_url: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Subject {
+    Pipeline,
+    Stage(String),
+    Job(TransitionJob),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transition {
+    pub project: String,
+    pub pipeline_id: u64,
+    pub pipeline_url: String,
+    pub ref_: Option<String>,
+    pub subject: Subject,
+    pub from: Option<StatusState>, // None if seen for the first time
+    pub to: StatusState,
+}
+
+impl Transition {
+    pub fn summary(&self) -> String {
+        match &self.subject {
+            Subject::Pipeline => format!(
+                "Pipeline {} ({}) {}",
+                self.pipeline_id,
+                self.ref_.clone().unwrap_or_default(),
+                status_name(self.to)
+            ),
+            Subject::Stage(stage) => format!(
+                "Stage {} {} in pipeline {}",
+                stage,
+                status_name(self.to),
+                self.pipeline_id
+            ),
+            Subject::Job(job) => format!(
+                "Job {} ({}) {} in pipeline {}",
+                job.name,
+                job.stage,
+                status_name(self.to),
+                self.pipeline_id
+            ),
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut value = json!({
+            "project": self.project,
+            "pipeline": {
+                "id": self.pipeline_id,
+                "web_url": self.pipeline_url,
+                "ref": self.ref_,
+            },
+            "from": self.from.map(status_name),
+            "to": status_name(self.to),
+        });
+        match &self.subject {
+            Subject::Pipeline => (),
+            Subject::Stage(stage) => value["stage"] = json!(stage),
+            Subject::Job(job) => {
+                value["stage"] = json!(job.stage);
+                value["job"] = json!({
+                    "id": job.id,
+                    "name": job.name,
+                    "stage": job.stage,
+                    "web_url": job.web_url,
+                });
+            }
+        }
+        value
+    }
+
+    /// The transition as `GPV_*` environment variables for user commands.
+    pub fn env(&self) -> Vec<(&'static str, String)> {
+        let mut env = vec![
+            ("GPV_PROJECT", self.project.clone()),
+            ("GPV_PIPELINE_ID", self.pipeline_id.to_string()),
+            ("GPV_PIPELINE_URL", self.pipeline_url.clone()),
+            ("GPV_REF", self.ref_.clone().unwrap_or_default()),
+            ("GPV_STATUS", status_name(self.to).to_string()),
+            (
+                "GPV_PREVIOUS_STATUS",
+                self.from.map(status_name).unwrap_or("").to_string(),
+            ),
+            ("GPV_SUMMARY", self.summary()),
+        ];
+        match &self.subject {
+            Subject::Pipeline => env.push(("GPV_EVENT", "pipeline".to_string())),
+            Subject::Stage(stage) => {
+                env.push(("GPV_EVENT", "stage".to_string()));
+                env.push(("GPV_STAGE", stage.clone()));
+            }
+            Subject::Job(job) => {
+                env.push(("GPV_EVENT", "job".to_string()));
+                env.push(("GPV_STAGE", job.stage.clone()));
+                env.push(("GPV_JOB_ID", job.id.to_string()));
+                env.push(("GPV_JOB_NAME", job.name.clone()));
+                env.push(("GPV_JOB_URL", job.web_url.clone()));
+            }
+        }
+        env
+    }
+}
+
+pub fn status_name(status: StatusState) -> &'static str {
+    match status {
+        StatusState::Created => "created",
+        StatusState::WaitingForResource => "waiting_for_resource",
+        StatusState::Preparing => "preparing",
+        StatusState::Pending => "pending",
+        StatusState::Running => "running",
+        StatusState::Success => "success",
+        StatusState::Failed => "failed",
+        StatusState::Canceled => "canceled",
+        StatusState::Skipped => "skipped",
+        StatusState::Manual => "manual",
+        StatusState::Scheduled => "scheduled",
+    }
+}
+
+/// Final statuses, nothing runs anymore
+pub fn is_finished(status: StatusState) -> bool {
+    matches!(
+        status,
+        StatusState::Success | StatusState::Failed | StatusState::Canceled | StatusState::Skipped
+    )
+}
+
+/// Combined status of all jobs of a stage, similar to how GitLab does it:
+/// A stage is running as long as any job is, failed if a job failed
+/// without being allowed to, and successful otherwise.
+pub fn stage_status(jobs: &[&PipelineJob]) -> StatusState {
+    let has = |status: StatusState| jobs.iter().any(|j| j.job.status == status);
+    if has(StatusState::Running) {
+        return StatusState::Running;
+    }
+    for waiting in [
+        StatusState::Pending,
+        StatusState::Preparing,
+        StatusState::WaitingForResource,
+        StatusState::Created,
+    ] {
+        if has(waiting) {
+            return StatusState::Pending;
+        }
+    }
+    if jobs
+        .iter()
+        .any(|j| j.job.status == StatusState::Failed && !j.job.allow_failure)
+    {
+        return StatusState::Failed;
+    }
+    if has(StatusState::Canceled) {
+        return StatusState::Canceled;
+    }
+    if has(StatusState::Scheduled) {
+        return StatusState::Scheduled;
+    }
+    if jobs.iter().all(|j| j.job.status == StatusState::Skipped) {
+        return StatusState::Skipped;
+    }
+    if jobs.iter().all(|j| j.job.status == StatusState::Manual) {
+        return StatusState::Manual;
+    }
+    StatusState::Success
+}
+
+#[derive(Default)]
+pub struct StatusTracker {
+    pipelines: HashMap<u64, StatusState>,
+    stages: HashMap<(u64, String), StatusState>,
+    jobs: HashMap<u64, StatusState>,
+    unfinished: HashSet<u64>, // pipelines last seen active
+    seeded: bool,
+}
+
+impl StatusTracker {
+    /// Remember the statuses of the given project and return what changed
+    /// since the last call. The very first call only records the statuses,
+    /// so starting the viewer does not report everything as new.
+    pub fn update(&mut self, project: &GitlabProjectPipelines) -> Vec<Transition> {
+        let mut transitions = Vec::new();
+        for (pipeline, jobs) in &project.pipelines {
+            let transition =
+                |subject: Subject, from: Option<StatusState>, to: StatusState| Transition {
+                    project: project.name.clone(),
+                    pipeline_id: pipeline.id.value(),
+                    pipeline_url: pipeline.web_url.clone(),
+                    ref_: pipeline.ref_.clone(),
+                    subject,
+                    from,
+                    to,
+                };
+
+            let previous = self.pipelines.insert(pipeline.id.value(), pipeline.status);
+            if is_active(pipeline.status) {
+                self.unfinished.insert(pipeline.id.value());
+            } else {
+                self.unfinished.remove(&pipeline.id.value());
+            }
+            if previous != Some(pipeline.status) {
+                transitions.push(transition(Subject::Pipeline, previous, pipeline.status));
+            }
+
+            let mut stages: Vec<(&String, Vec<&PipelineJob>)> = Vec::new();
+            for pipeline_job in jobs.iter().rev() {
+                match stages
+                    .iter_mut()
+                    .find(|(s, _)| **s == pipeline_job.job.stage)
+                {
+                    Some((_, stage_jobs)) => stage_jobs.push(pipeline_job),
+                    None => stages.push((&pipeline_job.job.stage, vec![pipeline_job])),
+                }
+            }
+            for (stage, stage_jobs) in stages {
+                let status = stage_status(&stage_jobs);
+                let previous = self
+                    .stages
+                    .insert((pipeline.id.value(), stage.clone()), status);
+                if previous != Some(status) {
+                    transitions.push(transition(Subject::Stage(stage.clone()), previous, status));
+                }
+            }
+
+            for pipeline_job in jobs {
+                let job = &pipeline_job.job;
+                let previous = self.jobs.insert(job.id.value(), job.status);
+                if previous != Some(job.status) {
+                    let subject = Subject::Job(TransitionJob {
+                        id: job.id.value(),
+                        name: job.name.clone(),
+                        stage: job.stage.clone(),
+                        web_url: job.web_url.clone(),
+                    });
+                    transitions.push(transition(subject, previous, job.status));
+                }
+            }
+        }
+        if !self.seeded {
+            self.seeded = true;
+            return Vec::new();
+        }
+        transitions
+    }
+
+    /// Like `update`, but pipelines last seen active which are missing
+    /// in `project` are fetched with `fetch` and compared as well.
+    /// Pipelines which cannot be fetched anymore are forgotten.
+    pub fn update_with<F>(&mut self, project: &GitlabProjectPipelines, fetch: F) -> Vec<Transition>
+    where
+        F: Fn(u64) -> Result<(Pipeline, Vec<PipelineJob>), String>,
+    {
+        let mut departed: Vec<u64> = self
+            .unfinished
+            .iter()
+            .filter(|id| !project.pipelines.iter().any(|(p, _)| p.id.value() == **id))
+            .copied()
+            .collect();
+        if departed.is_empty() {
+            return self.update(project);
+        }
+        departed.sort();
+        let mut tracked = project.clone();
+        for id in departed {
+            match fetch(id) {
+                Ok(pipeline) => tracked.pipelines.push(pipeline),
+                Err(_) => {
+                    self.unfinished.remove(&id);
+                }
+            }
+        }
+        self.update(&tracked)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::*;
+
+    #[test]
+    fn test_status_tracker() {
+        let mut tracker = StatusTracker::default();
+        let running = project_pipelines(vec![(
+            pipeline_with_status(1, StatusState::Running),
+            vec![
+                job_with_status(11, "build", "build", StatusState::Running),
+                job_with_status(10, "check", "analysis", StatusState::Success),
+            ],
+        )]);
+        assert!(tracker.update(&running).is_empty());
+        assert!(tracker.update(&running).is_empty());
+
+        let failed = project_pipelines(vec![(
+            pipeline_with_status(1, StatusState::Failed),
+            vec![
+                job_with_status(11, "build", "build", StatusState::Failed),
+                job_with_status(10, "check", "analysis", StatusState::Success),
+            ],
+        )]);
+        let transitions = tracker.update(&failed);
+        assert_eq!(transitions.len(), 3);
+        assert_eq!(transitions[0].subject, Subject::Pipeline);
+        assert_eq!(transitions[0].from, Some(StatusState::Running));
+        assert_eq!(transitions[0].to, StatusState::Failed);
+        assert_eq!(transitions[1].subject, Subject::Stage("build".to_string()));
+        assert_eq!(transitions[1].to, StatusState::Failed);
+        assert_eq!(
+            transitions[2].summary(),
+            "Job build (build) failed in pipeline 1"
+        );
+        assert!(tracker.update(&failed).is_empty());
+    }
+
+    #[test]
+    fn test_pipeline_leaving_selection() {
+        let mut tracker = StatusTracker::default();
+        let older = (
+            pipeline_with_status(1, StatusState::Running),
+            vec![job_with_status(10, "build", "build", StatusState::Running)],
+        );
+        let newer = (
+            pipeline_with_status(2, StatusState::Running),
+            vec![job_with_status(20, "build", "build", StatusState::Running)],
+        );
+        let no_fetch = |id: u64| -> Result<(Pipeline, Vec<PipelineJob>), String> {
+            panic!("Fetched pipeline {}", id)
+        };
+        let both = project_pipelines(vec![newer.clone(), older]);
+        assert!(tracker.update_with(&both, no_fetch).is_empty());
+
+        // Only running pipelines are selected, so the finished one is gone
+        let only_newer = project_pipelines(vec![newer]);
+        let finished = |id: u64| {
+            assert_eq!(id, 1);
+            Ok((
+                pipeline_with_status(1, StatusState::Success),
+                vec![job_with_status(10, "build", "build", StatusState::Success)],
+            ))
+        };
+        let transitions = tracker.update_with(&only_newer, finished);
+        assert_eq!(transitions.len(), 3);
+        assert_eq!(transitions[0].pipeline_id, 1);
+        assert_eq!(transitions[0].subject, Subject::Pipeline);
+        assert_eq!(transitions[0].to, StatusState::Success);
+
+        // Finished, so it is not fetched again
+        assert!(tracker.update_with(&only_newer, no_fetch).is_empty());
+    }
+
+    #[test]
+    fn test_forget_missing_pipeline() {
+        let mut tracker = StatusTracker::default();
+        let running = project_pipelines(vec![(
+            pipeline_with_status(1, StatusState::Running),
+            vec![job_with_status(10, "build", "build", StatusState::Running)],
+        )]);
+        tracker.update(&running);
+        let empty = project_pipelines(vec![]);
+        let deleted = |_| Err("Could not query details of pipeline 1".to_string());
+        assert!(tracker.update_with(&empty, deleted).is_empty());
+        assert!(tracker.unfinished.is_empty());
+    }
+
+    #[test]
+    fn test_stage_status() {
+        let running = job_with_status(1, "a", "build", StatusState::Running);
+        let success = job_with_status(2, "b", "build", StatusState::Success);
+        let mut allowed_failure = job_with_status(3, "c", "build", StatusState::Failed);
+        allowed_failure.job.allow_failure = true;
+        let failed = job_with_status(4, "d", "build", StatusState::Failed);
+
+        assert_eq!(stage_status(&[&running, &success]), StatusState::Running);
+        assert_eq!(
+            stage_status(&[&success, &allowed_failure]),
+            StatusState::Success
+        );
+        assert_eq!(stage_status(&[&success, &failed]), StatusState::Failed);
+    }
+}
@@ -0,0 +1,503 @@
+/*
+Push updates from GitLab webhooks, as an addition to polling.
+
+Pipeline and job events are received on a local HTTP port, e.g.
+forwarded through a tunnel, and applied to the snapshot shown. Only
+statuses are in them, so pipelines and jobs not in the snapshot yet
+(a new push, a retry) make the viewer fetch instead.
+*/
+use crate::gitlabbing::GitlabProjectPipelines;
+use crate::schedule::Waker;
+use gitlab::StatusState;
+use serde::{Deserialize, Deserializer};
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const MAX_BODY: usize = 10 * 1024 * 1024;
+/// Request line and headers together
+const MAX_HEAD: u64 = 16 * 1024;
+/// Connections handled at once, more are dropped
+const MAX_CONNECTIONS: usize = 16;
+
+/// A status reported by GitLab, of the pipeline or one of its jobs
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebhookEvent {
+    pub project_id: u64,
+    pub pipeline_id: u64,
+    pub job_id: Option<u64>,
+    pub status: StatusState,
+}
+
+/// The gitlab crate does not know every status GitLab sends. A canceling
+/// pipeline still has jobs to stop, events with other unknown statuses are left out.
+fn hook_status<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<StatusState>, D::Error> {
+    let status = String::deserialize(deserializer)?;
+    Ok(match status.as_str() {
+        "created" => Some(StatusState::Created),
+        "waiting_for_resource" => Some(StatusState::WaitingForResource),
+        "preparing" => Some(StatusState::Preparing),
+        "pending" => Some(StatusState::Pending),
+        "running" | "canceling" => Some(StatusState::Running),
+        "success" => Some(StatusState::Success),
+        "failed" => Some(StatusState::Failed),
+        "canceled" => Some(StatusState::Canceled),
+        "skipped" => Some(StatusState::Skipped),
+        "manual" => Some(StatusState::Manual),
+        "scheduled" => Some(StatusState::Scheduled),
+        _ => None,
+    })
+}
+
+#[derive(Deserialize)]
+struct HookProject {
+    id: u64,
+}
+
+#[derive(Deserialize)]
+struct HookPipeline {
+    id: u64,
+    #[serde(deserialize_with = "hook_status")]
+    status: Option<StatusState>,
+}
+
+#[derive(Deserialize)]
+struct HookBuild {
+    id: u64,
+    #[serde(deserialize_with = "hook_status")]
+    status: Option<StatusState>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "object_kind", rename_all = "snake_case")]
+enum Payload {
+    Pipeline {
+        project: HookProject,
+        object_attributes: HookPipeline,
+        #[serde(default)]
+        builds: Vec<HookBuild>,
+    },
+    Build {
+        project_id: u64,
+        pipeline_id: u64,
+        build_id: u64,
+        #[serde(deserialize_with = "hook_status")]
+        build_status: Option<StatusState>,
+    },
+}
+
+/// The events of a pipeline or job hook, none for other hooks
+pub fn parse_payload(body: &[u8]) -> Result<Vec<WebhookEvent>, String> {
+    let value: serde_json::Value =
+        serde_json::from_slice(body).map_err(|e| format!("Invalid JSON: {}", e))?;
+    let kind = value.get("object_kind").and_then(|k| k.as_str());
+    if !matches!(kind, Some("pipeline" | "build")) {
+        return Ok(Vec::new());
+    }
+    let payload: Payload =
+        serde_json::from_value(value).map_err(|e| format!("Invalid payload: {}", e))?;
+    Ok(match payload {
+        Payload::Pipeline {
+            project,
+            object_attributes: pipeline,
+            builds,
+        } => {
+            let event = |job_id, status: Option<StatusState>| {
+                status.map(|status| WebhookEvent {
+                    project_id: project.id,
+                    pipeline_id: pipeline.id,
+                    job_id,
+                    status,
+                })
+            };
+            let mut events: Vec<WebhookEvent> = event(None, pipeline.status).into_iter().collect();
+            events.extend(builds.iter().filter_map(|b| event(Some(b.id), b.status)));
+            events
+        }
+        Payload::Build {
+            project_id,
+            pipeline_id,
+            build_id,
+            build_status,
+        } => build_status
+            .map(|status| WebhookEvent {
+                project_id,
+                pipeline_id,
+                job_id: Some(build_id),
+                status,
+            })
+            .into_iter()
+            .collect(),
+    })
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Applied {
+    Nothing,
+    Updated,
+    Unknown, // refers to a pipeline or job not in the snapshot
+}
+
+/// Sets the statuses of `events` in `snapshot`, events of other projects are ignored
+pub fn apply(snapshot: &mut GitlabProjectPipelines, events: &[WebhookEvent]) -> Applied {
+    let mut applied = Applied::Nothing;
+    for event in events {
+        if event.project_id != snapshot.project_id {
+            continue;
+        }
+        let pipeline = snapshot
+            .pipelines
+            .iter_mut()
+            .find(|(p, _)| p.id.value() == event.pipeline_id);
+        let (pipeline, jobs) = match pipeline {
+            None => return Applied::Unknown,
+            Some(pipeline) => pipeline,
+        };
+        let status = match event.job_id {
+            None => &mut pipeline.status,
+            Some(id) => match jobs.iter_mut().find(|j| j.job.id.value() == id) {
+                None => return Applied::Unknown,
+                Some(job) => &mut job.job.status,
+            },
+        };
+        if *status != event.status {
+            *status = event.status;
+            applied = Applied::Updated;
+        }
+    }
+    applied
+}
+
+/// Reads the next line of the head, which must fit into what is left of MAX_HEAD
+fn head_line(head: &mut impl BufRead, line: &mut String) -> Result<(), (&'static str, String)> {
+    line.clear();
+    let bad = |e: String| ("400 Bad Request", e);
+    head.read_line(line).map_err(|e| bad(e.to_string()))?;
+    if !line.ends_with('\n') {
+        return Err(bad("Headers too long or cut off".to_string()));
+    }
+    Ok(())
+}
+
+/// The body of a request, once method and token are checked.
+/// Err holds the response status and message.
+fn read_request(
+    stream: &TcpStream,
+    secret: Option<&str>,
+) -> Result<Vec<u8>, (&'static str, String)> {
+    let mut reader = BufReader::new(stream);
+    let mut head = (&mut reader).take(MAX_HEAD);
+    let mut line = String::new();
+    head_line(&mut head, &mut line)?;
+    let method = line
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    let mut length = 0;
+    let mut token = None;
+    loop {
+        head_line(&mut head, &mut line)?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            match name.trim().to_lowercase().as_str() {
+                "content-length" => {
+                    length = value
+                        .trim()
+                        .parse()
+                        .map_err(|_| ("400 Bad Request", "Bad length".to_string()))?
+                }
+                "x-gitlab-token" => token = Some(value.trim().to_string()),
+                _ => (),
+            }
+        }
+    }
+    if method != "POST" {
+        return Err(("405 Method Not Allowed", "POST only".to_string()));
+    }
+    if secret.is_some_and(|s| !same_token(token.as_deref().unwrap_or(""), s)) {
+        return Err(("401 Unauthorized", "Wrong X-Gitlab-Token".to_string()));
+    }
+    if length > MAX_BODY {
+        return Err(("400 Bad Request", "Body too large".to_string()));
+    }
+    let mut body = vec![0; length];
+    reader
+        .read_exact(&mut body)
+        .map_err(|e| ("400 Bad Request", e.to_string()))?;
+    Ok(body)
+}
+
+/// Compares in constant time, so the secret cannot be guessed byte by byte
+fn same_token(given: &str, secret: &str) -> bool {
+    given.len() == secret.len()
+        && given
+            .bytes()
+            .zip(secret.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+fn handle(stream: TcpStream, secret: Option<&str>, sender: &Sender<WebhookEvent>, waker: &Waker) {
+    stream.set_read_timeout(Some(Duration::from_secs(5))).ok();
+    let (status, message) = match read_request(&stream, secret) {
+        Err(rejected) => rejected,
+        Ok(body) => match parse_payload(&body) {
+            Err(e) => ("400 Bad Request", e),
+            Ok(events) => {
+                let received = !events.is_empty();
+                for event in events {
+                    sender.send(event).ok();
+                }
+                if received {
+                    waker.wake();
+                }
+                ("200 OK", "OK".to_string())
+            }
+        },
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        message.len(),
+        message
+    );
+    (&stream).write_all(response.as_bytes()).ok();
+}
+
+pub struct WebhookReceiver {
+    pub address: SocketAddr,
+    events: Receiver<WebhookEvent>,
+}
+
+impl WebhookReceiver {
+    /// Listens on `listen` until `running` is false. Requests must carry
+    /// `secret` as X-Gitlab-Token, which only loopback addresses may go without.
+    /// `waker` is woken on events.
+    pub fn start(
+        listen: &str,
+        secret: Option<String>,
+        waker: Waker,
+        running: Arc<AtomicBool>,
+    ) -> Result<WebhookReceiver, String> {
+        let failed = |e: std::io::Error| format!("Could not listen on {}: {}", listen, e);
+        let listener = TcpListener::bind(listen).map_err(failed)?;
+        listener.set_nonblocking(true).map_err(failed)?;
+        let address = listener.local_addr().map_err(failed)?;
+        if secret.is_none() && !address.ip().is_loopback() {
+            return Err(format!(
+                "Listening on {} needs a webhook secret, set as \"Secret token\" in GitLab",
+                listen
+            ));
+        }
+        let (sender, events) = mpsc::channel();
+        let handling = Arc::new(AtomicUsize::new(0));
+        thread::spawn(move || {
+            while running.load(Ordering::SeqCst) {
+                match listener.accept() {
+                    // Only counted up here, so no more than MAX_CONNECTIONS get through
+                    Ok(_) if handling.load(Ordering::SeqCst) >= MAX_CONNECTIONS => (),
+                    Ok((stream, _)) => {
+                        stream.set_nonblocking(false).ok();
+                        // A slow client must not hold up the others
+                        let (secret, sender, waker) =
+                            (secret.clone(), sender.clone(), waker.clone());
+                        let handling = handling.clone();
+                        handling.fetch_add(1, Ordering::SeqCst);
+                        thread::spawn(move || {
+                            handle(stream, secret.as_deref(), &sender, &waker);
+                            handling.fetch_sub(1, Ordering::SeqCst);
+                        });
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                        thread::sleep(Duration::from_millis(50));
+                    }
+                    Err(_) => break,
+                }
+            }
+        });
+        Ok(WebhookReceiver { address, events })
+    }
+
+    /// The events received since the last call
+    pub fn events(&self) -> Vec<WebhookEvent> {
+        self.events.try_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::*;
+
+    const PIPELINE_HOOK: &str = r#"{
+        "object_kind": "pipeline",
+        "object_attributes": {"id": 1, "ref": "main", "status": "running", "detailed_status": "running"},
+        "project": {"id": 36813125, "path_with_namespace": "julianbuettner/gitlab-pipeline-viewer"},
+        "builds": [
+            {"id": 11, "stage": "build", "name": "build", "status": "success"},
+            {"id": 12, "stage": "test", "name": "test", "status": "running"}
+        ]
+    }"#;
+
+    const JOB_HOOK: &str = r#"{
+        "object_kind": "build",
+        "build_id": 12,
+        "build_name": "test",
+        "build_status": "failed",
+        "pipeline_id": 1,
+        "project_id": 36813125
+    }"#;
+
+    fn post(address: SocketAddr, token: &str, body: &str) -> String {
+        let request = format!(
+            "POST /hook HTTP/1.1\r\nHost: localhost\r\nX-Gitlab-Token: {}\r\nContent-Length: {}\r\n\r\n{}",
+            token,
+            body.len(),
+            body
+        );
+        send(address, request.as_bytes())
+    }
+
+    /// The response, empty if the connection was dropped
+    fn send(address: SocketAddr, request: &[u8]) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(request).ok();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).ok();
+        response
+    }
+
+    #[test]
+    fn test_parse_payload() {
+        let events = parse_payload(PIPELINE_HOOK.as_bytes()).unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(
+            events[0],
+            WebhookEvent {
+                project_id: 36813125,
+                pipeline_id: 1,
+                job_id: None,
+                status: StatusState::Running,
+            }
+        );
+        assert_eq!(events[2].job_id, Some(12));
+        let events = parse_payload(JOB_HOOK.as_bytes()).unwrap();
+        assert_eq!(events[0].status, StatusState::Failed);
+        assert_eq!(parse_payload(br#"{"object_kind": "push"}"#), Ok(vec![]));
+        let canceling = JOB_HOOK.replace("\"failed\"", "\"canceling\"");
+        let events = parse_payload(canceling.as_bytes()).unwrap();
+        assert_eq!(events[0].status, StatusState::Running);
+        let unknown = JOB_HOOK.replace("\"failed\"", "\"new_status\"");
+        assert_eq!(parse_payload(unknown.as_bytes()), Ok(vec![]));
+        assert!(parse_payload(b"{").is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut snapshot = project_pipelines(vec![(
+            pipeline_with_status(1, StatusState::Pending),
+            vec![
+                job_with_status(11, "build", "build", StatusState::Running),
+                job_with_status(12, "test", "test", StatusState::Created),
+            ],
+        )]);
+        let events = parse_payload(PIPELINE_HOOK.as_bytes()).unwrap();
+        assert_eq!(apply(&mut snapshot, &events), Applied::Updated);
+        assert_eq!(snapshot.pipelines[0].0.status, StatusState::Running);
+        assert_eq!(snapshot.pipelines[0].1[1].job.status, StatusState::Running);
+        assert_eq!(apply(&mut snapshot, &events), Applied::Nothing);
+
+        let other_project = WebhookEvent {
+            project_id: 1,
+            ..events[0].clone()
+        };
+        assert_eq!(apply(&mut snapshot, &[other_project]), Applied::Nothing);
+        let retried = WebhookEvent {
+            job_id: Some(13),
+            ..events[0].clone()
+        };
+        assert_eq!(apply(&mut snapshot, &[retried]), Applied::Unknown);
+    }
+
+    #[test]
+    fn test_receiver() {
+        let running = Arc::new(AtomicBool::new(true));
+        let waker = Waker::default();
+        let receiver = WebhookReceiver::start(
+            "127.0.0.1:0",
+            Some("s3cret".to_string()),
+            waker.clone(),
+            running.clone(),
+        )
+        .unwrap();
+
+        // An unfinished request does not keep others waiting
+        let _slow = TcpStream::connect(receiver.address).unwrap();
+        let response = post(receiver.address, "wrong", JOB_HOOK);
+        assert!(response.starts_with("HTTP/1.1 401"));
+        let response = post(receiver.address, "s3cre", JOB_HOOK);
+        assert!(response.starts_with("HTTP/1.1 401"));
+        assert!(!waker.take());
+        let response = post(receiver.address, "s3cret", "{");
+        assert!(response.starts_with("HTTP/1.1 400"));
+
+        let response = post(receiver.address, "s3cret", JOB_HOOK);
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(waker.take());
+        let events = receiver.events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].job_id, Some(12));
+
+        let open = WebhookReceiver::start("0.0.0.0:0", None, waker.clone(), running.clone());
+        assert!(open.is_err());
+        running.store(false, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_receiver_limits() {
+        let running = Arc::new(AtomicBool::new(true));
+        let secret = Some("s3cret".to_string());
+        let receiver =
+            WebhookReceiver::start("127.0.0.1:0", secret, Waker::default(), running.clone())
+                .unwrap();
+
+        // Rejected before waiting for a body that never comes
+        let request = "POST / HTTP/1.1\r\nX-Gitlab-Token: wrong\r\nContent-Length: 5000000\r\n\r\n";
+        let response = send(receiver.address, request.as_bytes());
+        assert!(response.starts_with("HTTP/1.1 401"));
+        let mut request = b"POST / HTTP/1.1\r\nX-Long: ".to_vec();
+        request.resize(MAX_HEAD as usize + 10, b'a');
+        let response = send(receiver.address, &request);
+        assert!(response.starts_with("HTTP/1.1 400"));
+
+        let slow: Vec<TcpStream> = (0..MAX_CONNECTIONS)
+            .map(|_| TcpStream::connect(receiver.address).unwrap())
+            .collect();
+        assert_eq!(send(receiver.address, JOB_HOOK.as_bytes()), "");
+        drop(slow);
+        let answered = (0..100).any(|_| {
+            thread::sleep(Duration::from_millis(20));
+            post(receiver.address, "s3cret", JOB_HOOK).starts_with("HTTP/1.1 200")
+        });
+        assert!(answered);
+        running.store(false, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_same_token() {
+        assert!(same_token("s3cret", "s3cret"));
+        assert!(!same_token("s3creT", "s3cret"));
+        assert!(!same_token("s3cre", "s3cret"));
+        assert!(!same_token("", "s3cret"));
+    }
+}